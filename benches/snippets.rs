@@ -0,0 +1,109 @@
+// Rendering a page with many snippets: `cargo bench --bench snippets`.
+// Every round renders the page body afresh, past the rendered-page cache, as
+// a changed page or snippet would. The baseline renders the same snippets
+// with `render_template`, parsing each one (and the partial) per render; it
+// leaves the Markdown out, so it only understates the difference.
+
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use camino::Utf8PathBuf;
+use flaty::web::{self, App};
+use handlebars::Handlebars;
+use serde_json::json;
+
+const KINDS: usize = 20;
+const SNIPPETS: usize = 200;
+const ROUNDS: u32 = 200;
+
+#[tokio::main]
+async fn main() {
+    let root = Utf8PathBuf::from_path_buf(
+        std::env::temp_dir().join(format!("flaty-bench-snippets-{}", std::process::id())),
+    )
+    .unwrap();
+    std::fs::create_dir_all(root.join("_style/snippets")).unwrap();
+    std::fs::create_dir_all(root.join("_style/partials")).unwrap();
+    std::fs::write(
+        root.join("_style/partials/heading.html"),
+        "<h3>{{title}}</h3>",
+    )
+    .unwrap();
+    let sources: Vec<String> = (0..KINDS)
+        .map(|kind| {
+            format!(
+                "<aside class=\"s{kind}\">{{{{#if title}}}}{{{{> heading}}}}{{{{/if}}}}\
+                 {{{{#each items}}}}<li>{{{{this}}}}</li>{{{{/each}}}}\
+                 {{{{{{contents}}}}}}<small>{{{{page.author}}}}</small></aside>"
+            )
+        })
+        .collect();
+    for (kind, source) in sources.iter().enumerate() {
+        std::fs::write(root.join(format!("_style/snippets/s{kind}.html")), source).unwrap();
+    }
+    let mut page = String::from("---\nauthor = \"Ann\"\n---\n# Snippets\n\n");
+    for i in 0..SNIPPETS {
+        page.push_str(&format!(
+            ":::s{}\ntitle = \"Snippet {i}\"\nitems = [\"a\", \"b\", \"c\"]\n\n\
+             Some *Markdown* for snippet {i}.\n:::\n\n",
+            i % KINDS
+        ));
+    }
+    let path = root.join("page.md");
+    std::fs::write(&path, page).unwrap();
+
+    let app = App::new(root.clone());
+    // Loads and compiles everything once.
+    web::render_body(&app, &path).await.unwrap();
+    report(
+        "compiled",
+        time(|| async {
+            std::hint::black_box(web::render_body(&app, &path).await.unwrap());
+        })
+        .await,
+    );
+
+    report(
+        "baseline",
+        time(|| async {
+            let mut hbs = Handlebars::new();
+            hbs.register_partial("heading", "<h3>{{title}}</h3>")
+                .unwrap();
+            let mut body = String::new();
+            for i in 0..SNIPPETS {
+                let ctx = json!({
+                    "title": format!("Snippet {i}"),
+                    "items": ["a", "b", "c"],
+                    "contents": format!("<p>Some <em>Markdown</em> for snippet {i}.</p>"),
+                    "page": { "author": "Ann" },
+                });
+                body.push_str(&hbs.render_template(&sources[i % KINDS], &ctx).unwrap());
+            }
+            std::hint::black_box(body);
+        })
+        .await,
+    );
+    std::fs::remove_dir_all(&root).ok();
+}
+
+// Mean and best time of `ROUNDS` runs.
+async fn time<F: Future<Output = ()>>(mut run: impl FnMut() -> F) -> (Duration, Duration) {
+    let mut total = Duration::ZERO;
+    let mut best = Duration::MAX;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        run().await;
+        let elapsed = start.elapsed();
+        total += elapsed;
+        best = best.min(elapsed);
+    }
+    (total / ROUNDS, best)
+}
+
+fn report(name: &str, (mean, best): (Duration, Duration)) {
+    println!(
+        "{name}: page of {SNIPPETS} snippets, {mean:?} per render (best {best:?}, {ROUNDS} rounds)"
+    );
+}
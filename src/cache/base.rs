@@ -23,6 +23,8 @@ struct Cached<T> {
     // on the default value and once a read/compute fails, so callers get an
     // error instead of a stale value until the file is valid again.
     ok: bool,
+    // Why, when not ok: repeated until the file changes.
+    failure: Option<String>,
     value: T,
 }
 
@@ -36,7 +38,10 @@ impl<T> CacheBase<T> {
         T: Cacheable + Clone + Send + 'static,
     {
         let path = path.as_ref();
-        let unavailable = || Error::msg(format!("`{}` unavailable", path.display()));
+        let unavailable = |failure: &Option<String>| match failure {
+            Some(failure) => Error::msg(failure.clone()),
+            None => Error::msg(format!("`{}` unavailable", path.display())),
+        };
 
         let (digest, value) = {
             let lock = self.mutex.lock();
@@ -45,7 +50,7 @@ impl<T> CacheBase<T> {
                     return if lock.ok {
                         Ok(lock.value.clone())
                     } else {
-                        Err((lock.value.clone(), unavailable()))
+                        Err((lock.value.clone(), unavailable(&lock.failure)))
                     };
                 }
             }
@@ -61,7 +66,7 @@ impl<T> CacheBase<T> {
                 if lock.ok {
                     Ok(value)
                 } else {
-                    Err((value, unavailable()))
+                    Err((value, unavailable(&lock.failure)))
                 }
             }
             Err(err) => {
@@ -72,6 +77,7 @@ impl<T> CacheBase<T> {
                 lock.digest = None;
                 let err = Error::from(err).context(format!("cannot read `{}`", path.display()));
                 error!("{err:?}");
+                lock.failure = Some(format!("{err:#}"));
                 Err((value, err))
             }
             Ok((digest, Some(contents))) => {
@@ -84,6 +90,7 @@ impl<T> CacheBase<T> {
                         lock.last_check = Some(Instant::now());
                         lock.digest = Some(digest);
                         lock.ok = true;
+                        lock.failure = None;
                         lock.value = new_value;
                         Ok(value2)
                     }
@@ -94,6 +101,7 @@ impl<T> CacheBase<T> {
                         lock.ok = false;
                         let err = err.context(format!("cannot process `{}`", path.display()));
                         error!("{err:?}");
+                        lock.failure = Some(format!("{err:#}"));
                         Err((value, err))
                     }
                     Err(join_err) => {
@@ -103,6 +111,7 @@ impl<T> CacheBase<T> {
                         let err = Error::from(join_err)
                             .context(format!("compute panicked for `{}`", path.display()));
                         error!("{err:?}");
+                        lock.failure = Some(format!("{err:#}"));
                         Err((value, err))
                     }
                 }
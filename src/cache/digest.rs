@@ -10,6 +10,12 @@ pub struct Digest {
     hash: u128,
 }
 
+impl Digest {
+    pub fn hash(&self) -> u128 {
+        self.hash
+    }
+}
+
 pub async fn load_file(
     path: &Path,
     digest: Option<Digest>,
@@ -37,3 +43,23 @@ pub async fn load_file(
 
     Ok((new_digest, Some(contents)))
 }
+
+// Like `load_file`, for files of any type (images, fonts...): only the digest
+// is kept, and an unchanged mtime/size skips reading the file again.
+pub async fn file_digest(path: &Path, digest: Option<Digest>) -> io::Result<Digest> {
+    let mut file = File::open(path).await?;
+    let meta = file.metadata().await?;
+    let size = meta.size();
+    let mtime = meta.mtime();
+
+    if let Some(digest) = digest {
+        if size == digest.size && mtime == digest.mtime {
+            return Ok(digest);
+        }
+    }
+
+    let mut contents = Vec::new();
+    file.read_to_end(&mut contents).await?;
+    let hash = XxHash3_128::oneshot(&contents);
+    Ok(Digest { mtime, size, hash })
+}
@@ -1,6 +1,7 @@
 mod base;
 mod cacheable;
 mod digest;
+mod snapshot;
 
 use std::{
     path::{Path, PathBuf},
@@ -13,10 +14,16 @@ use tokio::time::Instant;
 
 use self::base::CacheBase;
 pub use self::cacheable::Cacheable;
+use self::digest::file_digest;
+pub use self::digest::Digest;
+pub use self::snapshot::Snapshot;
 
 pub struct Cache<T> {
     path: PathBuf,
     cache: CacheBase<T>,
+    // What a missing file yields: always the same value, so that (for an
+    // `Arc`) its identity only changes with the file.
+    missing: T,
 }
 
 impl<T> Cache<T> {
@@ -27,6 +34,7 @@ impl<T> Cache<T> {
         Self {
             path: path.into(),
             cache: CacheBase::default(),
+            missing: T::default(),
         }
     }
 
@@ -37,7 +45,7 @@ impl<T> Cache<T> {
         T: Cacheable + Clone + Default + Send + 'static,
     {
         if !tokio::fs::try_exists(&self.path).await.unwrap_or(false) {
-            return Ok(T::default());
+            return Ok(self.missing.clone());
         }
         self.cache.load(&self.path).await
     }
@@ -109,6 +117,113 @@ impl<T> CacheMap<T> {
     }
 }
 
+// Values derived from files' metadata or contents, rechecked at most every
+// couple of seconds like the other caches.
+struct TimedMap<V> {
+    map: DashMap<PathBuf, (Instant, V)>,
+    cap: usize,
+}
+
+impl<V: Copy> Default for TimedMap<V> {
+    fn default() -> Self {
+        Self {
+            map: DashMap::new(),
+            cap: MAX_ENTRIES,
+        }
+    }
+}
+
+impl<V: Copy> TimedMap<V> {
+    // The value and whether it is recent enough to use without rechecking.
+    fn get(&self, path: &Path) -> Option<(V, bool)> {
+        self.map
+            .get(path)
+            .map(|entry| (entry.1, entry.0.elapsed().as_secs() < 2))
+    }
+
+    fn insert(&self, path: &Path, value: V) {
+        self.map.insert(path.into(), (Instant::now(), value));
+        self.enforce_cap();
+    }
+
+    fn remove(&self, path: &Path) {
+        self.map.remove(path);
+    }
+
+    fn sweep(&self, ttl: Duration) {
+        let now = Instant::now();
+        self.map
+            .retain(|_, (last_check, _)| now.saturating_duration_since(*last_check) < ttl);
+    }
+
+    fn enforce_cap(&self) {
+        let excess = self.map.len().saturating_sub(self.cap);
+        if excess == 0 {
+            return;
+        }
+        let mut entries: Vec<(PathBuf, Instant)> = self
+            .map
+            .iter()
+            .map(|e| (e.key().clone(), e.value().0))
+            .collect();
+        entries.sort_by_key(|(_, time)| *time);
+        for (key, _) in entries.into_iter().take(excess) {
+            self.map.remove(&key);
+        }
+    }
+}
+
+// Content digests of arbitrary files (static assets).
+#[derive(Default)]
+pub struct DigestMap(TimedMap<Digest>);
+
+impl DigestMap {
+    pub async fn load(&self, path: impl AsRef<Path>) -> std::io::Result<Digest> {
+        let path = path.as_ref();
+        let previous = self.0.get(path);
+        if let Some((digest, true)) = previous {
+            return Ok(digest);
+        }
+        match file_digest(path, previous.map(|(digest, _)| digest)).await {
+            Ok(digest) => {
+                self.0.insert(path, digest);
+                Ok(digest)
+            }
+            Err(err) => {
+                self.0.remove(path);
+                Err(err)
+            }
+        }
+    }
+
+    pub fn sweep(&self, ttl: Duration) {
+        self.0.sweep(ttl);
+    }
+}
+
+// Whether optional files (error pages...) exist, so that a flood of requests
+// probing for them does not hit the filesystem every time.
+#[derive(Default)]
+pub struct ExistsMap(TimedMap<bool>);
+
+impl ExistsMap {
+    pub async fn exists(&self, path: impl AsRef<Path>) -> bool {
+        let path = path.as_ref();
+        if let Some((exists, true)) = self.0.get(path) {
+            return exists;
+        }
+        let exists = tokio::fs::metadata(path)
+            .await
+            .is_ok_and(|meta| meta.is_file());
+        self.0.insert(path, exists);
+        exists
+    }
+
+    pub fn sweep(&self, ttl: Duration) {
+        self.0.sweep(ttl);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -118,10 +233,28 @@ mod tests {
 
     impl Cacheable for Upper {
         fn compute(src: &str) -> Result<Self, Error> {
+            anyhow::ensure!(src != "!", "no shouting");
             Ok(Upper(src.to_uppercase()))
         }
     }
 
+    #[tokio::test]
+    async fn failures_are_repeated() {
+        let dir = std::env::temp_dir().join(format!("flaty-failure-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("f.txt");
+        std::fs::write(&path, "!").unwrap();
+
+        let cache: CacheMap<Upper> = CacheMap::default();
+        let (_, first) = cache.load(&path).await.unwrap_err();
+        assert!(format!("{first:#}").ends_with(": no shouting"));
+        // Checked again within a couple of seconds: the same reason.
+        let (_, again) = cache.load(&path).await.unwrap_err();
+        assert_eq!(format!("{again:#}"), format!("{first:#}"));
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
     #[tokio::test]
     async fn sweep_drops_idle_entries() {
         let dir = std::env::temp_dir().join(format!("flaty-sweep-{}", std::process::id()));
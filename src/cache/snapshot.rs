@@ -0,0 +1,85 @@
+use std::{future::Future, sync::Arc, time::Duration};
+
+use parking_lot::Mutex;
+use tokio::{sync::Mutex as AsyncMutex, time::Instant};
+
+// How old a snapshot gets before a request starts walking again.
+const RESCAN_AFTER: Duration = Duration::from_secs(2);
+
+// A value built by walking a directory. Requests get the current value at
+// once; when it is stale, one of them starts a walk in the background. Only
+// the first load (or the first after a sweep) waits for a walk.
+pub struct Snapshot<T> {
+    // The value and when the directory was last walked for it.
+    current: Arc<Mutex<Option<(Instant, T)>>>,
+    // Held while walking, so that walks do not pile up.
+    walk: Arc<AsyncMutex<()>>,
+}
+
+impl<T> Default for Snapshot<T> {
+    fn default() -> Self {
+        Self {
+            current: Arc::default(),
+            walk: Arc::default(),
+        }
+    }
+}
+
+impl<T: Clone + Send + 'static> Snapshot<T> {
+    // `rescan` walks again, given the previous value (to keep it when
+    // nothing changed).
+    pub async fn load<F>(&self, rescan: impl FnOnce(Option<T>) -> F) -> T
+    where
+        F: Future<Output = T> + Send + 'static,
+    {
+        let current = self.current.lock().clone();
+        if let Some((last_check, value)) = current {
+            if last_check.elapsed() >= RESCAN_AFTER {
+                if let Ok(walk) = self.walk.clone().try_lock_owned() {
+                    let scan = rescan(Some(value.clone()));
+                    let current = self.current.clone();
+                    tokio::spawn(async move {
+                        let value = scan.await;
+                        *current.lock() = Some((Instant::now(), value));
+                        drop(walk);
+                    });
+                }
+            }
+            return value;
+        }
+
+        let _walk = self.walk.lock().await;
+        // Another request may have walked meanwhile.
+        if let Some((_, value)) = &*self.current.lock() {
+            return value.clone();
+        }
+        let value = rescan(None).await;
+        *self.current.lock() = Some((Instant::now(), value.clone()));
+        value
+    }
+
+    // Forget the value when it has not been refreshed within `ttl`.
+    pub fn sweep(&self, ttl: Duration) {
+        let mut current = self.current.lock();
+        if current
+            .as_ref()
+            .is_some_and(|(time, _)| time.elapsed() >= ttl)
+        {
+            *current = None;
+        }
+    }
+
+    // Make the value look stale, so that the next load walks again.
+    #[cfg(test)]
+    pub fn expire(&self) {
+        if let Some((time, _)) = &mut *self.current.lock() {
+            *time -= RESCAN_AFTER;
+        }
+    }
+
+    // Wait for a background walk to finish.
+    #[cfg(test)]
+    pub async fn walked(&self) {
+        let _walk = self.walk.lock().await;
+    }
+}
@@ -0,0 +1,315 @@
+use std::cmp::Ordering;
+
+use anyhow::bail;
+use chrono::DateTime;
+use chrono_tz::Tz;
+use serde::Deserialize;
+use serde_json::{json, Value as Json};
+
+use crate::{
+    date,
+    markdown::Page,
+    site::{parent_url, NavLink, Site, SitePage},
+};
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Order {
+    Asc,
+    Desc,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CollectionSpec {
+    #[serde(default)]
+    source: String,
+    sort: Option<String>,
+    order: Option<Order>,
+    per_page: Option<usize>,
+}
+
+// A page listing the pages of a directory, from its front matter:
+// `collection = { source = "posts/", sort = "date", order = "desc", per_page = 10 }`.
+pub struct Collection {
+    // URL of the listed directory.
+    source: String,
+    // Front-matter field to sort by.
+    sort: String,
+    order: Order,
+    per_page: Option<usize>,
+}
+
+impl Collection {
+    // The collection declared by the page at `url`, if any.
+    pub fn new(url: &str, page: &Page) -> Option<anyhow::Result<Self>> {
+        let spec = page.fields().get("collection")?;
+        Some(Self::from_spec(url, spec))
+    }
+
+    // Every published page directly under `url`, newest first.
+    pub fn children(url: &str) -> Self {
+        Collection {
+            source: url.to_owned(),
+            sort: "date".into(),
+            order: Order::Desc,
+            per_page: None,
+        }
+    }
+
+    fn from_spec(url: &str, spec: &Json) -> anyhow::Result<Self> {
+        let spec: CollectionSpec = serde_json::from_value(spec.clone())?;
+        // Relative to the page's own directory, which is the default.
+        let mut source = match spec.source.starts_with('/') {
+            true => spec.source,
+            false => format!("{url}{}", spec.source),
+        };
+        if !source.ends_with('/') {
+            source.push('/');
+        }
+        if source[1..]
+            .split_terminator('/')
+            .any(|c| c.is_empty() || c.starts_with(['.', '_']))
+        {
+            bail!("collection: invalid source `{source}`");
+        }
+        if spec.per_page == Some(0) {
+            bail!("collection: per_page must be positive");
+        }
+        let sort = spec.sort.unwrap_or_else(|| "date".into());
+        // Newest first by default.
+        let order = spec.order.unwrap_or(match sort.as_str() {
+            "date" => Order::Desc,
+            _ => Order::Asc,
+        });
+        Ok(Collection {
+            source,
+            sort,
+            order,
+            per_page: spec.per_page,
+        })
+    }
+
+    // The pages directly under the source that `include` accepts (published
+    // ones the user may see), in listing order.
+    pub fn members<'a>(
+        &self,
+        site: &'a Site,
+        include: impl Fn(&SitePage) -> bool,
+    ) -> Vec<&'a SitePage> {
+        let mut members: Vec<&SitePage> = site
+            .child_pages(&self.source)
+            .into_iter()
+            .filter(|entry| include(entry))
+            .collect();
+        members.sort_by(|a, b| {
+            let (a_key, b_key) = (
+                a.page.fields().get(&self.sort),
+                b.page.fields().get(&self.sort),
+            );
+            let ordering = match (a_key, b_key) {
+                (Some(a), Some(b)) if self.order == Order::Desc => compare(b, a),
+                (Some(a), Some(b)) => compare(a, b),
+                // Pages without the field come last either way.
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            };
+            ordering.then_with(|| a.url.cmp(&b.url))
+        });
+        members
+    }
+
+    // Template variables for page `number` (from 1) of the listing shown at
+    // `url`, among the pages `include` accepts; None past the last page.
+    pub fn listing(
+        &self,
+        site: &Site,
+        url: &str,
+        number: usize,
+        include: impl Fn(&SitePage) -> bool,
+    ) -> Option<Json> {
+        let members = self.members(site, include);
+        let per_page = self.per_page.unwrap_or(members.len()).max(1);
+        let total_pages = members.len().div_ceil(per_page).max(1);
+        if number == 0 || number > total_pages {
+            return None;
+        }
+        let pages: Vec<Json> = members
+            .iter()
+            .skip((number - 1) * per_page)
+            .take(per_page)
+            .map(|entry| entry_fields(entry))
+            .collect();
+        let page_url = |n: usize| match n {
+            1 => url.to_owned(),
+            n => format!("{url}?page={n}"),
+        };
+        Some(json!({
+            "pages": pages,
+            "page": number,
+            "total_pages": total_pages,
+            "total": members.len(),
+            "prev": (number > 1).then(|| page_url(number - 1)),
+            "next": (number < total_pages).then(|| page_url(number + 1)),
+        }))
+    }
+}
+
+// A listed page: its front matter plus `url` and `summary`.
+pub fn entry_fields(entry: &SitePage) -> Json {
+    let mut fields = entry.page.fields().clone();
+    fields.insert("url".into(), Json::from(entry.url.as_str()));
+    fields.insert("summary".into(), Json::from(entry.page.summary()));
+    Json::Object(fields)
+}
+
+// The pages before and after the page at `url` in the first collection
+// (by URL of the listing page) that includes it, among the pages `include`
+// accepts, or None if none does.
+pub fn neighbours(
+    site: &Site,
+    url: &str,
+    include: impl Fn(&SitePage) -> bool,
+) -> Option<(Option<NavLink>, Option<NavLink>)> {
+    let parent = parent_url(url)?;
+    let collection = site.pages().iter().find_map(|entry| {
+        Collection::new(&entry.url, &entry.page)?
+            .ok()
+            .filter(|collection| collection.source == parent)
+    })?;
+    let members = collection.members(site, include);
+    let i = members.iter().position(|entry| entry.url == url)?;
+    let prev = i.checked_sub(1).map(|i| NavLink::from(members[i]));
+    let next = members.get(i + 1).map(|entry| NavLink::from(*entry));
+    Some((prev, next))
+}
+
+// Neither a draft, nor dated in the future, nor outside its schedule.
+pub fn published(page: &Page, now: DateTime<Tz>) -> bool {
+    !page.draft()
+        && page.date().is_none_or(|date| date <= now)
+        && Schedule::of(page, now).is_ok_and(|schedule| schedule == Schedule::Live)
+}
+
+// Where a page stands between its `publish_date` and `expire_date`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Schedule {
+    // Not yet published.
+    Pending,
+    Live,
+    Expired,
+}
+
+impl Schedule {
+    pub fn of(page: &Page, now: DateTime<Tz>) -> anyhow::Result<Self> {
+        let tz = now.timezone();
+        let (publish, expire) = (page.publish_date(tz)?, page.expire_date(tz)?);
+        Ok(if publish.is_some_and(|date| date > now) {
+            Schedule::Pending
+        } else if expire.is_some_and(|date| date <= now) {
+            Schedule::Expired
+        } else {
+            Schedule::Live
+        })
+    }
+}
+
+// The first time after `now` at which a page's `date` or schedule changes
+// whether it is published, if any.
+pub fn next_change<'a>(
+    pages: impl IntoIterator<Item = &'a Page>,
+    now: DateTime<Tz>,
+) -> Option<DateTime<Tz>> {
+    let tz = now.timezone();
+    pages
+        .into_iter()
+        .flat_map(|page| {
+            [
+                page.date().map(|date| date.with_timezone(&tz)),
+                page.publish_date(tz).ok().flatten(),
+                page.expire_date(tz).ok().flatten(),
+            ]
+        })
+        .flatten()
+        .filter(|time| *time > now)
+        .min()
+}
+
+// Numbers by value, dates by time, other strings alphabetically.
+fn compare(a: &Json, b: &Json) -> Ordering {
+    if let (Some(a), Some(b)) = (a.as_f64(), b.as_f64()) {
+        return a.total_cmp(&b);
+    }
+    match (a.as_str(), b.as_str()) {
+        (Some(a), Some(b)) => match (date::parse(a), date::parse(b)) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            _ => a.cmp(b),
+        },
+        _ => Ordering::Equal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collection(url: &str, spec: &str) -> anyhow::Result<Collection> {
+        let spec: toml::Value = toml::from_str(spec).unwrap();
+        Collection::from_spec(url, &serde_json::to_value(spec).unwrap())
+    }
+
+    #[test]
+    fn specs() {
+        let c = collection("/blog/", "").unwrap();
+        assert_eq!(c.source, "/blog/");
+        assert!(c.sort == "date" && c.order == Order::Desc);
+        let c = collection("/blog/", "source = \"posts\"\nsort = \"title\"").unwrap();
+        assert_eq!(c.source, "/blog/posts/");
+        assert!(c.order == Order::Asc);
+        assert_eq!(collection("/blog/", "source = \"/\"").unwrap().source, "/");
+        assert!(collection("/blog/", "source = \"../x/\"").is_err());
+        assert!(collection("/blog/", "source = \"_drafts/\"").is_err());
+        assert!(collection("/blog/", "per_page = 0").is_err());
+        assert!(collection("/blog/", "order = \"up\"").is_err());
+        assert!(collection("/blog/", "sorting = \"date\"").is_err());
+    }
+
+    #[test]
+    fn schedules() {
+        use crate::cache::Cacheable;
+
+        let paris = chrono_tz::Europe::Paris;
+        let now = "2024-07-01T07:30:00Z"
+            .parse::<DateTime<chrono::Utc>>()
+            .unwrap()
+            .with_timezone(&paris);
+        let schedule = |fields: &str| {
+            let page = Page::compute(&format!("---\n{fields}\n---\n")).unwrap();
+            Schedule::of(&page, now).map(|schedule| (schedule, published(&page, now)))
+        };
+        assert_eq!(schedule("").unwrap(), (Schedule::Live, true));
+        // 09:00 in Paris is 07:00 UTC.
+        let live = "publish_date = 2024-07-01T09:00:00\nexpire_date = 2024-07-01T10:00:00";
+        assert_eq!(schedule(live).unwrap(), (Schedule::Live, true));
+        let pending = "publish_date = 2024-07-01T11:00:00";
+        assert_eq!(schedule(pending).unwrap(), (Schedule::Pending, false));
+        let expired = "expire_date = 2024-07-01T07:00:00Z";
+        assert_eq!(schedule(expired).unwrap(), (Schedule::Expired, false));
+        assert!(schedule("publish_date = \"soon\"").is_err());
+        assert!(schedule("expire_date = 2024").is_err());
+
+        let pages: Vec<Page> = ["", live, pending, expired, "date = 2024-07-01T08:00:00Z"]
+            .iter()
+            .map(|fields| Page::compute(&format!("---\n{fields}\n---\n")).unwrap())
+            .collect();
+        // The live page expires at 08:00 UTC, when the dated one appears.
+        let next = next_change(&pages, now).unwrap();
+        assert_eq!(next.to_rfc3339(), "2024-07-01T10:00:00+02:00");
+        assert_eq!(
+            next_change(&pages, next),
+            Some(next + chrono::Duration::hours(1))
+        );
+        assert_eq!(next_change(&pages[..1], now), None);
+    }
+}
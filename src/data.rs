@@ -0,0 +1,246 @@
+use std::sync::Arc;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use serde_json::{Map, Value as Json};
+use tracing::{error, warn};
+
+use crate::{
+    cache::{CacheMap, Cacheable, Snapshot},
+    markdown::toml_to_json,
+};
+
+// Deeper directories are not searched for data files.
+const MAX_DEPTH: usize = 32;
+
+// A `_data/*.toml` file.
+#[derive(Default)]
+pub struct TomlData(Json);
+
+impl Cacheable for TomlData {
+    fn compute(src: &str) -> anyhow::Result<Self> {
+        let table: toml::Table = src.parse()?;
+        Ok(TomlData(toml_to_json(toml::Value::Table(table))))
+    }
+}
+
+// A `_data/*.json` file.
+#[derive(Default)]
+pub struct JsonData(Json);
+
+impl Cacheable for JsonData {
+    fn compute(src: &str) -> anyhow::Result<Self> {
+        Ok(JsonData(serde_json::from_str(src)?))
+    }
+}
+
+#[derive(Clone)]
+enum DataFile {
+    Toml(Arc<TomlData>),
+    Json(Arc<JsonData>),
+}
+
+impl DataFile {
+    fn value(&self) -> &Json {
+        match self {
+            DataFile::Toml(data) => &data.0,
+            DataFile::Json(data) => &data.0,
+        }
+    }
+
+    fn same(&self, other: &DataFile) -> bool {
+        match (self, other) {
+            (DataFile::Toml(a), DataFile::Toml(b)) => Arc::ptr_eq(a, b),
+            (DataFile::Json(a), DataFile::Json(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+struct Scan {
+    // Path relative to `_data/`, sorted.
+    files: Vec<(Utf8PathBuf, DataFile)>,
+    data: Arc<Json>,
+}
+
+#[derive(Default)]
+struct DataFiles {
+    toml: CacheMap<Arc<TomlData>>,
+    json: CacheMap<Arc<JsonData>>,
+}
+
+// The `site.data` of an app: every file under `_data/`, keyed by path
+// (`_data/team/members.toml` -> `team.members`). The directory is rescanned in
+// the background (see `Snapshot`); files come from (and stay fresh through)
+// their caches. The value is replaced, never modified, when a file changes.
+#[derive(Default)]
+pub struct SiteData {
+    files: Arc<DataFiles>,
+    scan: Snapshot<Arc<Scan>>,
+}
+
+impl SiteData {
+    pub async fn load(&self, root: &Utf8Path) -> Arc<Json> {
+        let dir = root.join("_data");
+        let files = self.files.clone();
+        let scan = self
+            .scan
+            .load(|previous| async move { rescan(&files, dir, previous).await })
+            .await;
+        scan.data.clone()
+    }
+
+    pub fn sweep(&self, ttl: std::time::Duration) {
+        self.files.toml.sweep(ttl);
+        self.files.json.sweep(ttl);
+        self.scan.sweep(ttl);
+    }
+}
+
+async fn rescan(caches: &DataFiles, dir: Utf8PathBuf, previous: Option<Arc<Scan>>) -> Arc<Scan> {
+    let walk_dir = dir.clone();
+    let found = tokio::task::spawn_blocking(move || find_files(&walk_dir))
+        .await
+        .unwrap_or_else(|err| {
+            error!("data scan failed: {err}");
+            Vec::new()
+        });
+    let mut files = Vec::with_capacity(found.len());
+    for path in found {
+        let full = dir.join(&path);
+        // Invalid files are logged (with their path) by the cache and left
+        // out.
+        let file = match path.extension() {
+            Some("toml") => caches.toml.load(&full).await.ok().map(DataFile::Toml),
+            _ => caches.json.load(&full).await.ok().map(DataFile::Json),
+        };
+        if let Some(file) = file {
+            files.push((path, file));
+        }
+    }
+    match previous {
+        Some(scan)
+            if scan.files.len() == files.len()
+                && scan
+                    .files
+                    .iter()
+                    .zip(&files)
+                    .all(|(a, b)| a.0 == b.0 && a.1.same(&b.1)) =>
+        {
+            scan
+        }
+        _ => {
+            let data = merge(&files);
+            Arc::new(Scan {
+                files,
+                data: Arc::new(data),
+            })
+        }
+    }
+}
+
+// Nest each file's value under its directories and stem. A file whose key is
+// already taken (`team.toml` next to `team.json` or `team/`) is left out.
+fn merge(files: &[(Utf8PathBuf, DataFile)]) -> Json {
+    let mut data = Map::new();
+    for (path, file) in files {
+        let mut keys: Vec<&str> = path
+            .parent()
+            .map(Utf8Path::iter)
+            .into_iter()
+            .flatten()
+            .collect();
+        keys.push(path.file_stem().unwrap_or_default());
+        if !insert(&mut data, &keys, file.value()) {
+            error!("`_data/{path}` conflicts with another data file, ignoring it");
+        }
+    }
+    Json::Object(data)
+}
+
+// Whether `value` could be set at `keys`.
+fn insert(table: &mut Map<String, Json>, keys: &[&str], value: &Json) -> bool {
+    match keys {
+        [] => false,
+        [key] if table.contains_key(*key) => false,
+        [key] => {
+            table.insert(key.to_string(), value.clone());
+            true
+        }
+        [key, rest @ ..] => match table
+            .entry(key.to_string())
+            .or_insert_with(|| Json::Object(Map::new()))
+        {
+            Json::Object(nested) => insert(nested, rest, value),
+            _ => false,
+        },
+    }
+}
+
+// `.toml` and `.json` files under `dir`, relative to it, skipping `_`/`.`
+// entries. Sorted.
+fn find_files(dir: &Utf8Path) -> Vec<Utf8PathBuf> {
+    let mut found = Vec::new();
+    let mut stack = vec![(Utf8PathBuf::new(), 0)];
+    while let Some((relative, depth)) = stack.pop() {
+        let Ok(entries) = dir.join(&relative).read_dir_utf8() else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            if name.starts_with(['_', '.']) {
+                continue;
+            }
+            let path = relative.join(name);
+            if entry.path().is_dir() {
+                if depth >= MAX_DEPTH {
+                    warn!("not looking for data below `{}`", entry.path());
+                } else {
+                    stack.push((path, depth + 1));
+                }
+            } else if matches!(path.extension(), Some("toml" | "json")) {
+                found.push(path);
+            }
+        }
+    }
+    found.sort();
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn nested_data() {
+        let root = Utf8PathBuf::from_path_buf(
+            std::env::temp_dir().join(format!("flaty-data-{}", std::process::id())),
+        )
+        .unwrap();
+        let dir = root.join("_data");
+        std::fs::create_dir_all(dir.join("team")).unwrap();
+        std::fs::write(dir.join("contact.toml"), "email = \"a@b.c\"").unwrap();
+        std::fs::write(dir.join("team/members.json"), "[{\"name\": \"Ann\"}]").unwrap();
+        std::fs::write(dir.join("team.toml"), "x = 1").unwrap();
+        std::fs::write(dir.join("broken.json"), "{").unwrap();
+        std::fs::write(dir.join("notes.txt"), "").unwrap();
+
+        let data = SiteData::default();
+        let value = data.load(&root).await;
+        // `team.toml` loses to the `team/` directory (sorted first).
+        assert_eq!(
+            *value,
+            serde_json::json!({
+                "contact": { "email": "a@b.c" },
+                "team": { "members": [{ "name": "Ann" }] },
+            })
+        );
+        // A stale value is still served while the directory is walked
+        // again; unchanged files keep the same value.
+        data.scan.expire();
+        assert!(Arc::ptr_eq(&value, &data.load(&root).await));
+        data.scan.walked().await;
+        assert!(Arc::ptr_eq(&value, &data.load(&root).await));
+
+        std::fs::remove_dir_all(&root).ok();
+    }
+}
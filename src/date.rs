@@ -0,0 +1,71 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
+
+// A front-matter date: an RFC 3339 date-time, a date-time without offset
+// (taken as UTC) or a bare date (midnight UTC). TOML dates reach pages as
+// strings in these forms.
+pub fn parse(value: &str) -> Option<DateTime<Utc>> {
+    parse_in(value, &Utc)
+}
+
+// Like `parse`, with dates and date-times without offset local to `tz`. A
+// local time skipped by a DST change is invalid; a repeated one is the first.
+pub fn parse_in<Tz: TimeZone>(value: &str, tz: &Tz) -> Option<DateTime<Tz>> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return Some(date.with_timezone(tz));
+    }
+    let naive = ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"]
+        .into_iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()?
+                .and_hms_opt(0, 0, 0)
+        })?;
+    tz.from_local_datetime(&naive).earliest()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn front_matter_dates() {
+        let utc = |s: &str| s.parse::<DateTime<Utc>>().unwrap();
+        assert_eq!(parse("2024-03-01"), Some(utc("2024-03-01T00:00:00Z")));
+        assert_eq!(
+            parse("2024-03-01T10:30:00"),
+            Some(utc("2024-03-01T10:30:00Z"))
+        );
+        assert_eq!(
+            parse("2024-03-01T10:30:00+02:00"),
+            Some(utc("2024-03-01T08:30:00Z"))
+        );
+        assert_eq!(
+            parse("2024-03-01 10:30:00.5Z"),
+            Some(utc("2024-03-01T10:30:00.5Z"))
+        );
+        assert_eq!(parse("March 1st"), None);
+        assert_eq!(parse("2024-02-30"), None);
+    }
+
+    #[test]
+    fn local_dates() {
+        let paris = chrono_tz::Europe::Paris;
+        let utc = |s: &str| s.parse::<DateTime<Utc>>().unwrap();
+        assert_eq!(
+            parse_in("2024-07-01T09:00:00", &paris),
+            Some(utc("2024-07-01T07:00:00Z").with_timezone(&paris))
+        );
+        assert_eq!(
+            parse_in("2024-01-01", &paris),
+            Some(utc("2023-12-31T23:00:00Z").with_timezone(&paris))
+        );
+        // Offsets win over the timezone.
+        assert_eq!(
+            parse_in("2024-07-01T09:00:00Z", &paris),
+            Some(utc("2024-07-01T09:00:00Z").with_timezone(&paris))
+        );
+        // Skipped by the switch to summer time.
+        assert_eq!(parse_in("2024-03-31T02:30:00", &paris), None);
+    }
+}
@@ -0,0 +1,197 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Error;
+use camino::Utf8Path;
+use dashmap::DashMap;
+use serde_json::{Map, Value as Json};
+use tokio::time::Instant;
+
+use crate::{
+    cache::{CacheMap, Cacheable, ExistsMap},
+    markdown::{toml_to_json, Page},
+};
+
+// A `_defaults.toml` file: front-matter fields for every `page.md` in its
+// directory and below.
+#[derive(Default)]
+pub struct Defaults(Map<String, Json>);
+
+impl Cacheable for Defaults {
+    fn compute(src: &str) -> anyhow::Result<Self> {
+        let table: toml::Table = src.parse()?;
+        Ok(Defaults(
+            table
+                .into_iter()
+                .map(|(key, value)| (key, toml_to_json(value)))
+                .collect(),
+        ))
+    }
+}
+
+// A page with its defaults merged in, kept so that its identity only changes
+// with the page or one of its defaults files.
+struct Merged {
+    page: Arc<Page>,
+    defaults: Vec<Arc<Defaults>>,
+    merged: Arc<Page>,
+    last_use: Instant,
+}
+
+// The pages of an app, with the fields of the `_defaults.toml` files of
+// their directories (from the root down to their own, nearest winning)
+// filling in what their front matter leaves out.
+pub struct SitePages {
+    files: CacheMap<Arc<Page>>,
+    defaults: CacheMap<Arc<Defaults>>,
+    probes: ExistsMap,
+    merged: DashMap<String, Merged>,
+}
+
+impl Default for SitePages {
+    fn default() -> Self {
+        SitePages {
+            // Not capped: the site snapshot holds every page anyway, and its
+            // rescans would otherwise evict and reparse pages past the cap.
+            // Only existing `page.md` files (and error pages) get entries.
+            files: CacheMap::new(usize::MAX),
+            defaults: CacheMap::default(),
+            probes: ExistsMap::default(),
+            merged: DashMap::new(),
+        }
+    }
+}
+
+impl SitePages {
+    // The page at `path`. Only `page.md` files under `root` get defaults; an
+    // invalid defaults file makes the pages below it invalid.
+    pub async fn load(
+        &self,
+        root: &Utf8Path,
+        path: &Utf8Path,
+    ) -> Result<Arc<Page>, (Arc<Page>, Error)> {
+        let page = self.files.load(path).await?;
+        let dir = match (path.file_name(), path.parent()) {
+            (Some("page.md"), Some(dir)) if dir.starts_with(root) => dir,
+            _ => return Ok(page),
+        };
+        let mut defaults = Vec::new();
+        for dir in dir.ancestors().take_while(|dir| dir.starts_with(root)) {
+            let file = dir.join("_defaults.toml");
+            if self.probes.exists(&file).await {
+                defaults.push(self.defaults.load(&file).await.map_err(|(_, err)| {
+                    (
+                        page.clone(),
+                        err.context(format!("invalid defaults `{file}`")),
+                    )
+                })?);
+            }
+        }
+        if defaults.is_empty() {
+            self.merged.remove(path.as_str());
+            return Ok(page);
+        }
+        // Root first, so that nearer directories override.
+        defaults.reverse();
+
+        let mut entry = self
+            .merged
+            .entry(path.as_str().to_owned())
+            .or_insert_with(|| Merged {
+                page: Default::default(),
+                defaults: Vec::new(),
+                merged: Default::default(),
+                last_use: Instant::now(),
+            });
+        let same = Arc::ptr_eq(&entry.page, &page)
+            && entry.defaults.len() == defaults.len()
+            && entry
+                .defaults
+                .iter()
+                .zip(&defaults)
+                .all(|(a, b)| Arc::ptr_eq(a, b));
+        if !same {
+            let mut fields = Map::new();
+            for file in &defaults {
+                fields.extend(file.0.clone());
+            }
+            entry.merged = Arc::new(page.with_defaults(fields));
+            entry.page = page;
+            entry.defaults = defaults;
+        }
+        entry.last_use = Instant::now();
+        Ok(entry.merged.clone())
+    }
+
+    pub fn sweep(&self, ttl: Duration) {
+        self.files.sweep(ttl);
+        self.defaults.sweep(ttl);
+        self.probes.sweep(ttl);
+        let now = Instant::now();
+        self.merged
+            .retain(|_, merged| now.saturating_duration_since(merged.last_use) < ttl);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use camino::Utf8PathBuf;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn cascade() {
+        let root = Utf8PathBuf::from_path_buf(
+            std::env::temp_dir().join(format!("flaty-defaults-{}", std::process::id())),
+        )
+        .unwrap();
+        let post = root.join("blog/post");
+        std::fs::create_dir_all(&post).unwrap();
+        std::fs::write(
+            root.join("_defaults.toml"),
+            "author = \"Ann\"\nlang = \"en\"",
+        )
+        .unwrap();
+        std::fs::write(
+            root.join("blog/_defaults.toml"),
+            "template = \"post\"\nauthor = \"Bob\"",
+        )
+        .unwrap();
+        std::fs::write(root.join("page.md"), "Home").unwrap();
+        std::fs::write(post.join("page.md"), "---\nlang = \"fr\"\n---\nPost").unwrap();
+        std::fs::write(root.join("blog/404.md"), "Gone").unwrap();
+
+        let pages = SitePages::default();
+        let field = |page: &Page, name: &str| page.fields().get(name).cloned();
+        let home = pages.load(&root, &root.join("page.md")).await.ok().unwrap();
+        assert_eq!(home.template(), "default");
+        assert_eq!(field(&home, "author"), Some("Ann".into()));
+        let page = pages.load(&root, &post.join("page.md")).await.ok().unwrap();
+        assert_eq!(page.template(), "post");
+        assert_eq!(field(&page, "author"), Some("Bob".into()));
+        assert_eq!(field(&page, "lang"), Some("fr".into()));
+        let other = pages
+            .load(&root, &root.join("blog/404.md"))
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(field(&other, "author"), None);
+
+        // Unchanged files keep the same page; a changed defaults file
+        // replaces it.
+        let again = pages.load(&root, &post.join("page.md")).await.ok().unwrap();
+        assert!(Arc::ptr_eq(&page, &again));
+        std::fs::write(root.join("blog/_defaults.toml"), "template = \"article\"").unwrap();
+        pages.sweep(Duration::ZERO);
+        let page = pages.load(&root, &post.join("page.md")).await.ok().unwrap();
+        assert_eq!(page.template(), "article");
+        assert_eq!(field(&page, "author"), Some("Ann".into()));
+
+        // Invalid defaults make the pages below them invalid.
+        std::fs::write(root.join("blog/_defaults.toml"), "template =").unwrap();
+        pages.sweep(Duration::ZERO);
+        assert!(pages.load(&root, &post.join("page.md")).await.is_err());
+        assert!(pages.load(&root, &root.join("page.md")).await.is_ok());
+
+        std::fs::remove_dir_all(&root).ok();
+    }
+}
@@ -0,0 +1,285 @@
+use std::fmt::Write as _;
+
+use anyhow::bail;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::Value as Json;
+
+use crate::markdown::Page;
+
+// Most recent entries included in a feed.
+pub const MAX_ENTRIES: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Atom,
+    Rss,
+}
+
+impl Format {
+    // Feeds are served next to their page: `/blog/feed.xml`, `/blog/rss.xml`.
+    pub fn from_file_name(name: &str) -> Option<Self> {
+        match name {
+            "feed.xml" => Some(Format::Atom),
+            "rss.xml" => Some(Format::Rss),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Atom => "application/atom+xml; charset=utf-8",
+            Format::Rss => "application/rss+xml; charset=utf-8",
+        }
+    }
+
+    fn file_name(self) -> &'static str {
+        match self {
+            Format::Atom => "feed.xml",
+            Format::Rss => "rss.xml",
+        }
+    }
+}
+
+// The feeds a page publishes: `feed = true` for both formats, or
+// `feed = "atom"` / `feed = "rss"` for one.
+pub fn formats(page: &Page) -> anyhow::Result<Vec<Format>> {
+    Ok(match page.fields().get("feed") {
+        None | Some(Json::Bool(false)) => Vec::new(),
+        Some(Json::Bool(true)) => vec![Format::Atom, Format::Rss],
+        Some(Json::String(format)) if format == "atom" => vec![Format::Atom],
+        Some(Json::String(format)) if format == "rss" => vec![Format::Rss],
+        Some(other) => bail!("invalid feed `{other}`: expected true, \"atom\" or \"rss\""),
+    })
+}
+
+pub struct Entry {
+    pub url: String,
+    pub title: String,
+    pub author: Option<String>,
+    pub published: Option<DateTime<Utc>>,
+    pub updated: DateTime<Utc>,
+    pub summary: String,
+    // Rendered HTML.
+    pub contents: String,
+}
+
+pub struct Feed {
+    // URL of the page publishing the feed.
+    pub url: String,
+    pub title: String,
+    pub description: String,
+    pub author: Option<String>,
+    // Newest first.
+    pub entries: Vec<Entry>,
+}
+
+impl Feed {
+    // The feed document, with links made absolute against `base_url` (no
+    // trailing slash).
+    pub fn render(&self, format: Format, base_url: &str, now: DateTime<Utc>) -> String {
+        let updated = self
+            .entries
+            .iter()
+            .map(|entry| entry.updated)
+            .max()
+            .unwrap_or(now);
+        match format {
+            Format::Atom => self.atom(base_url, updated),
+            Format::Rss => self.rss(base_url, updated),
+        }
+    }
+
+    fn atom(&self, base_url: &str, updated: DateTime<Utc>) -> String {
+        let link = format!("{base_url}{}", self.url);
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+        let _ = writeln!(xml, "  <title>{}</title>", escape(&self.title));
+        let _ = writeln!(xml, "  <link href=\"{}\"/>", escape(&link));
+        let _ = writeln!(
+            xml,
+            "  <link rel=\"self\" href=\"{}{}\"/>",
+            escape(&link),
+            Format::Atom.file_name()
+        );
+        let _ = writeln!(xml, "  <id>{}</id>", escape(&link));
+        let _ = writeln!(xml, "  <updated>{}</updated>", rfc3339(updated));
+        if !self.description.is_empty() {
+            let _ = writeln!(xml, "  <subtitle>{}</subtitle>", escape(&self.description));
+        }
+        if let Some(author) = &self.author {
+            let _ = writeln!(xml, "  <author><name>{}</name></author>", escape(author));
+        }
+        for entry in &self.entries {
+            let link = format!("{base_url}{}", entry.url);
+            xml.push_str("  <entry>\n");
+            let _ = writeln!(xml, "    <title>{}</title>", escape(&entry.title));
+            let _ = writeln!(xml, "    <link href=\"{}\"/>", escape(&link));
+            let _ = writeln!(xml, "    <id>{}</id>", escape(&link));
+            if let Some(published) = entry.published {
+                let _ = writeln!(xml, "    <published>{}</published>", rfc3339(published));
+            }
+            let _ = writeln!(xml, "    <updated>{}</updated>", rfc3339(entry.updated));
+            if let Some(author) = &entry.author {
+                let _ = writeln!(xml, "    <author><name>{}</name></author>", escape(author));
+            }
+            if !entry.summary.is_empty() {
+                let _ = writeln!(xml, "    <summary>{}</summary>", escape(&entry.summary));
+            }
+            // Relative links in the contents resolve against the entry's URL.
+            let _ = writeln!(
+                xml,
+                "    <content type=\"html\" xml:base=\"{}\">{}</content>",
+                escape(&link),
+                escape(&entry.contents)
+            );
+            xml.push_str("  </entry>\n");
+        }
+        xml.push_str("</feed>\n");
+        xml
+    }
+
+    fn rss(&self, base_url: &str, updated: DateTime<Utc>) -> String {
+        let link = format!("{base_url}{}", self.url);
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        xml.push_str(concat!(
+            "<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\"",
+            " xmlns:content=\"http://purl.org/rss/1.0/modules/content/\"",
+            " xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n",
+        ));
+        xml.push_str("  <channel>\n");
+        let _ = writeln!(xml, "    <title>{}</title>", escape(&self.title));
+        let _ = writeln!(xml, "    <link>{}</link>", escape(&link));
+        let _ = writeln!(
+            xml,
+            "    <atom:link rel=\"self\" type=\"application/rss+xml\" href=\"{}{}\"/>",
+            escape(&link),
+            Format::Rss.file_name()
+        );
+        let _ = writeln!(
+            xml,
+            "    <description>{}</description>",
+            escape(&self.description)
+        );
+        let _ = writeln!(
+            xml,
+            "    <lastBuildDate>{}</lastBuildDate>",
+            updated.to_rfc2822()
+        );
+        for entry in &self.entries {
+            let link = format!("{base_url}{}", entry.url);
+            xml.push_str("    <item>\n");
+            let _ = writeln!(xml, "      <title>{}</title>", escape(&entry.title));
+            let _ = writeln!(xml, "      <link>{}</link>", escape(&link));
+            let _ = writeln!(
+                xml,
+                "      <guid isPermaLink=\"true\">{}</guid>",
+                escape(&link)
+            );
+            let date = entry.published.unwrap_or(entry.updated);
+            let _ = writeln!(xml, "      <pubDate>{}</pubDate>", date.to_rfc2822());
+            // RSS's own `author` must be an email address.
+            if let Some(author) = entry.author.as_ref().or(self.author.as_ref()) {
+                let _ = writeln!(xml, "      <dc:creator>{}</dc:creator>", escape(author));
+            }
+            let _ = writeln!(
+                xml,
+                "      <description>{}</description>",
+                escape(&entry.summary)
+            );
+            let _ = writeln!(
+                xml,
+                "      <content:encoded>{}</content:encoded>",
+                escape(&entry.contents)
+            );
+            xml.push_str("    </item>\n");
+        }
+        xml.push_str("  </channel>\n</rss>\n");
+        xml
+    }
+}
+
+fn rfc3339(date: DateTime<Utc>) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::Cacheable;
+
+    fn feed() -> Feed {
+        Feed {
+            url: "/blog/".into(),
+            title: "Tom & Jerry".into(),
+            description: "Posts".into(),
+            author: Some("Tom".into()),
+            entries: vec![Entry {
+                url: "/blog/a/".into(),
+                title: "<A>".into(),
+                author: None,
+                published: Some("2024-03-01T00:00:00Z".parse().unwrap()),
+                updated: "2024-03-02T00:00:00Z".parse().unwrap(),
+                summary: "Sum".into(),
+                contents: "<p>Hi</p>".into(),
+            }],
+        }
+    }
+
+    #[test]
+    fn atom_and_rss() {
+        let now = "2024-06-01T00:00:00Z".parse().unwrap();
+        let atom = feed().render(Format::Atom, "https://example.com", now);
+        assert!(atom.contains("<title>Tom &amp; Jerry</title>"));
+        assert!(atom.contains("<link rel=\"self\" href=\"https://example.com/blog/feed.xml\"/>"));
+        assert!(atom.contains("<updated>2024-03-02T00:00:00Z</updated>\n  <subtitle>"));
+        assert!(atom.contains("<title>&lt;A&gt;</title>"));
+        assert!(atom.contains("<id>https://example.com/blog/a/</id>"));
+        assert!(atom.contains("<published>2024-03-01T00:00:00Z</published>"));
+        assert!(atom.contains(">&lt;p&gt;Hi&lt;/p&gt;</content>"));
+
+        let rss = feed().render(Format::Rss, "https://example.com", now);
+        assert!(rss.contains("<link>https://example.com/blog/</link>"));
+        assert!(rss.contains("<pubDate>Fri, 1 Mar 2024 00:00:00 +0000</pubDate>"));
+        assert!(rss.contains("<dc:creator>Tom</dc:creator>"));
+        assert!(rss.contains("<content:encoded>&lt;p&gt;Hi&lt;/p&gt;</content:encoded>"));
+
+        // An empty feed is as recent as the request.
+        let empty = Feed {
+            entries: Vec::new(),
+            ..feed()
+        };
+        assert!(empty
+            .render(Format::Atom, "https://example.com", now)
+            .contains("<updated>2024-06-01T00:00:00Z</updated>"));
+    }
+
+    #[test]
+    fn opting_in() {
+        let formats = |src: &str| formats(&Page::compute(src).unwrap());
+        assert!(formats("").unwrap().is_empty());
+        assert_eq!(
+            formats("---\nfeed = true\n---\n").unwrap(),
+            [Format::Atom, Format::Rss]
+        );
+        assert_eq!(
+            formats("---\nfeed = \"rss\"\n---\n").unwrap(),
+            [Format::Rss]
+        );
+        assert!(formats("---\nfeed = \"json\"\n---\n").is_err());
+    }
+}
@@ -0,0 +1,559 @@
+use std::fmt::Write as _;
+
+use chrono::{DateTime, Datelike, Timelike};
+use chrono_tz::Tz;
+use handlebars::{
+    handlebars_helper, Context, Handlebars, Helper, HelperDef, RenderContext, RenderError,
+    RenderErrorReason, ScopedJson,
+};
+use pure_rust_locales::{locale_match, Locale};
+use serde_json::Value as Json;
+
+use crate::{
+    date,
+    markdown::{render_markdown, strip_html_comments},
+    taxonomy,
+};
+
+// Without `locale=` or `[site] locale`.
+const DEFAULT_LOCALE: Locale = Locale::en_US;
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+const ELLIPSIS: &str = "…";
+
+// Bare languages whose main country is not spelled like them (`fr_FR`).
+const LANGUAGES: &[(&str, &str)] = &[
+    ("ar", "ar_SA"),
+    ("cs", "cs_CZ"),
+    ("da", "da_DK"),
+    ("el", "el_GR"),
+    ("en", "en_US"),
+    ("he", "he_IL"),
+    ("hi", "hi_IN"),
+    ("ja", "ja_JP"),
+    ("ko", "ko_KR"),
+    ("nb", "nb_NO"),
+    ("sv", "sv_SE"),
+    ("uk", "uk_UA"),
+    ("zh", "zh_CN"),
+];
+
+// Handlebars' own helpers, registered by `Handlebars::new` (which cannot list
+// them; `reserved_names` checks this against it).
+const HANDLEBARS_HELPERS: &[&str] = &[
+    "if", "unless", "each", "with", "lookup", "raw", "log", "eq", "ne", "gt", "gte", "lt", "lte",
+    "and", "or", "not", "len",
+];
+
+// The helpers every template (layout, snippet or partial) may use, with their
+// constructors (given the name).
+pub const BUILTINS: &[(&str, NewHelper)] = &[
+    ("is_empty", |_| Box::new(is_empty)),
+    ("date", |name| or_variable(name, DateHelper)),
+    ("number", |name| or_variable(name, NumberHelper)),
+    ("slugify", |name| or_variable(name, slugify)),
+    ("truncate", |name| or_variable(name, truncate)),
+    ("excerpt", |name| or_variable(name, excerpt)),
+    ("markdown", |name| or_variable(name, markdown)),
+    ("json", |name| or_variable(name, json)),
+    ("url_encode", |name| or_variable(name, url_encode)),
+    ("default", |name| or_variable(name, default)),
+    ("join", |name| or_variable(name, join)),
+    ("split", |name| or_variable(name, split)),
+];
+
+type NewHelper = fn(&'static str) -> Box<dyn HelperDef + Send + Sync>;
+
+// The layouts' helper for fingerprinted asset URLs.
+pub const ASSET: &str = "asset";
+
+// Whether helper scripts cannot take `name`: it is one of Handlebars' own
+// helpers, ours or `asset`.
+pub fn reserved(name: &str) -> bool {
+    name == ASSET
+        || HANDLEBARS_HELPERS.contains(&name)
+        || BUILTINS.iter().any(|&(builtin, _)| builtin == name)
+}
+
+// Register `BUILTINS`.
+pub fn register(hbs: &mut Handlebars<'_>) {
+    for &(name, helper) in BUILTINS {
+        hbs.register_helper(name, helper(name));
+    }
+}
+
+fn or_variable(
+    name: &'static str,
+    helper: impl HelperDef + Send + Sync + 'static,
+) -> Box<dyn HelperDef + Send + Sync> {
+    Box::new(OrVariable { name, helper })
+}
+
+// Pages and search hits have `date` and `excerpt` fields: without arguments,
+// `{{date}}` is still the variable, not the helper.
+struct OrVariable<H> {
+    name: &'static str,
+    helper: H,
+}
+
+impl<H: HelperDef> HelperDef for OrVariable<H> {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        r: &'reg Handlebars<'reg>,
+        ctx: &'rc Context,
+        rc: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        if h.params().is_empty() && h.hash().is_empty() {
+            return rc.evaluate(ctx, self.name);
+        }
+        self.helper.call_inner(h, r, ctx, rc)
+    }
+}
+
+// `fr_FR`, `fr-FR`, or a bare language (`fr`) for its main country.
+pub fn parse_locale(name: &str) -> Option<Locale> {
+    let name = name.replace('-', "_");
+    if let Ok(locale) = Locale::try_from(name.as_str()) {
+        return Some(locale);
+    }
+    if name.contains('_') {
+        return None;
+    }
+    let full = match LANGUAGES.iter().find(|(language, _)| *language == name) {
+        Some((_, full)) => (*full).to_owned(),
+        None => format!("{name}_{}", name.to_ascii_uppercase()),
+    };
+    Locale::try_from(full.as_str()).ok()
+}
+
+// True for null, empty string, empty array, or empty object.
+handlebars_helper!(is_empty: |v: Json| {
+    match v {
+        Json::Null => true,
+        Json::String(s) => s.is_empty(),
+        Json::Array(a) => a.is_empty(),
+        Json::Object(o) => o.is_empty(),
+        _ => false,
+    }
+});
+
+// `{{slugify "Rust Lang"}}` -> `rust-lang`, as taxonomy terms are.
+handlebars_helper!(slugify: |s: str| taxonomy::slug(s));
+
+// `{{truncate title 40}}`: at most 40 characters, the last one an ellipsis
+// when cut.
+handlebars_helper!(truncate: |s: str, max: u64| truncate_chars(s, max as usize));
+
+// `{{excerpt contents 200}}`: the text of some HTML, whitespace collapsed,
+// cut after a word within 200 characters (the default).
+handlebars_helper!(excerpt: |html: str, *args| {
+    let max = args.get(1).and_then(|max| max.as_u64()).unwrap_or(200);
+    excerpt_text(html, max as usize)
+});
+
+// `{{{markdown summary}}}`: a front-matter string rendered as Markdown.
+handlebars_helper!(markdown: |src: str| strip_html_comments(&render_markdown(src)));
+
+// `{{{json page}}}`: a value as JSON, safe inside a `<script>` element.
+handlebars_helper!(json: |value: Json| {
+    serde_json::to_string(value)
+        .map_err(|err| RenderErrorReason::Other(err.to_string()))?
+        .replace('<', "\\u003c")
+        .replace('>', "\\u003e")
+        .replace('&', "\\u0026")
+});
+
+// `{{url_encode term}}`: percent-encoded for a URL path segment or query value.
+handlebars_helper!(url_encode: |s: str| {
+    const RESERVED: &percent_encoding::AsciiSet = &percent_encoding::NON_ALPHANUMERIC
+        .remove(b'-')
+        .remove(b'_')
+        .remove(b'.')
+        .remove(b'~');
+    percent_encoding::utf8_percent_encode(s, RESERVED).to_string()
+});
+
+// `{{default subtitle "None"}}`: the fallback for null or an empty string.
+handlebars_helper!(default: |value: Json, fallback: Json| {
+    match value {
+        Json::Null => fallback.clone(),
+        Json::String(s) if s.is_empty() => fallback.clone(),
+        value => value.clone(),
+    }
+});
+
+// `{{join tags ", "}}`: the items of a list, as text.
+handlebars_helper!(join: |items: array, separator: str| {
+    items.iter().map(text).collect::<Vec<_>>().join(separator)
+});
+
+// `{{#each (split keywords ",")}}`: the trimmed, non-empty parts of a string.
+handlebars_helper!(split: |s: str, separator: str| {
+    s.split(separator)
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .map(Json::from)
+        .collect::<Vec<_>>()
+});
+
+// A string as itself, null as nothing, anything else as JSON.
+fn text(value: &Json) -> String {
+    match value {
+        Json::String(s) => s.clone(),
+        Json::Null => String::new(),
+        value => value.to_string(),
+    }
+}
+
+fn truncate_chars(s: &str, max: usize) -> String {
+    if s.chars().count() <= max {
+        return s.to_owned();
+    }
+    let mut cut: String = s.chars().take(max.saturating_sub(1)).collect();
+    cut.truncate(cut.trim_end().len());
+    cut.push_str(ELLIPSIS);
+    cut
+}
+
+fn excerpt_text(html: &str, max: usize) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            // Tags separate words: `<p>a</p><p>b</p>` is `a b`.
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            }
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    let text = unescape(&text);
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let mut out = String::new();
+    for word in &words {
+        let len = out.chars().count() + word.chars().count() + 1;
+        if len > max {
+            if out.is_empty() {
+                return truncate_chars(word, max);
+            }
+            out.push_str(ELLIPSIS);
+            return out;
+        }
+        if !out.is_empty() {
+            out.push(' ');
+        }
+        out.push_str(word);
+    }
+    out
+}
+
+// The entities Markdown rendering produces.
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+// `locale=` else `[site] locale`, as seen by the template.
+fn locale(h: &Helper<'_>, ctx: &Context) -> Result<Locale, RenderError> {
+    let name = h
+        .hash_get("locale")
+        .map(|locale| locale.value().clone())
+        .or_else(|| ctx.data().pointer("/site/locale").cloned());
+    match name {
+        None => Ok(DEFAULT_LOCALE),
+        Some(Json::String(name)) => parse_locale(&name)
+            .ok_or_else(|| RenderErrorReason::Other(format!("unknown locale `{name}`")).into()),
+        Some(_) => Err(RenderErrorReason::Other("locale must be a string".into()).into()),
+    }
+}
+
+// `{{date updated "%d %B %Y"}}`: a front-matter date in the site's timezone
+// (or `tz="..."`), with month and day names in its locale (or `locale=`).
+// Null renders as nothing.
+struct DateHelper;
+
+impl HelperDef for DateHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        ctx: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        let value = h
+            .param(0)
+            .ok_or(RenderErrorReason::ParamNotFoundForIndex("date", 0))?
+            .value();
+        let value = match value {
+            Json::Null => return Ok(Json::from("").into()),
+            Json::String(value) => value,
+            _ => return Err(RenderErrorReason::InvalidParamType("date string").into()),
+        };
+        let format = match h.param(1) {
+            Some(format) => format
+                .value()
+                .as_str()
+                .ok_or(RenderErrorReason::InvalidParamType("format string"))?,
+            None => DEFAULT_DATE_FORMAT,
+        };
+        let tz = h
+            .hash_get("tz")
+            .map(|tz| tz.value().clone())
+            .or_else(|| ctx.data().pointer("/site/timezone").cloned());
+        let tz: Tz = match tz {
+            None => Tz::UTC,
+            Some(Json::String(name)) => name
+                .parse()
+                .map_err(|_| RenderErrorReason::Other(format!("unknown timezone `{name}`")))?,
+            Some(_) => return Err(RenderErrorReason::InvalidParamType("timezone string").into()),
+        };
+        let date = date::parse_in(value, &tz)
+            .ok_or_else(|| RenderErrorReason::Other(format!("invalid date `{value}`")))?;
+        Ok(Json::String(format_date(&date, format, locale(h, ctx)?)?).into())
+    }
+}
+
+// chrono's `strftime`, with `%B`, `%b`, `%A`, `%a` and `%p` in `locale`.
+fn format_date(date: &DateTime<Tz>, format: &str, locale: Locale) -> Result<String, RenderError> {
+    let month = date.month0() as usize;
+    let weekday = date.weekday().num_days_from_sunday() as usize;
+    let mut localized = String::with_capacity(format.len());
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            localized.push(c);
+            continue;
+        }
+        let name = match chars.next() {
+            Some('B') => locale_match!(locale => LC_TIME::MON)[month],
+            Some('b' | 'h') => locale_match!(locale => LC_TIME::ABMON)[month],
+            Some('A') => locale_match!(locale => LC_TIME::DAY)[weekday],
+            Some('a') => locale_match!(locale => LC_TIME::ABDAY)[weekday],
+            Some('p') => locale_match!(locale => LC_TIME::AM_PM)[usize::from(date.hour() >= 12)],
+            // Left to chrono, with whatever follows (`%%`, `%-d`...).
+            Some(other) => {
+                localized.push('%');
+                localized.push(other);
+                continue;
+            }
+            None => {
+                localized.push('%');
+                continue;
+            }
+        };
+        localized.push_str(&name.replace('%', "%%"));
+    }
+    let mut formatted = String::new();
+    write!(formatted, "{}", date.format(&localized))
+        .map_err(|_| RenderErrorReason::Other(format!("invalid date format `{format}`")))?;
+    Ok(formatted)
+}
+
+// `{{number price 2}}`: a number with that many decimals (default: none for
+// integers, 2 otherwise) and the separators of the locale (or `locale=`).
+struct NumberHelper;
+
+impl HelperDef for NumberHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        ctx: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        let value = h
+            .param(0)
+            .ok_or(RenderErrorReason::ParamNotFoundForIndex("number", 0))?
+            .value();
+        let number = match value {
+            Json::Number(number) => number.as_f64(),
+            Json::String(s) => s.trim().parse().ok(),
+            _ => None,
+        }
+        .ok_or(RenderErrorReason::InvalidParamType("number"))?;
+        let decimals = match h.param(1) {
+            Some(decimals) => decimals
+                .value()
+                .as_u64()
+                .ok_or(RenderErrorReason::InvalidParamType("number of decimals"))?
+                .min(20) as usize,
+            None if value.is_i64() || value.is_u64() => 0,
+            None => 2,
+        };
+        Ok(Json::String(format_number(number, decimals, locale(h, ctx)?)).into())
+    }
+}
+
+fn format_number(number: f64, decimals: usize, locale: Locale) -> String {
+    let point = locale_match!(locale => LC_NUMERIC::DECIMAL_POINT);
+    let separator = locale_match!(locale => LC_NUMERIC::THOUSANDS_SEP);
+    let grouping = locale_match!(locale => LC_NUMERIC::GROUPING);
+
+    let formatted = format!("{:.*}", decimals, number.abs());
+    let (integer, fraction) = formatted.split_once('.').unwrap_or((&formatted, ""));
+    // Group sizes from the right; the last one repeats.
+    let mut groups = Vec::new();
+    let mut rest = integer;
+    let mut sizes = grouping.iter().copied();
+    let mut size = sizes.next().unwrap_or(0);
+    while size > 0 && rest.len() > size as usize {
+        let (head, group) = rest.split_at(rest.len() - size as usize);
+        groups.push(group);
+        rest = head;
+        size = sizes.next().unwrap_or(size);
+    }
+    groups.push(rest);
+    groups.reverse();
+
+    let negative = number < 0.0 && formatted.bytes().any(|b| b.is_ascii_digit() && b != b'0');
+    let mut out = String::from(if negative { "-" } else { "" });
+    out.push_str(&groups.join(separator));
+    if !fraction.is_empty() {
+        out.push_str(point);
+        out.push_str(fraction);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str, context: Json) -> Result<String, RenderError> {
+        let mut hbs = Handlebars::new();
+        register(&mut hbs);
+        hbs.render_template(template, &context)
+    }
+
+    #[test]
+    fn dates() {
+        let context = serde_json::json!({
+            "updated": "2024-03-01T23:30:00Z",
+            "day": "2024-03-04",
+            "site": { "timezone": "Europe/Paris", "locale": "fr_FR" },
+        });
+        let date = |template| render(template, context.clone()).unwrap();
+        // Past midnight in Paris.
+        assert_eq!(
+            date("{{date updated \"%A %-d %B %Y\"}}"),
+            "samedi 2 mars 2024"
+        );
+        assert_eq!(date("{{date updated}}"), "2024-03-02");
+        assert_eq!(
+            date("{{date updated \"%a %d %b, %H:%M %p\" locale=\"en\" tz=\"UTC\"}}"),
+            "Fri 01 Mar, 23:30 PM"
+        );
+        assert_eq!(
+            date("{{date day \"%B %% %e\" locale=\"de-DE\"}}"),
+            "März %  4"
+        );
+        assert_eq!(date("{{date missing}}"), "");
+        assert!(render("{{date \"soon\"}}", Json::Null).is_err());
+        assert!(render("{{date \"2024-01-01\" \"%Q\"}}", Json::Null).is_err());
+        assert!(render("{{date \"2024-01-01\" locale=\"xx\"}}", Json::Null).is_err());
+    }
+
+    #[test]
+    fn numbers() {
+        let number = |template| render(template, Json::Null).unwrap();
+        assert_eq!(number("{{number 1234567}}"), "1,234,567");
+        assert_eq!(number("{{number 1234.5}}"), "1,234.50");
+        assert_eq!(number("{{number -0.004 2}}"), "0.00");
+        assert_eq!(number("{{number \"-1234.5\" 0}}"), "-1,234");
+        assert_eq!(
+            number("{{number 1234567.891 1 locale=\"de_DE\"}}"),
+            "1.234.567,9"
+        );
+        assert_eq!(
+            number("{{number 12345678 locale=\"en_IN\"}}"),
+            "1,23,45,678"
+        );
+        assert!(render("{{number \"many\"}}", Json::Null).is_err());
+    }
+
+    #[test]
+    fn strings() {
+        let context = serde_json::json!({
+            "title": "Hello, wide world",
+            "tags": ["rust", "web", 3],
+            "contents": "<p>One &amp; <em>two</em></p>\n<p>three four</p>",
+            "summary": "Some *emphasis*",
+            "empty": "",
+        });
+        let render = |template| render(template, context.clone()).unwrap();
+        assert_eq!(render("{{slugify title}}"), "hello-wide-world");
+        assert_eq!(render("{{truncate title 8}}"), "Hello,…");
+        assert_eq!(render("{{truncate title 80}}"), "Hello, wide world");
+        assert_eq!(render("{{excerpt contents 14}}"), "One &amp; two…");
+        assert_eq!(render("{{excerpt contents}}"), "One &amp; two three four");
+        assert_eq!(
+            render("{{{markdown summary}}}"),
+            "<p>Some <em>emphasis</em></p>\n"
+        );
+        assert_eq!(
+            render("{{{json tags}}} {{{json \"</script>\"}}}"),
+            "[\"rust\",\"web\",3] \"\\u003c/script\\u003e\""
+        );
+        assert_eq!(render("{{url_encode \"a b/é\"}}"), "a%20b%2F%C3%A9");
+        assert_eq!(
+            render("{{default empty \"none\"}} {{default title \"none\"}}"),
+            "none Hello, wide world"
+        );
+        assert_eq!(render("{{join tags \", \"}}"), "rust, web, 3");
+        assert_eq!(
+            render("{{#each (split \"a, b,,c\" \",\")}}[{{this}}]{{/each}}"),
+            "[a][b][c]"
+        );
+        assert_eq!(render("{{is_empty empty}} {{is_empty tags}}"), "true false");
+    }
+
+    #[test]
+    fn variables_named_like_helpers() {
+        let context = serde_json::json!({
+            "excerpt": "<b>x</b>",
+            "pages": [{ "date": "2024-01-01" }],
+        });
+        assert_eq!(
+            render("{{{excerpt}}}|{{#each pages}}{{date}}{{/each}}", context).unwrap(),
+            "<b>x</b>|2024-01-01"
+        );
+    }
+
+    #[test]
+    fn locales() {
+        assert_eq!(parse_locale("fr"), Some(Locale::fr_FR));
+        assert_eq!(parse_locale("pt-BR"), Some(Locale::pt_BR));
+        assert_eq!(parse_locale("en"), Some(Locale::en_US));
+        assert_eq!(parse_locale("xx"), None);
+    }
+
+    // Whether `hbs` has a helper `name` (`Handlebars::has_helper` is not
+    // public): calling a missing one fails with `HelperNotFound`.
+    fn has_helper(hbs: &Handlebars<'_>, name: &str) -> bool {
+        let called = hbs.render_template(&format!("{{{{{name} 1}}}}"), &Json::Null);
+        !called.is_err_and(|err| matches!(err.reason(), RenderErrorReason::HelperNotFound(_)))
+    }
+
+    #[test]
+    fn reserved_names() {
+        let mut hbs = Handlebars::new();
+        for name in HANDLEBARS_HELPERS {
+            assert!(has_helper(&hbs, name), "{name}");
+            assert!(reserved(name));
+        }
+        register(&mut hbs);
+        for &(name, _) in BUILTINS {
+            assert!(has_helper(&hbs, name), "{name}");
+            assert!(reserved(name));
+        }
+        assert!(reserved(ASSET));
+        assert!(!has_helper(&hbs, "upper"));
+        assert!(!reserved("upper"));
+    }
+}
@@ -0,0 +1,23 @@
+// The server's modules, a library so that `benches/` can reach them.
+
+mod cache;
+mod collection;
+mod data;
+mod date;
+mod defaults;
+mod feed;
+mod helpers;
+mod maintenance;
+mod markdown;
+mod preview;
+mod redirect;
+mod request;
+mod sass;
+mod script;
+mod search;
+mod site;
+mod sitemap;
+mod taxonomy;
+mod template;
+mod url;
+pub mod web;
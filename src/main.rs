@@ -1,11 +1,15 @@
-use std::{net::ToSocketAddrs, sync::Arc, time::Duration};
+use std::{
+    net::{SocketAddr, ToSocketAddrs},
+    sync::Arc,
+    time::Duration,
+};
 
 use anyhow::{anyhow, Context};
 use axum::{
     body::Body,
     debug_handler,
-    extract::State,
-    http::{header, HeaderValue, Method, Request, StatusCode},
+    extract::{ConnectInfo, State},
+    http::{header, HeaderName, HeaderValue, Method, Request, StatusCode},
     response::{IntoResponse, Response},
     Router,
 };
@@ -15,15 +19,8 @@ use dashmap::DashMap;
 use tower::ServiceExt;
 use tower_http::{services::ServeFile, set_header::SetResponseHeaderLayer};
 use tracing::{info, warn};
-use twox_hash::XxHash3_128;
-
-use crate::web::{App, MyRequest};
 
-mod cache;
-mod markdown;
-mod sass;
-mod url;
-mod web;
+use flaty::web::{self, App, CachePolicy, MyRequest, Rendered};
 
 // Cached pages/styles and idle multi-mode sites are released after this long
 // with no access; the next request recomputes/recreates them transparently.
@@ -45,6 +42,9 @@ struct Args {
     /// Serve each subdirectory as a site selected by the Host header
     #[arg(long)]
     multi: bool,
+    /// Show template errors in the browser
+    #[arg(long)]
+    dev: bool,
 }
 
 enum Sites {
@@ -55,6 +55,7 @@ enum Sites {
     Multi {
         root: Utf8PathBuf,
         apps: DashMap<String, Arc<App>>,
+        development: bool,
     },
 }
 
@@ -62,7 +63,11 @@ impl Sites {
     async fn resolve(&self, host: Option<&str>) -> Option<Arc<App>> {
         match self {
             Sites::Single(app) => Some(app.clone()),
-            Sites::Multi { root, apps } => {
+            Sites::Multi {
+                root,
+                apps,
+                development,
+            } => {
                 let name = normalize_host(host?)?;
                 if let Some(app) = apps.get(&name) {
                     app.touch();
@@ -81,7 +86,7 @@ impl Sites {
                 }
                 Some(
                     apps.entry(name)
-                        .or_insert_with(|| Arc::new(App::new(dir)))
+                        .or_insert_with(|| Arc::new(App::new(dir).with_development(*development)))
                         .clone(),
                 )
             }
@@ -158,7 +163,7 @@ async fn main() -> anyhow::Result<()> {
             if !is_site_name(&name) || !entry.path().is_dir() {
                 continue;
             }
-            let app = Arc::new(App::new(entry.path().to_owned()));
+            let app = Arc::new(App::new(entry.path().to_owned()).with_development(args.dev));
             if let Err(err) = app.check_config().await {
                 warn!("site `{name}`: {err:?} (serving 404 until `_config.toml` is valid)");
             }
@@ -173,9 +178,10 @@ async fn main() -> anyhow::Result<()> {
         Sites::Multi {
             root: args.directory,
             apps,
+            development: args.dev,
         }
     } else {
-        let app = Arc::new(App::new(args.directory));
+        let app = Arc::new(App::new(args.directory).with_development(args.dev));
         if let Err(err) = app.check_config().await {
             warn!("{err:?} (serving 404 until `_config.toml` is valid)");
         }
@@ -207,18 +213,25 @@ async fn main() -> anyhow::Result<()> {
     let local_addr = listener.local_addr()?;
 
     info!("listening on http://{}/", local_addr);
-    axum::serve(listener, app.into_make_service())
-        .with_graceful_shutdown(async {
-            tokio::signal::ctrl_c()
-                .await
-                .expect("failed to install Ctrl+C handler")
-        })
-        .await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler")
+    })
+    .await?;
     Ok(())
 }
 
 #[debug_handler]
-async fn handler(State(sites): State<Arc<Sites>>, req: Request<Body>) -> Response {
+async fn handler(
+    State(sites): State<Arc<Sites>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    req: Request<Body>,
+) -> Response {
     let host = req
         .headers()
         .get(header::HOST)
@@ -232,7 +245,17 @@ async fn handler(State(sites): State<Arc<Sites>>, req: Request<Body>) -> Respons
 
     // HEAD is handled as GET; hyper drops the response body.
     if method != Method::GET && method != Method::HEAD {
-        return error_page(&app, StatusCode::NOT_FOUND, "404.html", String::new()).await;
+        let mut response = error_page(
+            &app,
+            StatusCode::METHOD_NOT_ALLOWED,
+            "Method not allowed",
+            uri_path,
+        )
+        .await;
+        response
+            .headers_mut()
+            .insert(header::ALLOW, HeaderValue::from_static("GET, HEAD"));
+        return response;
     }
 
     let if_none_match = req
@@ -248,86 +271,121 @@ async fn handler(State(sites): State<Arc<Sites>>, req: Request<Body>) -> Respons
 
     let request = MyRequest::GET {
         path: uri_path,
+        query: req.uri().query(),
         authorization,
+        peer: Some(peer.ip()),
+        forwarded_for: req
+            .headers()
+            .get("x-forwarded-for")
+            .and_then(|v| v.to_str().ok()),
+        host,
+        accept_language: req
+            .headers()
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|v| v.to_str().ok()),
     };
 
     match web::web(app.clone(), request).await {
-        Ok(r) => match r {
-            web::MyResponse::Html(x) => {
-                cached(x, "text/html; charset=utf-8", if_none_match.as_deref())
-            }
-            web::MyResponse::Css(x) => {
-                cached(x, "text/css; charset=utf-8", if_none_match.as_deref())
-            }
-            web::MyResponse::File(f) => serve_file(&f, req).await,
-            web::MyResponse::Redirect(url) => redirect(&url),
-        },
+        Ok(r) => respond(r, if_none_match.as_deref(), req).await,
         Err(e) => {
             use StatusCode as S;
-            match e {
-                web::MyError::NotFound => {
-                    error_page(&app, S::NOT_FOUND, "404.html", String::new()).await
+            let (status, message) = match e {
+                web::MyError::NotFound => (S::NOT_FOUND, "Not found".into()),
+                web::MyError::Gone => (S::GONE, "Gone".into()),
+                web::MyError::Unauthorized => {
+                    let mut response =
+                        error_page(&app, S::UNAUTHORIZED, "Unauthorized", uri_path).await;
+                    response.headers_mut().insert(
+                        header::WWW_AUTHENTICATE,
+                        HeaderValue::from_static("Basic realm=\"flaty\""),
+                    );
+                    return response;
                 }
-                web::MyError::Unauthorized => unauthorized(),
-                web::MyError::InvalidPage => {
-                    error_page(
+                web::MyError::InvalidPage => (S::INTERNAL_SERVER_ERROR, "Invalid page".into()),
+                web::MyError::InvalidScss => (S::INTERNAL_SERVER_ERROR, "Invalid SCSS".into()),
+                web::MyError::Unavailable { retry_after } => {
+                    let mut response = error_page(
                         &app,
-                        S::INTERNAL_SERVER_ERROR,
-                        "500.html",
-                        "Invalid page".into(),
+                        S::SERVICE_UNAVAILABLE,
+                        "Down for maintenance",
+                        uri_path,
                     )
-                    .await
-                }
-                web::MyError::InvalidScss => {
-                    error_page(
-                        &app,
-                        S::INTERNAL_SERVER_ERROR,
-                        "500.html",
-                        "Invalid SCSS".into(),
-                    )
-                    .await
+                    .await;
+                    response
+                        .headers_mut()
+                        .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+                    return response;
                 }
-                web::MyError::Internal(msg) => {
-                    error_page(&app, S::INTERNAL_SERVER_ERROR, "500.html", msg).await
+                web::MyError::Template(diagnostic) => {
+                    if app.development() {
+                        return rendered(web::error_overlay(&diagnostic), None);
+                    }
+                    (S::INTERNAL_SERVER_ERROR, "Invalid template".into())
                 }
+                web::MyError::Internal(msg) => (S::INTERNAL_SERVER_ERROR, msg),
                 // Details are logged; do not echo file paths to clients.
-                web::MyError::CannotRead => {
-                    error_page(
-                        &app,
-                        S::INTERNAL_SERVER_ERROR,
-                        "500.html",
-                        "Internal error".into(),
-                    )
-                    .await
-                }
-            }
+                web::MyError::CannotRead => (S::INTERNAL_SERVER_ERROR, "Internal error".into()),
+            };
+            error_page(&app, status, &message, uri_path).await
         }
     }
 }
 
-// Serve a custom error page from `_style/{file}` if present, else a plain body.
-async fn error_page(app: &App, status: StatusCode, file: &str, fallback: String) -> Response {
-    let path = app.root().join("_style").join(file);
-    if let Ok(html) = tokio::fs::read_to_string(&path).await {
-        return Response::builder()
-            .status(status)
-            .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
-            .body(Body::from(html))
-            .unwrap()
-            .into_response();
+async fn respond(r: web::MyResponse, if_none_match: Option<&str>, req: Request<Body>) -> Response {
+    match r {
+        web::MyResponse::Page(page) => rendered(page, if_none_match),
+        web::MyResponse::Css(x, hash, policy) => {
+            let etag = Some(web::hash_etag(hash));
+            cached(x, etag, "text/css; charset=utf-8", policy, if_none_match)
+        }
+        web::MyResponse::File(f, policy) => {
+            let mut response = serve_file(&f, req).await;
+            if policy == CachePolicy::Immutable && response.status().is_success() {
+                response
+                    .headers_mut()
+                    .insert(header::CACHE_CONTROL, cache_control(policy));
+            }
+            response
+        }
+        web::MyResponse::Redirect(url, status) => redirect(&url, status).unwrap_or_else(|err| {
+            warn!("cannot redirect to `{}`: {err}", url.escape_debug());
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }),
+        web::MyResponse::Noindex(inner) => {
+            let mut response = Box::pin(respond(*inner, if_none_match, req)).await;
+            response.headers_mut().insert(
+                HeaderName::from_static("x-robots-tag"),
+                HeaderValue::from_static("noindex"),
+            );
+            response
+        }
     }
-    (status, fallback).into_response()
 }
 
-// Serve a generated body with an ETag; answer 304 when it is unchanged.
-fn cached(body: String, mime: &str, if_none_match: Option<&str>) -> Response {
-    let etag = format!("\"{:032x}\"", XxHash3_128::oneshot(body.as_bytes()));
+// Render the site's page for an error status (see `web::error_page`).
+async fn error_page(app: &Arc<App>, status: StatusCode, message: &str, path: &str) -> Response {
+    rendered(
+        web::error_page(app, status.as_u16(), message, path).await,
+        None,
+    )
+}
+
+// Serve a generated body with an ETag (hashed unless given); answer 304
+// when it is unchanged.
+fn cached(
+    body: String,
+    etag: Option<String>,
+    mime: &str,
+    policy: CachePolicy,
+    if_none_match: Option<&str>,
+) -> Response {
+    let etag = etag.unwrap_or_else(|| web::etag(&body));
 
     if if_none_match == Some(etag.as_str()) {
         return Response::builder()
             .status(StatusCode::NOT_MODIFIED)
             .header(header::ETAG, &etag)
-            .header(header::CACHE_CONTROL, "no-cache")
+            .header(header::CACHE_CONTROL, cache_control(policy))
             .body(Body::empty())
             .unwrap()
             .into_response();
@@ -336,28 +394,54 @@ fn cached(body: String, mime: &str, if_none_match: Option<&str>) -> Response {
     Response::builder()
         .header(header::CONTENT_TYPE, mime)
         .header(header::ETAG, &etag)
-        .header(header::CACHE_CONTROL, "no-cache")
+        .header(header::CACHE_CONTROL, cache_control(policy))
         .body(Body::from(body))
         .unwrap()
         .into_response()
 }
 
-fn unauthorized() -> Response {
-    Response::builder()
-        .status(StatusCode::UNAUTHORIZED)
-        .header(header::WWW_AUTHENTICATE, "Basic realm=\"flaty\"")
-        .body(Body::from("Unauthorized"))
-        .unwrap()
-        .into_response()
+// Serve a rendered page with the status and content type it asks for. Only
+// a 200 is cacheable; other statuses are sent as-is.
+fn rendered(page: Rendered, if_none_match: Option<&str>) -> Response {
+    let content_type = page
+        .content_type
+        .as_deref()
+        .unwrap_or("text/html; charset=utf-8");
+    let status = StatusCode::from_u16(page.status).unwrap_or(StatusCode::OK);
+    if status == StatusCode::OK {
+        cached(
+            page.body,
+            page.etag,
+            content_type,
+            page.policy,
+            if_none_match,
+        )
+    } else {
+        Response::builder()
+            .status(status)
+            .header(header::CONTENT_TYPE, content_type)
+            .header(header::CACHE_CONTROL, cache_control(page.policy))
+            .body(Body::from(page.body))
+            .unwrap()
+            .into_response()
+    }
 }
 
-fn redirect(url: &str) -> Response {
-    Response::builder()
-        .status(StatusCode::MOVED_PERMANENTLY)
-        .header("Location", url)
-        .body(Body::empty())
-        .unwrap()
-        .into_response()
+fn cache_control(policy: CachePolicy) -> HeaderValue {
+    match policy {
+        CachePolicy::Revalidate => HeaderValue::from_static("no-cache"),
+        // Fingerprinted URLs change with their contents: cache for a year.
+        CachePolicy::Immutable => HeaderValue::from_static("public, max-age=31536000, immutable"),
+        CachePolicy::Private => HeaderValue::from_static("private, no-store"),
+    }
+}
+
+fn redirect(url: &str, status: u16) -> anyhow::Result<Response> {
+    Ok(Response::builder()
+        .status(StatusCode::from_u16(status).unwrap_or(StatusCode::MOVED_PERMANENTLY))
+        .header(header::LOCATION, HeaderValue::from_str(url)?)
+        .body(Body::empty())?
+        .into_response())
 }
 
 async fn serve_file(path: &Utf8Path, req: Request<Body>) -> Response {
@@ -0,0 +1,153 @@
+use std::net::IpAddr;
+
+use anyhow::anyhow;
+use ipnet::IpNet;
+use serde::Deserialize;
+
+const DEFAULT_RETRY_AFTER: u32 = 3600;
+
+// `maintenance = true`, or a table that also lists who may bypass it.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum MaintenanceSpec {
+    Switch(bool),
+    Table(MaintenanceTable),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaintenanceTable {
+    #[serde(default)]
+    enabled: bool,
+    retry_after: Option<u32>,
+    #[serde(default)]
+    users: Vec<String>,
+    #[serde(default)]
+    ips: Vec<String>,
+    #[serde(default)]
+    trust_forwarded: bool,
+}
+
+// Maintenance mode: every request gets a 503, except from the listed users
+// and addresses. Also switched on by a `_maintenance` file at the site root.
+#[derive(Debug)]
+pub struct Maintenance {
+    pub enabled: bool,
+    pub retry_after: u32,
+    users: Vec<String>,
+    ips: Vec<IpNet>,
+    // Take the client address from the last `X-Forwarded-For` entry (the one
+    // added by the reverse proxy) rather than from the connection.
+    trust_forwarded: bool,
+}
+
+impl Default for Maintenance {
+    fn default() -> Self {
+        Maintenance {
+            enabled: false,
+            retry_after: DEFAULT_RETRY_AFTER,
+            users: Vec::new(),
+            ips: Vec::new(),
+            trust_forwarded: false,
+        }
+    }
+}
+
+impl Maintenance {
+    pub fn new(spec: Option<MaintenanceSpec>) -> anyhow::Result<Self> {
+        let table = match spec {
+            None => return Ok(Maintenance::default()),
+            Some(MaintenanceSpec::Switch(enabled)) => {
+                return Ok(Maintenance {
+                    enabled,
+                    ..Maintenance::default()
+                })
+            }
+            Some(MaintenanceSpec::Table(table)) => table,
+        };
+        let ips = table
+            .ips
+            .iter()
+            .map(|ip| {
+                ip.parse::<IpNet>()
+                    .or_else(|_| ip.parse::<IpAddr>().map(IpNet::from))
+                    .map_err(|_| anyhow!("maintenance: invalid address or range `{ip}`"))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Maintenance {
+            enabled: table.enabled,
+            retry_after: table.retry_after.unwrap_or(DEFAULT_RETRY_AFTER),
+            users: table.users,
+            ips,
+            trust_forwarded: table.trust_forwarded,
+        })
+    }
+
+    // Whether a request from `user` (already authenticated) at `peer`, with
+    // the given `X-Forwarded-For` header, may browse during maintenance.
+    pub fn bypass(
+        &self,
+        user: Option<&str>,
+        peer: Option<IpAddr>,
+        forwarded_for: Option<&str>,
+    ) -> bool {
+        if user.is_some_and(|user| self.users.iter().any(|u| u == user)) {
+            return true;
+        }
+        let client = if self.trust_forwarded {
+            forwarded_for
+                .and_then(|header| header.rsplit(',').next())
+                .and_then(|ip| ip.trim().parse().ok())
+        } else {
+            peer
+        };
+        client.is_some_and(|client| self.ips.iter().any(|net| net.contains(&client)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(src: &str) -> anyhow::Result<Maintenance> {
+        #[derive(Deserialize)]
+        struct File {
+            maintenance: Option<MaintenanceSpec>,
+        }
+        let file: File = toml::from_str(src)?;
+        Maintenance::new(file.maintenance)
+    }
+
+    #[test]
+    fn switch_and_table() {
+        assert!(!parse("").unwrap().enabled);
+        assert!(parse("maintenance = true").unwrap().enabled);
+        let m = parse("[maintenance]\nretry_after = 60").unwrap();
+        assert!(!m.enabled);
+        assert_eq!(m.retry_after, 60);
+        assert!(parse("[maintenance]\nips = [\"not an ip\"]").is_err());
+        assert!(parse("[maintenance]\nenable = true").is_err());
+    }
+
+    #[test]
+    fn bypass_by_user_or_address() {
+        let m = parse(
+            "[maintenance]\nenabled = true\nusers = [\"admin\"]\nips = [\"10.0.0.0/8\", \"::1\"]",
+        )
+        .unwrap();
+        let local: IpAddr = "10.1.2.3".parse().unwrap();
+        let remote: IpAddr = "192.0.2.1".parse().unwrap();
+        assert!(m.bypass(Some("admin"), Some(remote), None));
+        assert!(!m.bypass(Some("other"), Some(remote), None));
+        assert!(m.bypass(None, Some(local), None));
+        assert!(m.bypass(None, Some("::1".parse().unwrap()), None));
+        assert!(!m.bypass(None, Some(remote), None));
+        // Forwarded addresses are ignored unless trusted.
+        assert!(!m.bypass(None, Some(remote), Some("10.0.0.1")));
+
+        let m = parse("[maintenance]\nips = [\"10.0.0.0/8\"]\ntrust_forwarded = true").unwrap();
+        assert!(m.bypass(None, Some(remote), Some("192.0.2.7, 10.0.0.1")));
+        // Only the entry added by the proxy counts, not client-supplied ones.
+        assert!(!m.bypass(None, Some(local), Some("10.0.0.1, 192.0.2.7")));
+    }
+}
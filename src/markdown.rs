@@ -1,11 +1,13 @@
 use std::{fmt, ops::Range};
 
 use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
 use pulldown_cmark::{html, Event, Parser, Tag, TagEnd};
 use serde_json::{Map, Value as Json};
 use toml::{Table, Value};
 
-use crate::cache::Cacheable;
+use crate::{cache::Cacheable, date};
 
 const MAX_SNIPPET_DEPTH: usize = 16;
 
@@ -63,10 +65,109 @@ impl Page {
             .unwrap_or("default")
     }
 
+    // Former URLs of this page (`aliases = ["/old/"]`), redirected to it.
+    pub fn aliases(&self) -> impl Iterator<Item = &str> {
+        self.fields
+            .get("aliases")
+            .and_then(Json::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Json::as_str)
+            .filter(|alias| alias.starts_with('/'))
+    }
+
+    // Where a pure redirect page (`redirect = "https://..."`) points.
+    pub fn redirect(&self) -> Option<&str> {
+        self.fields.get("redirect").and_then(Json::as_str)
+    }
+
+    // HTTP status to render the page with (`status = 410` for a gone page).
+    pub fn status(&self) -> anyhow::Result<Option<u16>> {
+        match self.fields.get("status") {
+            None => Ok(None),
+            Some(status) => status
+                .as_u64()
+                .and_then(|status| u16::try_from(status).ok())
+                .map(Some)
+                .ok_or_else(|| anyhow!("invalid status `{status}`")),
+        }
+    }
+
+    // Content type for pages whose template renders something else than HTML.
+    pub fn content_type(&self) -> Option<&str> {
+        self.fields.get("content_type").and_then(Json::as_str)
+    }
+
+    // Publication date (`date = 2024-03-01`), when valid.
+    pub fn date(&self) -> Option<DateTime<Utc>> {
+        self.fields
+            .get("date")
+            .and_then(Json::as_str)
+            .and_then(date::parse)
+    }
+
+    // Date of the last significant change (`updated = 2024-03-02`).
+    pub fn updated(&self) -> Option<DateTime<Utc>> {
+        self.fields
+            .get("updated")
+            .and_then(Json::as_str)
+            .and_then(date::parse)
+    }
+
+    // When the page goes live (`publish_date`); a date without offset is in
+    // the site's timezone `tz`.
+    pub fn publish_date(&self, tz: Tz) -> anyhow::Result<Option<DateTime<Tz>>> {
+        self.local_date("publish_date", tz)
+    }
+
+    // When the page is withdrawn (`expire_date`), like `publish_date`.
+    pub fn expire_date(&self, tz: Tz) -> anyhow::Result<Option<DateTime<Tz>>> {
+        self.local_date("expire_date", tz)
+    }
+
+    fn local_date(&self, name: &str, tz: Tz) -> anyhow::Result<Option<DateTime<Tz>>> {
+        match self.fields.get(name) {
+            None => Ok(None),
+            Some(Json::String(value)) => date::parse_in(value, &tz)
+                .map(Some)
+                .ok_or_else(|| anyhow!("invalid {name} `{value}`")),
+            Some(other) => Err(anyhow!("invalid {name} `{other}`")),
+        }
+    }
+
+    // Unpublished (`draft = true`): left out of listings.
+    pub fn draft(&self) -> bool {
+        self.fields.get("draft").and_then(Json::as_bool) == Some(true)
+    }
+
+    // The `summary` field, else the text of the first paragraph.
+    pub fn summary(&self) -> String {
+        if let Some(summary) = self.fields.get("summary").and_then(Json::as_str) {
+            return summary.to_owned();
+        }
+        self.body
+            .blocks()
+            .iter()
+            .find_map(|block| match block {
+                Block::Markdown(src) => first_paragraph(src),
+                Block::Snippet { .. } => None,
+            })
+            .unwrap_or_default()
+    }
+
     pub fn fields(&self) -> &Map<String, Json> {
         &self.fields
     }
 
+    // This page with `defaults` for the fields its front matter leaves out.
+    pub fn with_defaults(&self, mut defaults: Map<String, Json>) -> Page {
+        defaults.extend(self.fields.clone());
+        Page {
+            fields: defaults,
+            body: self.body.clone(),
+        }
+    }
+
     pub fn body(&self) -> &Document {
         &self.body
     }
@@ -90,6 +191,43 @@ pub fn render_markdown(src: &str) -> String {
     buf
 }
 
+// The text a reader sees in rendered Markdown, without any markup.
+pub fn plain_text(src: &str) -> String {
+    let mut text = String::new();
+    for event in Parser::new(src) {
+        match event {
+            Event::Text(s) | Event::Code(s) => text.push_str(&s),
+            Event::End(
+                TagEnd::Emphasis
+                | TagEnd::Strong
+                | TagEnd::Strikethrough
+                | TagEnd::Link
+                | TagEnd::Image,
+            ) => {}
+            // Block boundaries separate words.
+            Event::SoftBreak | Event::HardBreak | Event::End(_) => text.push(' '),
+            _ => {}
+        }
+    }
+    text
+}
+
+// Plain text of the first paragraph of some Markdown.
+fn first_paragraph(src: &str) -> Option<String> {
+    let mut events = Parser::new(src).skip_while(|e| !matches!(e, Event::Start(Tag::Paragraph)));
+    events.next()?;
+    let mut text = String::new();
+    for event in events {
+        match event {
+            Event::Text(s) | Event::Code(s) => text.push_str(&s),
+            Event::SoftBreak | Event::HardBreak => text.push(' '),
+            Event::End(TagEnd::Paragraph) => break,
+            _ => {}
+        }
+    }
+    Some(text)
+}
+
 pub fn strip_html_comments(src: &str) -> String {
     let mut output = String::with_capacity(src.len());
     let mut remaining = src;
@@ -277,8 +415,11 @@ impl<'a> DirectiveParser<'a> {
             .into_iter()
             .map(|(key, value)| (key, toml_to_json(value)))
             .collect();
-        if params.contains_key("contents") || params.contains_key("page") {
-            return self.error("`contents` and `page` are reserved parameters");
+        if ["contents", "page", "site"]
+            .iter()
+            .any(|name| params.contains_key(*name))
+        {
+            return self.error("`contents`, `page` and `site` are reserved parameters");
         }
         Ok(params)
     }
@@ -388,7 +529,7 @@ fn parse_header(src: &str) -> Result<(Map<String, Json>, &str), MarkdownError> {
 
 // TOML maps onto JSON one-to-one, except datetimes, which JSON lacks and we
 // render as strings.
-fn toml_to_json(value: Value) -> Json {
+pub fn toml_to_json(value: Value) -> Json {
     match value {
         Value::String(s) => Json::String(s),
         Value::Integer(n) => Json::Number(n.into()),
@@ -424,6 +565,27 @@ mod tests {
         );
     }
 
+    #[test]
+    fn summaries() {
+        let page = markdown("# Title\n\nFirst *para*\ngraph.\n\nSecond.").unwrap();
+        assert_eq!(page.summary(), "First para graph.");
+        let page = markdown("---\nsummary = \"Given.\"\n---\nFirst.").unwrap();
+        assert_eq!(page.summary(), "Given.");
+        assert_eq!(markdown("").unwrap().summary(), "");
+    }
+
+    #[test]
+    fn statuses() {
+        let status = |header: &str| markdown(&format!("---\n{header}\n---\n")).unwrap().status();
+        assert_eq!(status("").unwrap(), None);
+        assert_eq!(status("status = 410").unwrap(), Some(410));
+        // Errors name the bad value.
+        let err = status("status = \"gone\"").unwrap_err();
+        assert_eq!(err.to_string(), "invalid status `\"gone\"`");
+        assert!(status("status = 70000").is_err());
+        assert!(status("status = -1").is_err());
+    }
+
     #[test]
     fn body_only_has_no_header_fields() {
         let page = markdown("just text").unwrap();
@@ -0,0 +1,145 @@
+use anyhow::bail;
+use base64::Engine as _;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+
+const DEFAULT_TTL: u64 = 7 * 24 * 3600;
+const MIN_SECRET_LEN: usize = 16;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// The `[drafts]` table of `_config.toml`.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct DraftsSection {
+    #[serde(default)]
+    editors: Vec<String>,
+    preview_secret: Option<String>,
+    preview_ttl: Option<u64>,
+}
+
+// Who may see drafts: the listed (authenticated) editors, and anyone with a
+// preview link signed with the site's secret, until it expires.
+#[derive(Debug, Default)]
+pub struct Drafts {
+    editors: Vec<String>,
+    previews: Option<Previews>,
+}
+
+#[derive(Debug)]
+struct Previews {
+    secret: String,
+    // Lifetime of new links, in seconds.
+    ttl: u64,
+}
+
+impl Drafts {
+    pub fn new(section: DraftsSection) -> anyhow::Result<Self> {
+        let previews = match section.preview_secret {
+            Some(secret) if secret.len() < MIN_SECRET_LEN => {
+                bail!("drafts: preview_secret must be at least {MIN_SECRET_LEN} characters")
+            }
+            Some(secret) => Some(Previews {
+                secret,
+                ttl: section.preview_ttl.unwrap_or(DEFAULT_TTL),
+            }),
+            None => None,
+        };
+        Ok(Drafts {
+            editors: section.editors,
+            previews,
+        })
+    }
+
+    pub fn is_editor(&self, user: Option<&str>) -> bool {
+        user.is_some_and(|user| self.editors.iter().any(|editor| editor == user))
+    }
+
+    // A `?preview=` token for `path`, valid from `now` (Unix time) for the
+    // configured lifetime; None when previews are not configured.
+    pub fn token(&self, path: &str, now: u64) -> Option<String> {
+        let previews = self.previews.as_ref()?;
+        let expires = now.saturating_add(previews.ttl);
+        let signature = previews.sign(path, expires).finalize().into_bytes();
+        Some(format!(
+            "{expires}.{}",
+            base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(signature)
+        ))
+    }
+
+    // Whether `token` was issued for `path` and has not expired at `now`.
+    pub fn verify(&self, path: &str, token: &str, now: u64) -> bool {
+        let Some(previews) = &self.previews else {
+            return false;
+        };
+        let Some((expires, signature)) = token.split_once('.') else {
+            return false;
+        };
+        let (Ok(expires), Ok(signature)) = (
+            expires.parse::<u64>(),
+            base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(signature),
+        ) else {
+            return false;
+        };
+        now <= expires
+            && previews
+                .sign(path, expires)
+                .verify_slice(&signature)
+                .is_ok()
+    }
+}
+
+impl Previews {
+    fn sign(&self, path: &str, expires: u64) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(self.secret.as_bytes()).expect("any key length is valid");
+        mac.update(path.as_bytes());
+        mac.update(b"\n");
+        mac.update(expires.to_string().as_bytes());
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drafts(src: &str) -> anyhow::Result<Drafts> {
+        Drafts::new(toml::from_str(src)?)
+    }
+
+    #[test]
+    fn preview_tokens() {
+        let drafts = drafts("preview_secret = \"0123456789abcdef\"\npreview_ttl = 60").unwrap();
+        let token = drafts.token("/post/", 1000).unwrap();
+        assert!(token.starts_with("1060."));
+        assert!(drafts.verify("/post/", &token, 1000));
+        assert!(drafts.verify("/post/", &token, 1060));
+        // Expired, for another page, or tampered with.
+        assert!(!drafts.verify("/post/", &token, 1061));
+        assert!(!drafts.verify("/other/", &token, 1000));
+        let extended = token.replacen("1060", "9999", 1);
+        assert!(!drafts.verify("/post/", &extended, 1000));
+        assert!(!drafts.verify("/post/", "garbage", 1000));
+
+        // Another secret signs differently.
+        let other = drafts_with_secret("fedcba9876543210");
+        assert!(!other.verify("/post/", &token, 1000));
+    }
+
+    fn drafts_with_secret(secret: &str) -> Drafts {
+        drafts(&format!("preview_secret = \"{secret}\"")).unwrap()
+    }
+
+    #[test]
+    fn config() {
+        let none = drafts("editors = [\"ann\"]").unwrap();
+        assert!(none.is_editor(Some("ann")));
+        assert!(!none.is_editor(Some("bob")));
+        assert!(!none.is_editor(None));
+        assert_eq!(none.token("/post/", 0), None);
+        assert!(drafts("preview_secret = \"short\"").is_err());
+        assert!(drafts("editor = []").is_err());
+    }
+}
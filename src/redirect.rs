@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail};
+use axum::http::HeaderValue;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use serde::Deserialize;
+
+// Encoded in sources and targets, as in the paths clients request: what may
+// not appear raw in a URL path, non-ASCII included. `?` and `#` stay, as
+// targets may carry a query or fragment.
+const PATH: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'<')
+    .add(b'>')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+// A `[redirects]` entry: either just the target, or a table with a status.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum RuleSpec {
+    To(String),
+    Full {
+        to: String,
+        #[serde(default = "default_status")]
+        status: u16,
+    },
+}
+
+fn default_status() -> u16 {
+    301
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Target {
+    to: String,
+    status: u16,
+}
+
+// Redirect rules from `_config.toml`, checked before any page lookup.
+// Exact paths win over prefix rules (`/old/*`); among prefixes, the longest.
+#[derive(Debug, Default)]
+pub struct Redirects {
+    exact: HashMap<String, Target>,
+    // (source prefix, target), longest prefix first.
+    prefixes: Vec<(String, Target)>,
+}
+
+impl Redirects {
+    pub fn new(rules: HashMap<String, RuleSpec>) -> anyhow::Result<Self> {
+        let mut redirects = Redirects::default();
+        for (from, spec) in rules {
+            let (to, status) = match spec {
+                RuleSpec::To(to) => (to, default_status()),
+                RuleSpec::Full { to, status } => (to, status),
+            };
+            if !matches!(status, 301 | 302 | 307 | 308) {
+                bail!("redirect `{from}`: status must be 301, 302, 307 or 308, not {status}");
+            }
+            if !from.starts_with('/') || from.strip_suffix('*').unwrap_or(&from).contains('*') {
+                bail!("redirect `{from}`: source must be a path, with `*` only at its end");
+            }
+            if !to.starts_with('/') && !is_external(&to) {
+                bail!("redirect `{from}`: target `{to}` must be a path or an http(s) URL");
+            }
+            if to.contains('*') && (!from.ends_with('*') || !to.ends_with('*')) {
+                bail!("redirect `{from}`: `*` in the target needs a `*` ending both paths");
+            }
+            // It becomes a `Location` header.
+            if HeaderValue::from_str(&to).is_err() {
+                bail!(
+                    "redirect `{from}`: target `{}` has control characters",
+                    to.escape_debug()
+                );
+            }
+            // Request paths arrive percent-encoded (`/caf%C3%A9`).
+            let from = utf8_percent_encode(&from, PATH).to_string();
+            let to = utf8_percent_encode(&to, PATH).to_string();
+            let target = Target { to, status };
+            match from.strip_suffix('*') {
+                Some(prefix) => redirects.prefixes.push((prefix.to_owned(), target)),
+                None => {
+                    redirects.exact.insert(from, target);
+                }
+            }
+        }
+        redirects
+            .prefixes
+            .sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        redirects.check_chains()?;
+        Ok(redirects)
+    }
+
+    // Location and status for `path` (query appended), if a rule matches.
+    pub fn resolve(&self, path: &str, query: Option<&str>) -> Option<(String, u16)> {
+        let (mut location, status) = self.lookup(path)?;
+        if let Some(query) = query.filter(|q| !q.is_empty()) {
+            location.push(if location.contains('?') { '&' } else { '?' });
+            location.push_str(query);
+        }
+        Some((location, status))
+    }
+
+    fn lookup(&self, path: &str) -> Option<(String, u16)> {
+        if let Some(target) = self.exact.get(path) {
+            return Some((target.to.clone(), target.status));
+        }
+        self.prefixes.iter().find_map(|(prefix, target)| {
+            let rest = path.strip_prefix(prefix.as_str())?;
+            let location = match target.to.strip_suffix('*') {
+                Some(to) => format!("{to}{rest}"),
+                None => target.to.clone(),
+            };
+            Some((location, target.status))
+        })
+    }
+
+    // Reject any internal target that another rule (or itself) would
+    // redirect again: chains waste round trips, and loops never end.
+    fn check_chains(&self) -> anyhow::Result<()> {
+        let rules = self
+            .exact
+            .iter()
+            .map(|(from, target)| (from.clone(), target))
+            .chain(
+                self.prefixes
+                    .iter()
+                    .map(|(prefix, target)| (format!("{prefix}*"), target)),
+            );
+        for (from, target) in rules {
+            if is_external(&target.to) {
+                continue;
+            }
+            let to = target.to.split(['?', '#']).next().unwrap_or_default();
+            let conflict = match to.strip_suffix('*') {
+                // Any path under the target prefix may be redirected again.
+                Some(to) => self
+                    .exact
+                    .keys()
+                    .find(|from| from.starts_with(to))
+                    .cloned()
+                    .or_else(|| {
+                        self.prefixes
+                            .iter()
+                            .find(|(prefix, _)| to.starts_with(prefix) || prefix.starts_with(to))
+                            .map(|(prefix, _)| format!("{prefix}*"))
+                    }),
+                None => self
+                    .exact
+                    .get_key_value(to)
+                    .map(|(k, _)| k.clone())
+                    .or_else(|| {
+                        self.prefixes
+                            .iter()
+                            .find(|(prefix, _)| to.starts_with(prefix.as_str()))
+                            .map(|(prefix, _)| format!("{prefix}*"))
+                    }),
+            };
+            if let Some(other) = conflict {
+                return Err(anyhow!(
+                    "redirect `{from}`: target `{}` is itself redirected by `{other}`",
+                    target.to
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_external(url: &str) -> bool {
+    url.starts_with("https://") || url.starts_with("http://")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(src: &str) -> anyhow::Result<Redirects> {
+        #[derive(Deserialize)]
+        struct File {
+            redirects: HashMap<String, RuleSpec>,
+        }
+        let file: File = toml::from_str(src)?;
+        Redirects::new(file.redirects)
+    }
+
+    #[test]
+    fn resolves_exact_and_prefix_rules() {
+        let redirects = parse(
+            r#"
+            [redirects]
+            "/old/" = "/new/"
+            "/docs/*" = { to = "/manual/*", status = 308 }
+            "/docs/legacy/*" = { to = "/archive/", status = 302 }
+            "/chat/" = "https://chat.example.com/"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            redirects.resolve("/old/", None),
+            Some(("/new/".into(), 301))
+        );
+        assert_eq!(redirects.resolve("/old", None), None);
+        assert_eq!(
+            redirects.resolve("/docs/a/b.png", None),
+            Some(("/manual/a/b.png".into(), 308))
+        );
+        // The longest prefix wins.
+        assert_eq!(
+            redirects.resolve("/docs/legacy/x/", None),
+            Some(("/archive/".into(), 302))
+        );
+        assert_eq!(
+            redirects.resolve("/chat/", Some("room=1")),
+            Some(("https://chat.example.com/?room=1".into(), 301))
+        );
+        assert_eq!(redirects.resolve("/other/", None), None);
+    }
+
+    #[test]
+    fn non_ascii_sources() {
+        let redirects = parse(
+            "[redirects]\n\"/é\" = \"/e/\"\n\"/café/*\" = \"/cafe/*\"\n\"/menu/\" = \"/thé/\"",
+        )
+        .unwrap();
+        // As requested: percent-encoded.
+        assert_eq!(
+            redirects.resolve("/%C3%A9", None),
+            Some(("/e/".into(), 301))
+        );
+        assert_eq!(
+            redirects.resolve("/caf%C3%A9/menu/", None),
+            Some(("/cafe/menu/".into(), 301))
+        );
+        assert_eq!(
+            redirects.resolve("/menu/", None),
+            Some(("/th%C3%A9/".into(), 301))
+        );
+        assert!(parse("[redirects]\n\"/*é\" = \"/e/\"").is_err());
+        // The chain through an encoded path is seen.
+        assert!(parse("[redirects]\n\"/a/\" = \"/café/\"\n\"/caf%C3%A9/\" = \"/b/\"").is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        assert!(parse("[redirects]\n\"/a/\" = { to = \"/b/\", status = 200 }").is_err());
+        assert!(parse("[redirects]\n\"a/\" = \"/b/\"").is_err());
+        assert!(parse("[redirects]\n\"/a/*/b\" = \"/b/\"").is_err());
+        assert!(parse("[redirects]\n\"/a/\" = \"ftp://b/\"").is_err());
+        assert!(parse("[redirects]\n\"/a/\" = \"/b/*\"").is_err());
+        // Targets become `Location` headers.
+        assert!(parse("[redirects]\n\"/a/\" = \"/b/\x7f\"").is_err());
+        assert!(parse("[redirects]\n\"/a/\" = \"/b/\\u0001\"").is_err());
+    }
+
+    #[test]
+    fn rejects_chains_and_loops() {
+        // Chain: /a/ -> /b/ -> /c/.
+        assert!(parse("[redirects]\n\"/a/\" = \"/b/\"\n\"/b/\" = \"/c/\"").is_err());
+        // Loop between two rules, and a self-loop.
+        assert!(parse("[redirects]\n\"/a/\" = \"/b/\"\n\"/b/\" = \"/a/\"").is_err());
+        assert!(parse("[redirects]\n\"/a/\" = \"/a/\"").is_err());
+        // A prefix target landing under another prefix rule.
+        assert!(parse("[redirects]\n\"/x/*\" = \"/y/*\"\n\"/y/z/*\" = \"/w/*\"").is_err());
+        // A prefix rewritten into itself.
+        assert!(parse("[redirects]\n\"/x/*\" = \"/x/old/*\"").is_err());
+        // An exact target under a prefix rule.
+        assert!(parse("[redirects]\n\"/a/\" = \"/x/b/\"\n\"/x/*\" = \"/y/*\"").is_err());
+        // Disjoint rules are fine.
+        assert!(parse("[redirects]\n\"/a/\" = \"/b/\"\n\"/x/*\" = \"/y/*\"").is_ok());
+    }
+}
@@ -0,0 +1,284 @@
+use serde_json::{Map, Value as Json};
+
+// The fields of `request`, in the order of `RequestFields` bits.
+const FIELDS: [&str; 6] = ["path", "query", "host", "user", "groups", "languages"];
+
+// Most languages kept from `Accept-Language`.
+const MAX_LANGUAGES: usize = 16;
+
+// Which `request` fields a template may read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RequestFields(u8);
+
+// Past the bits of `FIELDS`: `query` read other than by parameter name.
+const WHOLE_QUERY: u8 = 1 << FIELDS.len();
+
+impl RequestFields {
+    pub const NONE: RequestFields = RequestFields(0);
+    pub const ALL: RequestFields = RequestFields((WHOLE_QUERY << 1) - 1);
+
+    pub fn union(self, other: RequestFields) -> RequestFields {
+        RequestFields(self.0 | other.0)
+    }
+
+    fn contains(self, index: usize) -> bool {
+        self.0 & (1 << index) != 0
+    }
+
+    // Whether responses may be cached by what is read: not when it is the
+    // whole query or `languages`, which clients vary at will.
+    pub fn cacheable(self) -> bool {
+        self.0 & WHOLE_QUERY == 0 && !self.contains(FIELDS.len() - 1)
+    }
+
+    // The fields Handlebars `src` reads: those named as `request.field` (or
+    // `request/field`) inside its tags. Any other mention of `request`, such
+    // as `{{#with request}}`, may read them all.
+    pub fn used_by(src: &str) -> RequestFields {
+        let mut used = RequestFields::NONE;
+        for after in mentions(src) {
+            let field = after.strip_prefix(['.', '/']).map(name);
+            used = used.union(match FIELDS.iter().position(|name| Some(*name) == field) {
+                Some(index) if FIELDS[index] == "query" && query_param(after).is_none() => {
+                    RequestFields((1 << index) | WHOLE_QUERY)
+                }
+                Some(index) => RequestFields(1 << index),
+                None => RequestFields::ALL,
+            });
+            if used == RequestFields::ALL {
+                break;
+            }
+        }
+        used
+    }
+}
+
+// The query parameters Handlebars `src` reads by name (`request.query.tag`),
+// without duplicates.
+pub fn query_params(src: &str) -> Vec<String> {
+    let mut params: Vec<String> = Vec::new();
+    for param in mentions(src).filter_map(query_param) {
+        if !params.iter().any(|known| known == param) {
+            params.push(param.to_owned());
+        }
+    }
+    params
+}
+
+// What follows each mention of `request` inside the tags of `src`.
+fn mentions(src: &str) -> impl Iterator<Item = &str> {
+    let tags = src.split("{{").skip(1).map(|rest| {
+        let end = rest.find("}}").unwrap_or(rest.len());
+        &rest[..end]
+    });
+    tags.flat_map(|tag| {
+        tag.match_indices("request").filter_map(move |(at, _)| {
+            let after = &tag[at + "request".len()..];
+            let standalone = !tag[..at].ends_with(is_name) && !after.starts_with(is_name);
+            standalone.then_some(after)
+        })
+    })
+}
+
+// The parameter named after `request` in `.query.name` (or `/query/name`).
+fn query_param(after: &str) -> Option<&str> {
+    let rest = after.strip_prefix(['.', '/'])?.strip_prefix("query")?;
+    let param = name(rest.strip_prefix(['.', '/'])?);
+    (!param.is_empty()).then_some(param)
+}
+
+fn is_name(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+// The name `src` starts with.
+fn name(src: &str) -> &str {
+    &src[..src.find(|c| !is_name(c)).unwrap_or(src.len())]
+}
+
+// What templates see as `request`.
+pub struct RequestVars {
+    path: String,
+    // Query parameters; the first of repeated ones.
+    query: Map<String, Json>,
+    host: Option<String>,
+    // The authenticated user, and the groups (from `[groups]`) they are in.
+    user: Option<String>,
+    groups: Vec<String>,
+    // From `Accept-Language`, most preferred first.
+    languages: Vec<String>,
+}
+
+impl RequestVars {
+    pub fn new(
+        path: &str,
+        query: Option<&str>,
+        host: Option<&str>,
+        user: Option<String>,
+        groups: Vec<String>,
+        accept_language: Option<&str>,
+    ) -> Self {
+        let mut params = Map::new();
+        for (key, value) in form_urlencoded::parse(query.unwrap_or_default().as_bytes()) {
+            params
+                .entry(key.into_owned())
+                .or_insert_with(|| Json::String(value.into_owned()));
+        }
+        RequestVars {
+            path: path.to_owned(),
+            query: params,
+            host: host.map(str::to_ascii_lowercase),
+            user,
+            groups,
+            languages: accept_language.map(languages).unwrap_or_default(),
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    // What a response rendered with `fields` varies with, as JSON: of
+    // `query`, only the `params` read by name. None if nothing is read.
+    pub fn cache_key(&self, fields: RequestFields, params: &[String]) -> Option<String> {
+        let mut vars = self.to_json(fields);
+        if let Some(Json::Object(query)) = vars.get_mut("query") {
+            query.retain(|name, _| params.contains(name));
+        }
+        (!vars.is_empty()).then(|| Json::Object(vars).to_string())
+    }
+
+    // The `fields` of `request`, the others left out.
+    pub fn to_json(&self, fields: RequestFields) -> Map<String, Json> {
+        let mut vars = Map::new();
+        for (index, name) in FIELDS.into_iter().enumerate() {
+            if !fields.contains(index) {
+                continue;
+            }
+            let value = match name {
+                "path" => Json::from(self.path.as_str()),
+                "query" => Json::Object(self.query.clone()),
+                "host" => Json::from(self.host.clone()),
+                "user" => Json::from(self.user.clone()),
+                "groups" => Json::from(self.groups.clone()),
+                _ => Json::from(self.languages.clone()),
+            };
+            vars.insert(name.into(), value);
+        }
+        vars
+    }
+}
+
+// The language tags of an `Accept-Language` header, by decreasing quality
+// (in header order for equal ones), without `*` and refused (`q=0`) ones.
+fn languages(header: &str) -> Vec<String> {
+    let mut weighted: Vec<(u16, &str)> = header
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let tag = parts.next()?.trim();
+            let valid = !tag.is_empty()
+                && tag.len() <= 35
+                && tag.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-');
+            if !valid {
+                return None;
+            }
+            // Quality in thousandths; malformed weights count as 1.
+            let quality = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .map_or(1000, |q| (q.clamp(0.0, 1.0) * 1000.0) as u16);
+            (quality > 0).then_some((quality, tag))
+        })
+        .collect();
+    weighted.sort_by_key(|(quality, _)| std::cmp::Reverse(*quality));
+    weighted
+        .into_iter()
+        .take(MAX_LANGUAGES)
+        .map(|(_, tag)| tag.to_owned())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_used() {
+        let used = |src| RequestFields::used_by(src);
+        assert_eq!(used("<p>Send a request</p>{{title}}"), RequestFields::NONE);
+        assert_eq!(used("{{page.requests}}{{my_request}}"), RequestFields::NONE);
+        let path = RequestFields(1);
+        let user = RequestFields(1 << 3);
+        assert_eq!(used("{{#if (eq request.path url)}}"), path);
+        assert_eq!(
+            used("{{request/path}} {{#if request.user}}Hi {{@root.request.user}}{{/if}}"),
+            path.union(user)
+        );
+        assert_eq!(
+            used("{{#with request}}{{path}}{{/with}}"),
+            RequestFields::ALL
+        );
+        assert_eq!(used("{{lookup request \"user\"}}"), RequestFields::ALL);
+        assert_eq!(used("{{request.[path]}}"), RequestFields::ALL);
+
+        // Clients vary the whole query and their languages at will.
+        let tag = used("{{request.query.tag}} {{request/query/page}}");
+        assert_eq!(tag, RequestFields(1 << 1));
+        assert!(tag.cacheable());
+        assert!(!used("{{#each request.query}}{{/each}}").cacheable());
+        assert!(!used("{{request.languages.[0]}}").cacheable());
+        assert!(!RequestFields::ALL.cacheable());
+        assert_eq!(
+            query_params("{{request.query.tag}}{{#if request/query/page}}{{request.query.tag}}"),
+            ["tag", "page"]
+        );
+        assert!(query_params("{{request.query}}{{request.querying.x}}").is_empty());
+    }
+
+    #[test]
+    fn variables() {
+        let vars = RequestVars::new(
+            "/blog/",
+            Some("tag=rust&page=2&tag=web&q=a%20b"),
+            Some("Example.COM:8080"),
+            Some("ann".into()),
+            vec!["staff".into()],
+            Some("fr-CH, fr;q=0.9, en;q=0.8, *;q=0.5, de;q=0, x y"),
+        );
+        assert_eq!(
+            Json::Object(vars.to_json(RequestFields::ALL)),
+            serde_json::json!({
+                "path": "/blog/",
+                "query": { "tag": "rust", "page": "2", "q": "a b" },
+                "host": "example.com:8080",
+                "user": "ann",
+                "groups": ["staff"],
+                "languages": ["fr-CH", "fr", "en"],
+            })
+        );
+        let path = RequestFields(1);
+        assert_eq!(
+            Json::Object(vars.to_json(path)),
+            serde_json::json!({ "path": "/blog/" })
+        );
+        assert!(vars.to_json(RequestFields::NONE).is_empty());
+
+        // Responses vary with the parameters read by name only.
+        let query = RequestFields::used_by("{{request.query.tag}}");
+        assert_eq!(
+            vars.cache_key(query.union(path), &["tag".into()])
+                .as_deref(),
+            Some(r#"{"path":"/blog/","query":{"tag":"rust"}}"#)
+        );
+        assert_eq!(vars.cache_key(RequestFields::NONE, &[]), None);
+    }
+
+    #[test]
+    fn accept_language() {
+        assert_eq!(languages("en;q=0.5, de, fr;q=0.8"), ["de", "fr", "en"]);
+        assert_eq!(languages("en;q=oops, de;q=0.9"), ["en", "de"]);
+        assert!(languages("").is_empty());
+        assert!(languages("*").is_empty());
+    }
+}
@@ -1,14 +1,23 @@
 use anyhow::anyhow;
 use rsass::{compile_scss, output::Format};
+use twox_hash::XxHash3_128;
 
 use crate::cache::Cacheable;
 
 #[derive(Clone, Default)]
-pub struct Stylesheet(String);
+pub struct Stylesheet {
+    css: String,
+    hash: u128,
+}
 
 impl Stylesheet {
     pub fn css(&self) -> &str {
-        &self.0
+        &self.css
+    }
+
+    // Hash of the compiled CSS, used to fingerprint its URL.
+    pub fn hash(&self) -> u128 {
+        self.hash
     }
 }
 
@@ -17,6 +26,7 @@ impl Cacheable for Stylesheet {
         let css = compile_scss(src.as_bytes(), Format::default())
             .map_err(|e| anyhow!("invalid scss: {e}"))?;
         let css = String::from_utf8(css).map_err(|e| anyhow!("invalid utf8 in css: {e}"))?;
-        Ok(Stylesheet(css))
+        let hash = XxHash3_128::oneshot(css.as_bytes());
+        Ok(Stylesheet { css, hash })
     }
 }
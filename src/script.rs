@@ -0,0 +1,291 @@
+use std::sync::{Arc, OnceLock};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use handlebars::{
+    Context, Handlebars, Helper, HelperDef, RenderContext, RenderError, RenderErrorReason,
+    ScopedJson,
+};
+use rhai::{
+    serde::{from_dynamic, to_dynamic},
+    Dynamic, Engine, Scope, AST,
+};
+use serde_json::{Map, Value as Json};
+use tracing::{debug, error, warn};
+
+use crate::{
+    cache::{CacheMap, Cacheable, Snapshot},
+    helpers,
+    web::valid_asset_name,
+};
+
+// Limits keeping a script from hanging or exhausting a render thread.
+const MAX_OPERATIONS: u64 = 1_000_000;
+const MAX_CALL_LEVELS: usize = 32;
+const MAX_EXPR_DEPTH: usize = 64;
+const MAX_STRING_SIZE: usize = 1 << 20;
+const MAX_COLLECTION_SIZE: usize = 10_000;
+
+// A site's helper scripts, by helper name, sorted.
+pub type Scripts = Arc<[(String, Arc<Script>)]>;
+
+// The engine running every helper script: the standard library only (no
+// modules, no `eval`), within the limits above.
+fn engine() -> &'static Engine {
+    static ENGINE: OnceLock<Engine> = OnceLock::new();
+    ENGINE.get_or_init(|| {
+        let mut engine = Engine::new();
+        engine
+            .set_max_operations(MAX_OPERATIONS)
+            .set_max_call_levels(MAX_CALL_LEVELS)
+            .set_max_expr_depths(MAX_EXPR_DEPTH, MAX_EXPR_DEPTH)
+            .set_max_string_size(MAX_STRING_SIZE)
+            .set_max_array_size(MAX_COLLECTION_SIZE)
+            .set_max_map_size(MAX_COLLECTION_SIZE)
+            .set_max_modules(0)
+            .disable_symbol("eval")
+            .on_print(|text| debug!("helper script: {text}"))
+            .on_debug(|text, _, pos| debug!("helper script ({pos}): {text}"));
+        engine
+    })
+}
+
+// A `_style/helpers/<name>.rhai` script, compiled once per file change.
+#[derive(Default)]
+pub struct Script {
+    ast: AST,
+}
+
+impl Cacheable for Script {
+    fn compute(src: &str) -> anyhow::Result<Self> {
+        Ok(Script {
+            ast: engine().compile(src)?,
+        })
+    }
+}
+
+// `{{name a b key=c}}` runs the script with `params` (`[a, b]`) and `hash`
+// (`#{key: c}`) in scope; its last value is the helper's.
+pub struct ScriptHelper {
+    name: String,
+    script: Arc<Script>,
+}
+
+impl ScriptHelper {
+    pub fn new(name: &str, script: Arc<Script>) -> Self {
+        ScriptHelper {
+            name: name.to_owned(),
+            script,
+        }
+    }
+
+    fn call(&self, h: &Helper<'_>) -> Result<Json, String> {
+        let params: Vec<&Json> = h.params().iter().map(|param| param.value()).collect();
+        let hash: Map<String, Json> = h
+            .hash()
+            .iter()
+            .map(|(key, value)| ((*key).to_owned(), value.value().clone()))
+            .collect();
+        let mut scope = Scope::new();
+        scope.push_dynamic("params", to_dynamic(params).map_err(|err| err.to_string())?);
+        scope.push_dynamic("hash", to_dynamic(hash).map_err(|err| err.to_string())?);
+        let result = engine()
+            .eval_ast_with_scope::<Dynamic>(&mut scope, &self.script.ast)
+            .map_err(|err| err.to_string())?;
+        from_dynamic(&result).map_err(|err| err.to_string())
+    }
+}
+
+impl HelperDef for ScriptHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        // Rhai errors carry their line in the script.
+        self.call(h).map(ScopedJson::Derived).map_err(|err| {
+            RenderErrorReason::Other(format!("helper script `{}`: {err}", self.name)).into()
+        })
+    }
+}
+
+// The helper scripts of an app. The directory is rescanned in the background
+// (see `Snapshot`); scripts come from (and stay fresh through) their cache.
+// The list is replaced, never modified, when a script changes.
+#[derive(Default)]
+pub struct SiteScripts {
+    files: Arc<CacheMap<Arc<Script>>>,
+    scan: Snapshot<Scripts>,
+}
+
+impl SiteScripts {
+    pub async fn load(&self, root: &Utf8Path) -> Scripts {
+        let dir = root.join("_style/helpers");
+        let files = self.files.clone();
+        self.scan
+            .load(|previous| async move { rescan(&files, dir, previous).await })
+            .await
+    }
+
+    pub fn sweep(&self, ttl: std::time::Duration) {
+        self.files.sweep(ttl);
+        self.scan.sweep(ttl);
+    }
+}
+
+async fn rescan(
+    files: &CacheMap<Arc<Script>>,
+    dir: Utf8PathBuf,
+    previous: Option<Scripts>,
+) -> Scripts {
+    let walk_dir = dir.clone();
+    let found = tokio::task::spawn_blocking(move || find_scripts(&walk_dir))
+        .await
+        .unwrap_or_else(|err| {
+            error!("helper script scan failed: {err}");
+            Vec::new()
+        });
+    let mut scripts = Vec::with_capacity(found.len());
+    for name in found {
+        // Invalid scripts are logged (with their path) by the cache and left
+        // out.
+        if let Ok(script) = files.load(&dir.join(format!("{name}.rhai"))).await {
+            scripts.push((name, script));
+        }
+    }
+    match previous {
+        Some(previous)
+            if previous.len() == scripts.len()
+                && previous
+                    .iter()
+                    .zip(&scripts)
+                    .all(|(a, b)| a.0 == b.0 && Arc::ptr_eq(&a.1, &b.1)) =>
+        {
+            previous
+        }
+        _ => scripts.into(),
+    }
+}
+
+// Names of the `.rhai` files in `dir` that can be helper names, sorted.
+fn find_scripts(dir: &Utf8Path) -> Vec<String> {
+    let Ok(entries) = dir.read_dir_utf8() else {
+        return Vec::new();
+    };
+    let mut names = Vec::new();
+    for entry in entries.flatten() {
+        let path = Utf8PathBuf::from(entry.file_name());
+        if path.extension() != Some("rhai") {
+            continue;
+        }
+        match path.file_stem() {
+            Some(name) if helpers::reserved(name) => {
+                error!(
+                    "`{}` would replace a built-in helper, ignoring it",
+                    entry.path()
+                )
+            }
+            Some(name) if valid_asset_name(name) => names.push(name.to_owned()),
+            _ => warn!("invalid helper script name `{}`", entry.path()),
+        }
+    }
+    names.sort();
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn render(script: &str, template: &str) -> Result<String, RenderError> {
+        let script = Arc::new(Script::compute(script).unwrap());
+        let mut hbs = Handlebars::new();
+        hbs.register_helper("fmt", Box::new(ScriptHelper::new("fmt", script)));
+        hbs.render_template(
+            template,
+            &serde_json::json!({ "price": 1250, "tags": ["a"] }),
+        )
+    }
+
+    #[test]
+    fn helpers() {
+        let script = "let cents = params[0];\n\
+                      `${hash.currency ?? \"$\"}${cents / 100}.${cents % 100}`";
+        assert_eq!(render(script, "{{fmt price}}").unwrap(), "$12.50");
+        assert_eq!(
+            render(script, "{{fmt price currency=\"<€>\"}}").unwrap(),
+            "&lt;€&gt;12.50"
+        );
+        assert_eq!(
+            render("params[0].len()", "{{#if (fmt tags)}}yes{{/if}}").unwrap(),
+            "yes"
+        );
+        assert_eq!(
+            render("#{a: 1}", "{{#each (fmt)}}{{@key}}={{this}}{{/each}}").unwrap(),
+            "a=1"
+        );
+    }
+
+    #[test]
+    fn errors() {
+        // Syntax errors, when compiled, with their line.
+        let err = Script::compute("let x = 1;\nlet = 2;").err().unwrap();
+        assert!(err.to_string().contains("line 2"), "{err}");
+
+        // Runtime errors name the helper and the line.
+        let err = render("let x = 1;\nx.missing()", "{{fmt}}").unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("helper script `fmt`"), "{message}");
+        assert!(message.contains("line 2"), "{message}");
+
+        // Runaway scripts are stopped.
+        assert!(render("loop {}", "{{fmt}}").is_err());
+        assert!(render("fn f(x) { f(x) }\nf(1)", "{{fmt}}").is_err());
+        assert!(render("let s = \"x\"; loop { s += s; }", "{{fmt}}").is_err());
+        assert!(Script::compute("eval(\"1\")").is_err());
+        assert!(Script::compute("import \"x\" as x;").is_ok());
+        assert!(render("import \"x\" as x; 1", "{{fmt}}").is_err());
+    }
+
+    #[tokio::test]
+    async fn site_scripts() {
+        let root = Utf8PathBuf::from_path_buf(
+            std::env::temp_dir().join(format!("flaty-scripts-{}", std::process::id())),
+        )
+        .unwrap();
+        let dir = root.join("_style/helpers");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("upper.rhai"), "params[0].to_upper()").unwrap();
+        std::fs::write(dir.join("broken.rhai"), "let = ;").unwrap();
+        std::fs::write(dir.join("notes.txt"), "").unwrap();
+        std::fs::write(dir.join("bad name.rhai"), "1").unwrap();
+        std::fs::write(dir.join("if.rhai"), "1").unwrap();
+        std::fs::write(dir.join("date.rhai"), "1").unwrap();
+        std::fs::write(dir.join("asset.rhai"), "1").unwrap();
+
+        let scripts = SiteScripts::default();
+        let loaded = scripts.load(&root).await;
+        let names: Vec<_> = loaded.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["upper"]);
+
+        // Unchanged scripts keep the same list; a change replaces it.
+        scripts.scan.expire();
+        scripts.load(&root).await;
+        scripts.scan.walked().await;
+        assert!(Arc::ptr_eq(&loaded, &scripts.load(&root).await));
+        std::fs::write(dir.join("broken.rhai"), "1").unwrap();
+        scripts.sweep(Duration::ZERO);
+        let names: Vec<_> = scripts
+            .load(&root)
+            .await
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect();
+        assert_eq!(names, ["broken", "upper"]);
+
+        std::fs::remove_dir_all(&root).ok();
+    }
+}
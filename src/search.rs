@@ -0,0 +1,264 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::Serialize;
+
+use crate::{
+    markdown::{plain_text, Block, Document},
+    site::SitePage,
+};
+
+// A title word counts as much as this many occurrences in the body.
+const TITLE_WEIGHT: u32 = 5;
+const MAX_HITS: usize = 20;
+// Excerpt length, and how much of it comes before the first match.
+const EXCERPT_CHARS: usize = 160;
+const EXCERPT_LEAD: usize = 40;
+
+struct Entry {
+    url: String,
+    title: String,
+    text: String,
+}
+
+// Inverted index over the text of every page of a `Site`.
+#[derive(Default)]
+pub struct SearchIndex {
+    entries: Vec<Entry>,
+    // Term -> (entry, weighted occurrences).
+    postings: HashMap<String, Vec<(usize, u32)>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Hit {
+    pub url: String,
+    pub title: String,
+    // HTML: escaped text with matches in `<mark>`.
+    pub excerpt: String,
+}
+
+impl SearchIndex {
+    pub fn new(pages: &[SitePage]) -> Self {
+        let mut index = SearchIndex::default();
+        for entry in pages {
+            let page = &entry.page;
+            // Redirects and error-status pages have nothing worth finding;
+            // drafts must not be found.
+            if page.draft()
+                || page.redirect().is_some()
+                || !matches!(page.status(), Ok(None | Some(..300)))
+            {
+                continue;
+            }
+            let title = page
+                .fields()
+                .get("title")
+                .and_then(|title| title.as_str())
+                .unwrap_or_default()
+                .to_owned();
+            let mut text = String::new();
+            document_text(page.body(), &mut text);
+
+            let mut counts: HashMap<String, u32> = HashMap::new();
+            for (_, word) in words(&title) {
+                *counts.entry(word.to_lowercase()).or_default() += TITLE_WEIGHT;
+            }
+            for (_, word) in words(&text) {
+                *counts.entry(word.to_lowercase()).or_default() += 1;
+            }
+            let id = index.entries.len();
+            for (term, count) in counts {
+                index.postings.entry(term).or_default().push((id, count));
+            }
+            index.entries.push(Entry {
+                url: entry.url.clone(),
+                title,
+                text: collapse_whitespace(&text),
+            });
+        }
+        index
+    }
+
+    // Pages containing every word of `query`, best first, among those
+    // `visible` accepts (by URL).
+    pub fn search(&self, query: &str, visible: impl Fn(&str) -> bool) -> Vec<Hit> {
+        let terms: HashSet<String> = words(query).map(|(_, w)| w.to_lowercase()).collect();
+        if terms.is_empty() {
+            return Vec::new();
+        }
+        let total = self.entries.len() as f64;
+        let mut scores: HashMap<usize, (usize, f64)> = HashMap::new();
+        for term in &terms {
+            let Some(postings) = self.postings.get(term) else {
+                return Vec::new();
+            };
+            // Rare words say more about a page than common ones.
+            let idf = (1.0 + total / postings.len() as f64).ln();
+            for &(id, count) in postings {
+                let score = scores.entry(id).or_default();
+                score.0 += 1;
+                score.1 += (1.0 + f64::from(count).ln()) * idf;
+            }
+        }
+        let mut ranked: Vec<(usize, f64)> = scores
+            .into_iter()
+            .filter(|(_, (matched, _))| *matched == terms.len())
+            .map(|(id, (_, score))| (id, score))
+            .collect();
+        ranked.sort_by(|a, b| {
+            b.1.total_cmp(&a.1)
+                .then_with(|| self.entries[a.0].url.cmp(&self.entries[b.0].url))
+        });
+        ranked
+            .into_iter()
+            .map(|(id, _)| &self.entries[id])
+            .filter(|entry| visible(&entry.url))
+            .take(MAX_HITS)
+            .map(|entry| Hit {
+                url: entry.url.clone(),
+                title: entry.title.clone(),
+                excerpt: excerpt(&entry.text, &terms),
+            })
+            .collect()
+    }
+}
+
+// Text of the Markdown blocks, including those nested in snippets, and of
+// the snippets' string parameters.
+fn document_text(document: &Document, text: &mut String) {
+    for block in document.blocks() {
+        match block {
+            Block::Markdown(src) => text.push_str(&plain_text(src)),
+            Block::Snippet { params, body, .. } => {
+                for value in params.values().filter_map(|v| v.as_str()) {
+                    text.push_str(value);
+                    text.push(' ');
+                }
+                document_text(body, text);
+            }
+        }
+        text.push(' ');
+    }
+}
+
+// Words of `text` with their byte offsets.
+fn words(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(move |word| (word.as_ptr() as usize - text.as_ptr() as usize, word))
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// A window of `text` around the first match, HTML-escaped, matches marked.
+fn excerpt(text: &str, terms: &HashSet<String>) -> String {
+    let first = words(text)
+        .find(|(_, word)| terms.contains(&word.to_lowercase()))
+        .map_or(0, |(at, _)| at);
+    // Start a little before the match, on a word boundary.
+    let mut start = text[..first]
+        .char_indices()
+        .rev()
+        .nth(EXCERPT_LEAD)
+        .map_or(0, |(i, _)| i);
+    if start > 0 {
+        start = text[start..]
+            .find(' ')
+            .map_or(first, |i| start + i + 1)
+            .min(first);
+    }
+    let mut end = text[start..]
+        .char_indices()
+        .nth(EXCERPT_CHARS)
+        .map_or(text.len(), |(i, _)| start + i);
+    if end < text.len() {
+        end = text[start..end].rfind(' ').map_or(end, |i| start + i);
+    }
+
+    let window = &text[start..end];
+    let mut html = String::new();
+    if start > 0 {
+        html.push('…');
+    }
+    let mut done = 0;
+    for (at, word) in words(window) {
+        if terms.contains(&word.to_lowercase()) {
+            html.push_str(&handlebars::html_escape(&window[done..at]));
+            html.push_str("<mark>");
+            html.push_str(&handlebars::html_escape(word));
+            html.push_str("</mark>");
+            done = at + word.len();
+        }
+    }
+    html.push_str(&handlebars::html_escape(&window[done..]));
+    if end < text.len() {
+        html.push('…');
+    }
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{cache::Cacheable, markdown::Page};
+
+    fn index(pages: &[(&str, &str)]) -> SearchIndex {
+        let pages: Vec<SitePage> = pages
+            .iter()
+            .map(|(url, src)| SitePage {
+                url: (*url).to_owned(),
+                page: Arc::new(Page::compute(src).unwrap()),
+                modified: None,
+            })
+            .collect();
+        SearchIndex::new(&pages)
+    }
+
+    #[test]
+    fn ranks_titles_above_bodies() {
+        let index = index(&[
+            (
+                "/a/",
+                "---\ntitle = \"Cooking\"\n---\nAll about *rust* removal.",
+            ),
+            ("/b/", "---\ntitle = \"Rust\"\n---\nA programming language."),
+            ("/c/", "---\ntitle = \"Gone\"\nstatus = 410\n---\nRust."),
+            (
+                "/d/",
+                "---\ntitle = \"Moved\"\nredirect = \"/b/\"\n---\nRust.",
+            ),
+            ("/e/", "---\ntitle = \"Rust\"\ndraft = true\n---\n"),
+        ]);
+        let urls = |hits: Vec<Hit>| hits.into_iter().map(|h| h.url).collect::<Vec<_>>();
+        assert_eq!(urls(index.search("RUST", |_| true)), ["/b/", "/a/"]);
+        // Every word must match.
+        assert_eq!(urls(index.search("rust removal", |_| true)), ["/a/"]);
+        assert_eq!(
+            urls(index.search("rust cake", |_| true)),
+            Vec::<String>::new()
+        );
+        assert_eq!(urls(index.search("rust", |url| url != "/b/")), ["/a/"]);
+        assert!(index.search("  ", |_| true).is_empty());
+    }
+
+    #[test]
+    fn highlighted_excerpts() {
+        let long = "word ".repeat(60);
+        let src = format!("{long}<b>needle</b> & *Needle* {long}");
+        let index = index(&[("/", &src)]);
+        let hits = index.search("needle", |_| true);
+        let excerpt = &hits[0].excerpt;
+        assert!(excerpt.starts_with("…word"), "{excerpt}");
+        assert!(excerpt.ends_with("word…"), "{excerpt}");
+        // Inline HTML is dropped; text is escaped.
+        assert!(
+            excerpt.contains("<mark>needle</mark> &amp; <mark>Needle</mark>"),
+            "{excerpt}"
+        );
+        let text = excerpt.replace("<mark>", "").replace("</mark>", "");
+        assert!(text.chars().count() <= EXCERPT_CHARS + 2, "{text}");
+    }
+}
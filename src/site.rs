@@ -0,0 +1,380 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, OnceLock},
+    time::SystemTime,
+};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::Serialize;
+use tracing::{error, warn};
+
+use crate::{cache::Snapshot, defaults::SitePages, markdown::Page, search::SearchIndex};
+
+// Deeper directories are not searched for pages (guards against symlink loops).
+const MAX_DEPTH: usize = 32;
+
+// A `page.md` found while walking the site, with the URL it is served at.
+pub struct SitePage {
+    pub url: String,
+    pub page: Arc<Page>,
+    pub modified: Option<SystemTime>,
+}
+
+// A link to a page, for menus.
+#[derive(Debug, Serialize)]
+pub struct NavLink {
+    pub url: String,
+    pub title: String,
+}
+
+// Where a page sits in the site tree.
+#[derive(Debug, Default)]
+pub struct Nav {
+    // From the root down to the parent; missing levels are skipped.
+    pub breadcrumbs: Vec<NavLink>,
+    pub children: Vec<NavLink>,
+    // Other pages under the same parent.
+    pub siblings: Vec<NavLink>,
+}
+
+pub enum AliasTarget {
+    Page(String),
+    // Claimed by several pages; the paths of the claimants are logged.
+    Conflict,
+}
+
+// A snapshot of every page of a site and the indexes derived from them.
+// Replaced as a whole when any page is added, removed or changed.
+#[derive(Default)]
+pub struct Site {
+    // Sorted by URL.
+    pages: Vec<SitePage>,
+    aliases: HashMap<String, AliasTarget>,
+    // Built on first use.
+    search: OnceLock<SearchIndex>,
+}
+
+impl Site {
+    pub fn new(pages: Vec<SitePage>) -> Self {
+        let mut claims: HashMap<String, Vec<&str>> = HashMap::new();
+        // A draft's aliases would reveal it.
+        for entry in pages.iter().filter(|entry| !entry.page.draft()) {
+            for alias in entry.page.aliases() {
+                if alias != entry.url {
+                    claims.entry(alias.to_owned()).or_default().push(&entry.url);
+                }
+            }
+        }
+        let aliases = claims
+            .into_iter()
+            .map(|(alias, urls)| {
+                let target = match urls.as_slice() {
+                    [url] => AliasTarget::Page((*url).to_owned()),
+                    _ => {
+                        error!("alias `{alias}` is claimed by several pages: {urls:?}");
+                        AliasTarget::Conflict
+                    }
+                };
+                (alias, target)
+            })
+            .collect();
+        Site {
+            pages,
+            aliases,
+            search: OnceLock::new(),
+        }
+    }
+
+    // The full-text index of the pages (slow the first time: call off the
+    // runtime).
+    pub fn search(&self) -> &SearchIndex {
+        self.search.get_or_init(|| SearchIndex::new(&self.pages))
+    }
+
+    // The page that lists `path` (or `path/`) among its aliases.
+    pub fn alias(&self, path: &str) -> Option<&AliasTarget> {
+        self.aliases
+            .get(path)
+            .or_else(|| self.aliases.get(&format!("{path}/")))
+    }
+
+    // Navigation around the page at `url` (which ends with `/`), among the
+    // pages `include` accepts (published ones).
+    pub fn nav(&self, url: &str, include: impl Fn(&SitePage) -> bool) -> Nav {
+        let mut breadcrumbs = Vec::new();
+        let mut ancestor = parent_url(url);
+        while let Some(parent) = ancestor {
+            if let Some(page) = self.find(parent).filter(|page| include(page)) {
+                breadcrumbs.push(NavLink::from(page));
+            }
+            ancestor = parent_url(parent);
+        }
+        breadcrumbs.reverse();
+        let siblings = match parent_url(url) {
+            Some(parent) => self.children(parent, Some(url), &include),
+            None => Vec::new(),
+        };
+        Nav {
+            breadcrumbs,
+            children: self.children(url, None, &include),
+            siblings,
+        }
+    }
+
+    // The page at `url`, if any.
+    pub fn find(&self, url: &str) -> Option<&SitePage> {
+        self.pages
+            .binary_search_by(|page| page.url.as_str().cmp(url))
+            .ok()
+            .map(|i| &self.pages[i])
+    }
+
+    // Every page, sorted by URL.
+    pub fn pages(&self) -> &[SitePage] {
+        &self.pages
+    }
+
+    // Pages one level below `url`, sorted by URL.
+    pub fn child_pages(&self, url: &str) -> Vec<&SitePage> {
+        // Pages are sorted by URL, so those under `url` are contiguous.
+        let start = self.pages.partition_point(|page| page.url.as_str() <= url);
+        self.pages[start..]
+            .iter()
+            .take_while(|page| page.url.starts_with(url))
+            .filter(|page| parent_url(&page.url) == Some(url))
+            .collect()
+    }
+
+    // Pages one level below `url` that `include` accepts, in menu order,
+    // except `skip`.
+    fn children(
+        &self,
+        url: &str,
+        skip: Option<&str>,
+        include: &impl Fn(&SitePage) -> bool,
+    ) -> Vec<NavLink> {
+        let mut children: Vec<&SitePage> = self
+            .child_pages(url)
+            .into_iter()
+            .filter(|page| Some(page.url.as_str()) != skip && include(page))
+            .collect();
+        children.sort_by(|a, b| {
+            weight(a)
+                .cmp(&weight(b))
+                .then_with(|| nav_title(a).cmp(nav_title(b)))
+                .then_with(|| a.url.cmp(&b.url))
+        });
+        children.into_iter().map(NavLink::from).collect()
+    }
+
+    fn same_pages(&self, pages: &[SitePage]) -> bool {
+        self.pages.len() == pages.len()
+            && self.pages.iter().zip(pages).all(|(a, b)| {
+                a.url == b.url && Arc::ptr_eq(&a.page, &b.page) && a.modified == b.modified
+            })
+    }
+}
+
+// `/a/b/` -> `/a/`; None for the root.
+pub fn parent_url(url: &str) -> Option<&str> {
+    let trimmed = url.strip_suffix('/').unwrap_or(url);
+    trimmed.rfind('/').map(|i| &url[..=i])
+}
+
+// Menu order: `weight` (default 0, lighter first), then title.
+fn weight(page: &SitePage) -> i64 {
+    page.page
+        .fields()
+        .get("weight")
+        .and_then(|weight| weight.as_i64())
+        .unwrap_or(0)
+}
+
+// `nav_title`, else `title`, else the directory name ("Home" for the root).
+fn nav_title(page: &SitePage) -> &str {
+    let fields = page.page.fields();
+    fields
+        .get("nav_title")
+        .or_else(|| fields.get("title"))
+        .and_then(|title| title.as_str())
+        .unwrap_or_else(|| match page.url.trim_end_matches('/').rsplit('/').next() {
+            Some("") | None => "Home",
+            Some(name) => name,
+        })
+}
+
+impl From<&SitePage> for NavLink {
+    fn from(page: &SitePage) -> Self {
+        NavLink {
+            url: page.url.clone(),
+            title: nav_title(page).to_owned(),
+        }
+    }
+}
+
+// The current `Site` of an app, rescanned in the background (see
+// `Snapshot`). Pages themselves come from (and stay fresh through) the page
+// cache, with their defaults.
+#[derive(Default)]
+pub struct SiteIndex(Snapshot<Arc<Site>>);
+
+impl SiteIndex {
+    pub async fn load(&self, root: &Utf8Path, cache: &Arc<SitePages>) -> Arc<Site> {
+        let root = root.to_owned();
+        let cache = cache.clone();
+        self.0
+            .load(|previous| async move { rescan(&root, &cache, previous).await })
+            .await
+    }
+
+    // Forget the snapshot when it has not been used within `ttl`.
+    pub fn sweep(&self, ttl: std::time::Duration) {
+        self.0.sweep(ttl);
+    }
+}
+
+async fn rescan(root: &Utf8Path, cache: &SitePages, previous: Option<Arc<Site>>) -> Arc<Site> {
+    let walk_root = root.to_owned();
+    let files = tokio::task::spawn_blocking(move || find_pages(&walk_root))
+        .await
+        .unwrap_or_else(|err| {
+            error!("site scan failed: {err}");
+            Vec::new()
+        });
+    let mut pages = Vec::with_capacity(files.len());
+    for (url, path, modified) in files {
+        // Invalid pages are logged by the cache and left out.
+        if let Ok(page) = cache.load(root, &path).await {
+            pages.push(SitePage {
+                url,
+                page,
+                modified,
+            });
+        }
+    }
+    match previous {
+        Some(site) if site.same_pages(&pages) => site,
+        _ => Arc::new(Site::new(pages)),
+    }
+}
+
+// (URL, path, modification time) of every `page.md` under `root`, skipping
+// `_`/`.` entries just like URL resolution does. Sorted by URL.
+fn find_pages(root: &Utf8Path) -> Vec<(String, Utf8PathBuf, Option<SystemTime>)> {
+    let mut found = Vec::new();
+    let mut stack = vec![(root.to_owned(), String::from("/"), 0)];
+    while let Some((dir, url, depth)) = stack.pop() {
+        let path = dir.join("page.md");
+        if let Ok(meta) = std::fs::metadata(&path) {
+            if meta.is_file() {
+                found.push((url.clone(), path, meta.modified().ok()));
+            }
+        }
+        if depth >= MAX_DEPTH {
+            warn!("not looking for pages below `{dir}`: too deep");
+            continue;
+        }
+        let Ok(entries) = dir.read_dir_utf8() else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            if name.starts_with(['_', '.']) || !entry.path().is_dir() {
+                continue;
+            }
+            stack.push((entry.path().to_owned(), format!("{url}{name}/"), depth + 1));
+        }
+    }
+    found.sort_by(|a, b| a.0.cmp(&b.0));
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::Cacheable;
+
+    fn site(pages: &[(&str, &str)]) -> Site {
+        Site::new(
+            pages
+                .iter()
+                .map(|(url, src)| SitePage {
+                    url: (*url).to_owned(),
+                    page: Arc::new(Page::compute(src).unwrap()),
+                    modified: None,
+                })
+                .collect(),
+        )
+    }
+
+    fn urls(links: &[NavLink]) -> Vec<&str> {
+        links.iter().map(|link| link.url.as_str()).collect()
+    }
+
+    fn not_draft(page: &SitePage) -> bool {
+        !page.page.draft()
+    }
+
+    #[test]
+    fn navigation() {
+        let site = site(&[
+            ("/", ""),
+            ("/a/", "---\ntitle = \"Zed\"\n---\n"),
+            ("/a/x/", ""),
+            ("/a/x/deep/", ""),
+            ("/a/y/", ""),
+            ("/b/", "---\ntitle = \"Bee\"\nnav_title = \"About\"\n---\n"),
+            ("/c/", "---\nweight = -1\n---\n"),
+            ("/d/e/", ""),
+            ("/f/", "---\ndraft = true\n---\n"),
+            ("/f/g/", ""),
+        ]);
+
+        let nav = site.nav("/", not_draft);
+        assert!(nav.breadcrumbs.is_empty());
+        assert!(nav.siblings.is_empty());
+        // Weight first, then title; `/d/` has no page of its own.
+        assert_eq!(urls(&nav.children), ["/c/", "/b/", "/a/"]);
+        assert_eq!(nav.children[1].title, "About");
+        assert_eq!(nav.children[0].title, "c");
+
+        let nav = site.nav("/a/x/", not_draft);
+        assert_eq!(urls(&nav.breadcrumbs), ["/", "/a/"]);
+        assert_eq!(nav.breadcrumbs[0].title, "Home");
+        assert_eq!(urls(&nav.children), ["/a/x/deep/"]);
+        assert_eq!(urls(&nav.siblings), ["/a/y/"]);
+
+        // Missing levels are skipped.
+        let nav = site.nav("/d/e/", not_draft);
+        assert_eq!(urls(&nav.breadcrumbs), ["/"]);
+        assert!(nav.siblings.is_empty());
+
+        // Excluded pages are left out of menus and breadcrumbs.
+        let nav = site.nav("/f/g/", not_draft);
+        assert_eq!(urls(&nav.breadcrumbs), ["/"]);
+    }
+
+    #[tokio::test]
+    async fn background_rescan() {
+        let root = Utf8PathBuf::from_path_buf(
+            std::env::temp_dir().join(format!("flaty-site-index-{}", std::process::id())),
+        )
+        .unwrap();
+        std::fs::create_dir_all(root.join("a")).unwrap();
+        std::fs::write(root.join("page.md"), "").unwrap();
+        let index = Arc::new(SiteIndex::default());
+        let cache = Arc::new(SitePages::default());
+        assert_eq!(index.load(&root, &cache).await.pages().len(), 1);
+
+        // A recent snapshot is used as is; a stale one too, while the tree
+        // is walked again in the background.
+        std::fs::write(root.join("a/page.md"), "").unwrap();
+        assert_eq!(index.load(&root, &cache).await.pages().len(), 1);
+        index.0.expire();
+        assert_eq!(index.load(&root, &cache).await.pages().len(), 1);
+        index.0.walked().await;
+        assert_eq!(index.load(&root, &cache).await.pages().len(), 2);
+
+        std::fs::remove_dir_all(&root).ok();
+    }
+}
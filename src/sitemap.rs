@@ -0,0 +1,64 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::feed::escape;
+
+// `/sitemap.xml` listing the given page URLs, with their last modification
+// dates, made absolute against `base_url` (no trailing slash).
+pub fn sitemap(base_url: &str, pages: &[(String, Option<DateTime<Utc>>)]) -> String {
+    let mut xml = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n",
+        "<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    ));
+    for (url, lastmod) in pages {
+        xml.push_str("  <url>\n");
+        xml.push_str(&format!(
+            "    <loc>{}</loc>\n",
+            escape(&format!("{base_url}{url}"))
+        ));
+        if let Some(lastmod) = lastmod {
+            xml.push_str(&format!(
+                "    <lastmod>{}</lastmod>\n",
+                lastmod.to_rfc3339_opts(SecondsFormat::Secs, true)
+            ));
+        }
+        xml.push_str("  </url>\n");
+    }
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+// The `/robots.txt` served when the site has none: everything may be
+// crawled, and the sitemap is announced when its absolute URL is known.
+pub fn robots(base_url: Option<&str>) -> String {
+    let mut robots = String::from("User-agent: *\nAllow: /\n");
+    if let Some(base_url) = base_url {
+        robots.push_str(&format!("\nSitemap: {base_url}/sitemap.xml\n"));
+    }
+    robots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sitemap_and_robots() {
+        let xml = sitemap(
+            "https://example.com",
+            &[
+                ("/".into(), None),
+                (
+                    "/a&b/".into(),
+                    Some("2024-03-01T10:00:00Z".parse().unwrap()),
+                ),
+            ],
+        );
+        assert!(xml.contains("<loc>https://example.com/</loc>\n  </url>"));
+        assert!(xml.contains(
+            "<loc>https://example.com/a&amp;b/</loc>\n    <lastmod>2024-03-01T10:00:00Z</lastmod>"
+        ));
+        assert_eq!(robots(None), "User-agent: *\nAllow: /\n");
+        assert!(robots(Some("https://example.com"))
+            .ends_with("\nSitemap: https://example.com/sitemap.xml\n"));
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::bail;
+use serde_json::Value as Json;
+
+use crate::{
+    markdown::Page,
+    site::{Site, SitePage},
+};
+
+// Pages listed under `related`.
+const MAX_RELATED: usize = 5;
+
+// A taxonomy is named after the front-matter field holding its terms
+// (`tags = ["rust", "web"]`); its pages are served under `/{name}/`.
+pub fn check_names(names: &[String]) -> anyhow::Result<()> {
+    for name in names {
+        let valid = !name.is_empty()
+            && !name.starts_with(['_', '-'])
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !valid {
+            bail!("taxonomies: invalid name `{name}`");
+        }
+    }
+    Ok(())
+}
+
+// `Rust Lang` -> `rust-lang`: terms are matched and linked by slug.
+pub fn slug(term: &str) -> String {
+    let mut slug = String::with_capacity(term.len());
+    for c in term.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.truncate(slug.trim_end_matches('-').len());
+    slug
+}
+
+// Terms of `page` in `taxonomy`: a list of strings, or a single one.
+pub fn page_terms<'a>(page: &'a Page, taxonomy: &str) -> Vec<&'a str> {
+    match page.fields().get(taxonomy) {
+        Some(Json::String(term)) => vec![term.as_str()],
+        Some(Json::Array(terms)) => terms.iter().filter_map(Json::as_str).collect(),
+        _ => Vec::new(),
+    }
+}
+
+// `/{taxonomy}/{slug}/`, with non-ASCII slugs percent-encoded.
+pub fn term_url(taxonomy: &str, slug: &str) -> String {
+    let slug = percent_encoding::utf8_percent_encode(slug, percent_encoding::NON_ALPHANUMERIC)
+        .to_string()
+        .replace("%2D", "-");
+    format!("/{taxonomy}/{slug}/")
+}
+
+pub struct Term<'a> {
+    // As first spelled (by URL order of the pages).
+    pub name: &'a str,
+    pub slug: String,
+    // Newest first.
+    pub pages: Vec<&'a SitePage>,
+}
+
+// Every term of `taxonomy` among the pages `include` accepts, by slug.
+pub fn terms<'a>(
+    site: &'a Site,
+    taxonomy: &str,
+    include: impl Fn(&SitePage) -> bool,
+) -> Vec<Term<'a>> {
+    let mut terms: BTreeMap<String, Term<'a>> = BTreeMap::new();
+    for entry in site.pages().iter().filter(|entry| include(entry)) {
+        for name in page_terms(&entry.page, taxonomy) {
+            let slug = slug(name);
+            if slug.is_empty() {
+                continue;
+            }
+            let term = terms.entry(slug.clone()).or_insert_with(|| Term {
+                name,
+                slug,
+                pages: Vec::new(),
+            });
+            if !term.pages.iter().any(|page| page.url == entry.url) {
+                term.pages.push(entry);
+            }
+        }
+    }
+    let mut terms: Vec<Term> = terms.into_values().collect();
+    for term in &mut terms {
+        term.pages.sort_by(|a, b| newest_first(a, b));
+    }
+    terms
+}
+
+// Other pages sharing terms with `page` in any of `taxonomies`, most shared
+// terms first, then newest first.
+pub fn related<'a>(
+    site: &'a Site,
+    taxonomies: &[String],
+    url: &str,
+    page: &Page,
+    include: impl Fn(&SitePage) -> bool,
+) -> Vec<&'a SitePage> {
+    let own: Vec<(&str, String)> = taxonomies
+        .iter()
+        .flat_map(|taxonomy| {
+            page_terms(page, taxonomy)
+                .into_iter()
+                .map(move |term| (taxonomy.as_str(), slug(term)))
+        })
+        .collect();
+    if own.is_empty() {
+        return Vec::new();
+    }
+    let mut shared: HashMap<&str, (usize, &SitePage)> = HashMap::new();
+    for entry in site.pages() {
+        if entry.url == url || !include(entry) {
+            continue;
+        }
+        let count = own
+            .iter()
+            .filter(|(taxonomy, own_slug)| {
+                page_terms(&entry.page, taxonomy)
+                    .into_iter()
+                    .any(|term| slug(term) == *own_slug)
+            })
+            .count();
+        if count > 0 {
+            shared.insert(&entry.url, (count, entry));
+        }
+    }
+    let mut related: Vec<(usize, &SitePage)> = shared.into_values().collect();
+    related.sort_by(|(a_count, a), (b_count, b)| {
+        b_count.cmp(a_count).then_with(|| newest_first(a, b))
+    });
+    related
+        .into_iter()
+        .take(MAX_RELATED)
+        .map(|(_, entry)| entry)
+        .collect()
+}
+
+fn newest_first(a: &SitePage, b: &SitePage) -> std::cmp::Ordering {
+    b.page
+        .date()
+        .cmp(&a.page.date())
+        .then_with(|| a.url.cmp(&b.url))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::cache::Cacheable;
+
+    #[test]
+    fn slugs() {
+        assert_eq!(slug("Rust"), "rust");
+        assert_eq!(slug(" Rust  Lang! "), "rust-lang");
+        assert_eq!(slug("C++"), "c");
+        assert_eq!(slug("Éte"), "éte");
+        assert_eq!(slug("--"), "");
+        assert_eq!(term_url("tags", "rust-lang"), "/tags/rust-lang/");
+        assert_eq!(term_url("tags", "éte"), "/tags/%C3%A9te/");
+    }
+
+    #[test]
+    fn names() {
+        assert!(check_names(&["tags".into(), "categories".into()]).is_ok());
+        assert!(check_names(&["_tags".into()]).is_err());
+        assert!(check_names(&["a/b".into()]).is_err());
+    }
+
+    #[test]
+    fn terms_and_related() {
+        let page = |src: &str| Arc::new(Page::compute(src).unwrap());
+        let entry = |url: &str, src: &str| SitePage {
+            url: url.into(),
+            page: page(src),
+            modified: None,
+        };
+        let a = "---\ntags = [\"Rust\", \"web\"]\ndate = 2024-01-01\n---\n";
+        let site = Site::new(vec![
+            entry("/a/", a),
+            entry("/b/", "---\ntags = [\"rust\"]\ndate = 2024-02-01\n---\n"),
+            entry("/c/", "---\ntags = \"Web\"\ncategory = \"rust\"\n---\n"),
+            entry("/d/", "---\ntags = [\"rust\", \"web\"]\n---\n"),
+        ]);
+
+        let terms = terms(&site, "tags", |entry| entry.url != "/d/");
+        let summary: Vec<(&str, &str, Vec<&str>)> = terms
+            .iter()
+            .map(|term| {
+                let urls = term.pages.iter().map(|page| page.url.as_str()).collect();
+                (term.name, term.slug.as_str(), urls)
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("Rust", "rust", vec!["/b/", "/a/"]),
+                ("web", "web", vec!["/a/", "/c/"])
+            ]
+        );
+
+        let taxonomies = ["tags".to_string(), "category".to_string()];
+        let related = related(&site, &taxonomies, "/a/", &page(a), |_| true);
+        let urls: Vec<&str> = related.iter().map(|page| page.url.as_str()).collect();
+        // /d/ shares two terms; /b/ (newer) and /c/ one each.
+        assert_eq!(urls, ["/d/", "/b/", "/c/"]);
+    }
+}
@@ -0,0 +1,401 @@
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use anyhow::{anyhow, bail};
+use camino::Utf8Path;
+use handlebars::{Handlebars, RenderError};
+use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cache::Cacheable,
+    request::{self, RequestFields},
+    script::{ScriptHelper, Scripts},
+    web::valid_asset_name,
+};
+
+// A page-layout, snippet or partial template: its Handlebars source (with
+// any parent layout applied, see `expand`) compiled once per file change.
+#[derive(Clone, Default)]
+pub struct Template {
+    source: String,
+    compiled: handlebars::Template,
+    // The `request` fields it reads, and the query parameters by name.
+    request: RequestFields,
+    query: Vec<String>,
+    // The partial names it includes.
+    partials: Vec<String>,
+}
+
+impl Template {
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn request_fields(&self) -> RequestFields {
+        self.request
+    }
+
+    pub fn query_params(&self) -> &[String] {
+        &self.query
+    }
+}
+
+impl Cacheable for Template {
+    fn compute(src: &str) -> anyhow::Result<Self> {
+        let source = expand(src)?;
+        let compiled = handlebars::Template::compile(&source)?;
+        let request = RequestFields::used_by(&source);
+        let query = request::query_params(&source);
+        let partials = partial_names(&source)
+            .into_iter()
+            .map(str::to_owned)
+            .collect();
+        Ok(Template {
+            source,
+            compiled,
+            request,
+            query,
+            partials,
+        })
+    }
+}
+
+// A site's compiled templates, registered by name with one Handlebars
+// instance so a render only renders. A name is registered again once its
+// cache entry changes (a new `Arc`); nothing is parsed per request.
+pub struct Registry {
+    state: RwLock<Registered>,
+}
+
+struct Registered {
+    hbs: Handlebars<'static>,
+    templates: HashMap<String, Arc<Template>>,
+    // The scripts last given, and the helpers registered from them.
+    scripts: Option<Scripts>,
+    script_helpers: Vec<String>,
+}
+
+impl Registered {
+    // Whether rendering with `templates` needs nothing registered anew.
+    fn ready(&self, scripts: &Scripts, strict: bool, templates: &[(&str, &Arc<Template>)]) -> bool {
+        self.hbs.strict_mode() == strict
+            && self
+                .scripts
+                .as_ref()
+                .is_some_and(|registered| Arc::ptr_eq(registered, scripts))
+            && templates.iter().all(|(name, template)| {
+                self.templates
+                    .get(*name)
+                    .is_some_and(|registered| Arc::ptr_eq(registered, template))
+            })
+            && included(templates).all(|name| !self.templates.contains_key(name))
+    }
+
+    fn update(&mut self, scripts: &Scripts, strict: bool, templates: &[(&str, &Arc<Template>)]) {
+        self.hbs.set_strict_mode(strict);
+        if !self
+            .scripts
+            .as_ref()
+            .is_some_and(|registered| Arc::ptr_eq(registered, scripts))
+        {
+            for name in std::mem::take(&mut self.script_helpers) {
+                self.hbs.unregister_helper(&name);
+            }
+            for (name, script) in scripts.iter() {
+                let helper = ScriptHelper::new(name, script.clone());
+                self.hbs.register_helper(name, Box::new(helper));
+                self.script_helpers.push(name.clone());
+            }
+            self.scripts = Some(scripts.clone());
+        }
+        // Included but not given: a deleted partial, which must not render.
+        let gone: Vec<String> = included(templates)
+            .filter(|name| self.templates.contains_key(*name))
+            .map(str::to_owned)
+            .collect();
+        for name in gone {
+            self.hbs.unregister_template(&name);
+            self.templates.remove(&name);
+        }
+        for (name, template) in templates {
+            if self
+                .templates
+                .get(*name)
+                .is_some_and(|registered| Arc::ptr_eq(registered, template))
+            {
+                continue;
+            }
+            // Named, so that render errors say where they are.
+            let mut compiled = template.compiled.clone();
+            compiled.name = Some((*name).to_owned());
+            self.hbs.register_template(name, compiled);
+            self.templates
+                .insert((*name).to_owned(), (*template).clone());
+        }
+    }
+}
+
+// The partial names `templates` include that are not among them.
+fn included<'a>(templates: &'a [(&str, &Arc<Template>)]) -> impl Iterator<Item = &'a str> {
+    templates
+        .iter()
+        .flat_map(|(_, template)| template.partials.iter().map(String::as_str))
+        .filter(|name| !templates.iter().any(|(given, _)| given == name))
+}
+
+impl Registry {
+    // `hbs` comes with the helpers templates may use.
+    pub fn new(hbs: Handlebars<'static>) -> Self {
+        Registry {
+            state: RwLock::new(Registered {
+                hbs,
+                templates: HashMap::new(),
+                scripts: None,
+                script_helpers: Vec::new(),
+            }),
+        }
+    }
+
+    // Set up a render with `templates` (what it renders and the partials
+    // those include, by name), `scripts` as helpers, and strict mode (missing
+    // fields are errors instead of empty strings). Templates are registered
+    // again where they changed, and included names not given are removed.
+    // Until the guard is dropped, other renders cannot change any of it.
+    pub fn prepare<'a>(
+        &self,
+        scripts: &Scripts,
+        strict: bool,
+        templates: impl IntoIterator<Item = (&'a str, &'a Arc<Template>)>,
+    ) -> Rendering<'_> {
+        let templates: Vec<_> = templates.into_iter().collect();
+        let state = self.state.read();
+        if state.ready(scripts, strict, &templates) {
+            return Rendering { state };
+        }
+        drop(state);
+        let mut state = self.state.write();
+        state.update(scripts, strict, &templates);
+        Rendering {
+            state: RwLockWriteGuard::downgrade(state),
+        }
+    }
+}
+
+// Templates set up by `Registry::prepare`.
+pub struct Rendering<'a> {
+    state: RwLockReadGuard<'a, Registered>,
+}
+
+impl Rendering<'_> {
+    pub fn render(&self, name: &str, context: &impl Serialize) -> Result<String, RenderError> {
+        self.state.hbs.render(name, context)
+    }
+
+    // `err`, from rendering `page`, located in the template it happened in.
+    // Templates registered by path are named relative to `root`.
+    pub fn diagnose(&self, err: &RenderError, root: &Utf8Path, page: &str) -> Diagnostic {
+        let name = err.template_name.as_deref().unwrap_or_default();
+        let excerpt = match (self.state.templates.get(name), err.line_no) {
+            (Some(template), Some(line)) => excerpt(template.source(), line),
+            _ => Vec::new(),
+        };
+        Diagnostic {
+            template: relative(root, name),
+            line: err.line_no,
+            column: err.column_no,
+            message: err.reason().to_string(),
+            page: Some(page.to_owned()),
+            excerpt,
+        }
+    }
+}
+
+// Where and why a template failed, for the log and the development overlay.
+#[derive(Debug)]
+pub struct Diagnostic {
+    // The template file, relative to the site, or the partial name.
+    pub template: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+    // What was being rendered, when known.
+    pub page: Option<String>,
+    // Numbered lines around `line`.
+    pub excerpt: Vec<(usize, String)>,
+}
+
+impl Diagnostic {
+    // A template file at `path` that cannot be loaded: the reason (with the
+    // position of a syntax error) is in `message`.
+    pub fn load(root: &Utf8Path, path: &Utf8Path, message: String) -> Self {
+        Diagnostic {
+            template: relative(root, path.as_str()),
+            line: None,
+            column: None,
+            message,
+            page: None,
+            excerpt: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "template `{}`", self.template)?;
+        if let Some(line) = self.line {
+            write!(f, " line {line}")?;
+        }
+        if let Some(column) = self.column {
+            write!(f, ", column {column}")?;
+        }
+        write!(f, ": {}", self.message)?;
+        if let Some(page) = &self.page {
+            write!(f, " (rendering {page})")?;
+        }
+        Ok(())
+    }
+}
+
+fn relative(root: &Utf8Path, name: &str) -> String {
+    Utf8Path::new(name)
+        .strip_prefix(root)
+        .map_or_else(|_| name.to_owned(), |path| path.to_string())
+}
+
+// Lines `line - 2` to `line + 2` of `src`.
+fn excerpt(src: &str, line: usize) -> Vec<(usize, String)> {
+    src.lines()
+        .enumerate()
+        .map(|(index, text)| (index + 1, text.to_owned()))
+        .skip(line.saturating_sub(3))
+        .take(5)
+        .collect()
+}
+
+// The front matter a layout may start with.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Header {
+    // Parent layout: the rest of the file fills its `{{#*inline}}` blocks.
+    layout: String,
+}
+
+// Template source ready for Handlebars: a layout declaring a parent with
+// `layout = "base"` front matter becomes `{{#> base}}...{{/base}}`, the front
+// matter turned into a comment so that lines keep their numbers.
+pub fn expand(src: &str) -> anyhow::Result<String> {
+    let Some(rest) = src.strip_prefix("---\n") else {
+        return Ok(src.to_owned());
+    };
+    let (header, body) = rest
+        .split_once("\n---\n")
+        .ok_or_else(|| anyhow!("unterminated front matter"))?;
+    // The `---` lines included.
+    let lines = "\n".repeat(header.lines().count() + 2);
+    let header: Header = toml::from_str(header)?;
+    if !valid_asset_name(&header.layout) {
+        bail!("invalid layout `{}`", header.layout);
+    }
+    let layout = header.layout;
+    Ok(format!(
+        "{{{{#> {layout}}}}}{{{{!--{lines}--}}}}{body}{{{{/{layout}}}}}"
+    ))
+}
+
+// Names of the partials `src` includes by name (`{{> header}}`,
+// `{{#> base}}`), in order, without duplicates. Dynamic names and
+// `@partial-block` are not files and are left out.
+pub fn partial_names(src: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    let mut rest = src;
+    while let Some(start) = rest.find("{{") {
+        rest = &rest[start + 2..];
+        let tag = rest.trim_start_matches('~');
+        let Some(tag) = tag.strip_prefix('>').or_else(|| tag.strip_prefix("#>")) else {
+            continue;
+        };
+        let tag = tag.trim_start();
+        let end = tag
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+            .unwrap_or(tag.len());
+        let name = &tag[..end];
+        if !name.is_empty() && !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::script::Script;
+
+    #[test]
+    fn registry() {
+        let registry = Registry::new(Handlebars::new());
+        let scripts: Scripts = Vec::new().into();
+        let one = Arc::new(Template::compute("one {{x}} {{> part}}").unwrap());
+        let part = Arc::new(Template::compute("[{{x}}]").unwrap());
+        let context = serde_json::json!({ "x": 1 });
+        let rendering = registry.prepare(&scripts, false, [("page", &one), ("part", &part)]);
+        assert_eq!(rendering.render("page", &context).unwrap(), "one 1 [1]");
+        drop(rendering);
+
+        // Same template: kept; a new one (changed file): registered again.
+        let two = Arc::new(Template::compute("two {{x}}").unwrap());
+        let rendering = registry.prepare(&scripts, false, [("page", &two)]);
+        assert_eq!(rendering.render("page", &context).unwrap(), "two 1");
+        assert!(rendering.render("missing", &context).is_err());
+        drop(rendering);
+        assert!(Template::compute("{{#if x}}").is_err());
+
+        // A partial no longer given (deleted) is no longer included.
+        let rendering = registry.prepare(&scripts, false, [("page", &one)]);
+        assert!(rendering.render("page", &context).is_err());
+        drop(rendering);
+
+        // Strict mode makes missing fields errors.
+        let rendering = registry.prepare(&scripts, true, [("page", &two)]);
+        assert!(rendering.render("page", &serde_json::json!({})).is_err());
+    }
+
+    #[test]
+    fn script_helpers() {
+        let registry = Registry::new(Handlebars::new());
+        let page = Arc::new(Template::compute("{{shout x}}").unwrap());
+        let context = serde_json::json!({ "x": "hi" });
+        let script = |src| Arc::new(Script::compute(src).unwrap());
+        let render = |scripts: &Scripts| {
+            registry
+                .prepare(scripts, false, [("page", &page)])
+                .render("page", &context)
+        };
+        let scripts: Scripts = vec![("shout".to_owned(), script("params[0] + \"!\""))].into();
+        assert_eq!(render(&scripts).unwrap(), "hi!");
+
+        // A changed list replaces the helpers; removed ones are gone.
+        let scripts: Scripts = vec![("shout".to_owned(), script("params[0] + \"?\""))].into();
+        assert_eq!(render(&scripts).unwrap(), "hi?");
+        assert!(render(&Vec::new().into()).is_err());
+    }
+
+    #[test]
+    fn partial_references() {
+        let src = "{{> header}}{{~> nav title=\"x\"}}{{#> base}}{{#*inline \"main\"}}\
+                   {{> header}}{{/inline}}{{/base}}{{> (dynamic)}}{{> @partial-block}}{{x}}";
+        assert_eq!(partial_names(src), ["header", "nav", "base"]);
+    }
+
+    #[test]
+    fn parent_layouts() {
+        assert_eq!(expand("<p>{{x}}</p>").unwrap(), "<p>{{x}}</p>");
+        assert_eq!(
+            expand("---\nlayout = \"base\"\n---\n{{#*inline \"main\"}}Hi{{/inline}}").unwrap(),
+            "{{#> base}}{{!--\n\n\n--}}{{#*inline \"main\"}}Hi{{/inline}}{{/base}}"
+        );
+        assert!(expand("---\nlayout = \"../x\"\n---\n").is_err());
+        assert!(expand("---\nparent = \"base\"\n---\n").is_err());
+        assert!(expand("---\nlayout = \"base\"\n").is_err());
+    }
+}
@@ -1,18 +1,42 @@
-use std::{collections::HashMap, future::Future, pin::Pin, sync::Arc, time::Duration};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    future::Future,
+    net::IpAddr,
+    pin::Pin,
+    sync::{Arc, OnceLock, Weak},
+    time::Duration,
+};
 
+use axum::http::HeaderValue;
 use base64::Engine as _;
 use camino::{Utf8Path, Utf8PathBuf};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
 use dashmap::DashMap;
 use parking_lot::Mutex;
 use serde::Deserialize;
-use serde_json::Value as Json;
+use serde_json::{Map, Value as Json};
 use tokio::{sync::Mutex as AsyncMutex, time::Instant};
 use tracing::{debug, error};
+use twox_hash::XxHash3_128;
 
 use crate::{
-    cache::{Cache, CacheMap, Cacheable},
-    markdown::{render_markdown, strip_html_comments, Block, Document, Page},
+    cache::{Cache, CacheMap, Cacheable, DigestMap, ExistsMap},
+    collection::{entry_fields, neighbours, next_change, published, Collection, Schedule},
+    data::SiteData,
+    defaults::SitePages,
+    feed, helpers,
+    maintenance::{Maintenance, MaintenanceSpec},
+    markdown::{render_markdown, strip_html_comments, toml_to_json, Block, Document, Page},
+    preview::{Drafts, DraftsSection},
+    redirect::{Redirects, RuleSpec},
+    request::{RequestFields, RequestVars},
     sass::Stylesheet,
+    script::{Scripts, SiteScripts},
+    site::{AliasTarget, Site, SiteIndex, SitePage},
+    sitemap, taxonomy,
+    template::{self, Diagnostic, Registry, Rendering, Template},
     url::UrlPath,
 };
 
@@ -21,10 +45,26 @@ use crate::{
 pub struct App {
     root: Utf8PathBuf,
     config: Cache<Arc<Config>>,
-    pages: CacheMap<Arc<Page>>,
+    pages: Arc<SitePages>,
     templates: CacheMap<Arc<Template>>,
     styles: CacheMap<Arc<Stylesheet>>,
+    assets: DigestMap,
+    // Optional files: error pages, the `_maintenance` flag.
+    probes: ExistsMap,
     rendered: RenderedPages,
+    responses: Responses,
+    site: SiteIndex,
+    // `_data/` files, for templates.
+    data: SiteData,
+    // `_style/helpers/` scripts, for templates.
+    scripts: SiteScripts,
+    // Compiled templates. The layouts' `asset` helper needs the `Arc<App>`,
+    // so theirs is set up on first use.
+    layout_hbs: OnceLock<Registry>,
+    snippet_hbs: Registry,
+    // Development mode: template errors are shown in the browser.
+    development: bool,
+    clock: fn() -> DateTime<Utc>,
     last_access: Mutex<Instant>,
 }
 
@@ -33,16 +73,43 @@ impl App {
         App {
             config: Cache::new(root.join("_config.toml")),
             root,
-            pages: CacheMap::default(),
+            pages: Arc::default(),
             templates: CacheMap::default(),
             styles: CacheMap::default(),
+            assets: DigestMap::default(),
+            probes: ExistsMap::default(),
             rendered: RenderedPages::default(),
+            responses: Responses::default(),
+            site: SiteIndex::default(),
+            data: SiteData::default(),
+            scripts: SiteScripts::default(),
+            layout_hbs: OnceLock::new(),
+            snippet_hbs: Registry::new(snippet_helpers()),
+            development: false,
+            clock: Utc::now,
             last_access: Mutex::new(Instant::now()),
         }
     }
 
-    pub fn root(&self) -> &Utf8Path {
-        &self.root
+    pub fn with_development(self, development: bool) -> Self {
+        App {
+            development,
+            ..self
+        }
+    }
+
+    pub fn development(&self) -> bool {
+        self.development
+    }
+
+    // Where the time comes from: `Utc::now` unless tests pin it.
+    pub fn with_clock(self, clock: fn() -> DateTime<Utc>) -> Self {
+        App { clock, ..self }
+    }
+
+    // The current time in the site's timezone.
+    fn now(&self, config: &Config) -> DateTime<Tz> {
+        (self.clock)().with_timezone(&config.timezone)
     }
 
     // Mark this site as just accessed (multi mode uses it to drop idle sites).
@@ -59,7 +126,13 @@ impl App {
         self.pages.sweep(ttl);
         self.templates.sweep(ttl);
         self.styles.sweep(ttl);
+        self.assets.sweep(ttl);
+        self.probes.sweep(ttl);
         self.rendered.sweep(ttl);
+        self.responses.sweep(ttl);
+        self.site.sweep(ttl);
+        self.data.sweep(ttl);
+        self.scripts.sweep(ttl);
     }
 
     // Load the config once at startup so problems show up in the log.
@@ -70,21 +143,38 @@ impl App {
         self.config.load_optional().await.map_err(|(_, err)| err)?;
         Ok(())
     }
-}
 
-// A page-layout or snippet template, cached as raw Handlebars source.
-#[derive(Clone, Default)]
-struct Template(String);
+    // `url` with a `?v=` fingerprint of the asset's current contents, or
+    // unchanged when it names nothing servable.
+    pub async fn asset_url(&self, url: &str) -> String {
+        match self.asset_hash(url).await {
+            Some(hash) => format!("{url}?v={}", fingerprint(hash)),
+            None => url.to_owned(),
+        }
+    }
 
-impl Cacheable for Template {
-    fn compute(src: &str) -> anyhow::Result<Self> {
-        Ok(Template(src.to_owned()))
+    async fn asset_hash(&self, url: &str) -> Option<u128> {
+        let url = UrlPath::new(url).filter(|url| url.extension().is_some())?;
+        match compiled_stylesheet(self, url).await {
+            Ok(Some(css)) => Some(css.hash()),
+            Ok(None) => self
+                .assets
+                .load(self.root.join(url.relative_path()))
+                .await
+                .ok()
+                .map(|digest| digest.hash()),
+            Err(_) => None,
+        }
     }
 }
 
+// A template included by name (`{{> name}}`).
+type Partial = (String, Arc<Template>);
+
 const MAX_RENDERED_PAGES: usize = 1024;
 
-// Rendered Markdown keyed by the identities of its page and snippet inputs.
+// Rendered Markdown keyed by the identities of its page and snippet inputs
+// (`site` variables included, for pages with snippets).
 struct RenderedPages {
     map: DashMap<Utf8PathBuf, Arc<AsyncMutex<RenderedPage>>>,
     cap: usize,
@@ -107,31 +197,56 @@ impl Default for RenderedPages {
 struct RenderedPage {
     page: Option<Arc<Page>>,
     snippets: Vec<Arc<Template>>,
+    // Partials the snippets include.
+    partials: Vec<Partial>,
+    // What snippets saw as `site`; None without snippets.
+    site: Option<SiteVars>,
     html: String,
     last_access: Option<Instant>,
 }
 
 impl RenderedPage {
-    fn matches(&self, page: &Arc<Page>, snippets: &[Arc<Template>]) -> bool {
+    fn matches(
+        &self,
+        page: &Arc<Page>,
+        snippets: &[Arc<Template>],
+        partials: &[Partial],
+        site: &SiteVars,
+    ) -> bool {
         self.page
             .as_ref()
             .is_some_and(|cached| Arc::ptr_eq(cached, page))
-            && self.snippets.len() == snippets.len()
-            && self
-                .snippets
-                .iter()
-                .zip(snippets)
-                .all(|(cached, current)| Arc::ptr_eq(cached, current))
+            && (snippets.is_empty() || self.site.as_ref().is_some_and(|cached| cached.same(site)))
+            && same_templates(&self.snippets, snippets)
+            && same_partials(&self.partials, partials)
     }
 }
 
+fn same_templates(cached: &[Arc<Template>], current: &[Arc<Template>]) -> bool {
+    cached.len() == current.len()
+        && cached
+            .iter()
+            .zip(current)
+            .all(|(cached, current)| Arc::ptr_eq(cached, current))
+}
+
+fn same_partials(cached: &[Partial], current: &[Partial]) -> bool {
+    cached.len() == current.len()
+        && cached
+            .iter()
+            .zip(current)
+            .all(|(cached, current)| cached.0 == current.0 && Arc::ptr_eq(&cached.1, &current.1))
+}
+
 impl RenderedPages {
     async fn load(
         &self,
+        app: &Arc<App>,
         path: &Utf8Path,
-        root: &Utf8Path,
         page: Arc<Page>,
         snippets: Vec<Arc<Template>>,
+        partials: Vec<Partial>,
+        site: SiteVars,
     ) -> Result<String, MyError> {
         let entry = self
             .map
@@ -141,7 +256,7 @@ impl RenderedPages {
         let mut cached = entry.lock().await;
         cached.last_access = Some(Instant::now());
 
-        if cached.matches(&page, &snippets) {
+        if cached.matches(&page, &snippets, &partials, &site) {
             let html = cached.html.clone();
             drop(cached);
             self.enforce_cap();
@@ -152,23 +267,22 @@ impl RenderedPages {
         self.renders
             .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
 
-        let render_root = root.to_owned();
+        let render_app = app.clone();
         let render_page = page.clone();
         let render_snippets = snippets.clone();
+        let render_partials = partials.clone();
+        let render_site = site.clone();
+        let page_name = format!("`{}`", path.strip_prefix(&app.root).unwrap_or(path));
         let result = tokio::task::spawn_blocking(move || {
-            let mut snippets = render_snippets.iter();
-            let html = render_document(
-                &render_root,
+            render_document(
+                &render_app,
+                &page_name,
                 render_page.body(),
                 render_page.fields(),
-                &mut snippets,
-            )?;
-            if snippets.next().is_some() {
-                return Err(MyError::Internal(
-                    "unused snippet rendering dependency".into(),
-                ));
-            }
-            Ok(html)
+                &render_site,
+                &render_partials,
+                &render_snippets,
+            )
         })
         .await
         .map_err(|err| {
@@ -178,7 +292,9 @@ impl RenderedPages {
 
         if let Ok(html) = &result {
             cached.page = Some(page);
+            cached.site = (!snippets.is_empty()).then_some(site);
             cached.snippets = snippets;
+            cached.partials = partials;
             cached.html = html.clone();
         }
         drop(cached);
@@ -218,12 +334,158 @@ impl RenderedPages {
     }
 }
 
+const MAX_RESPONSES: usize = 1024;
+
+// Final page responses, layout included, so a warm request does no template
+// work and no hashing. Pages only some may see (drafts) are never kept.
+struct Responses {
+    map: DashMap<ResponseKey, CachedResponse>,
+    cap: usize,
+    #[cfg(test)]
+    hits: std::sync::atomic::AtomicUsize,
+}
+
+impl Default for Responses {
+    fn default() -> Self {
+        Self {
+            map: DashMap::new(),
+            cap: MAX_RESPONSES,
+            #[cfg(test)]
+            hits: std::sync::atomic::AtomicUsize::new(0),
+        }
+    }
+}
+
+// What a page's response varies with, besides its `ResponseInputs`.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct ResponseKey {
+    url: String,
+    // Of a collection listing (`?page=`).
+    number: Option<usize>,
+    // Who the menus and `related` pages are for, when some pages are
+    // protected.
+    user: Option<String>,
+    // The `request` fields the layout reads, as JSON.
+    request: Option<String>,
+}
+
+// The identities of everything a page's response was rendered from.
+struct ResponseInputs {
+    page: Arc<Page>,
+    // Navigation, listings and related pages.
+    site: Arc<Site>,
+    layout: LayoutInputs,
+}
+
+impl ResponseInputs {
+    fn same(&self, other: &ResponseInputs) -> bool {
+        Arc::ptr_eq(&self.page, &other.page)
+            && Arc::ptr_eq(&self.site, &other.site)
+            && self.layout.same(&other.layout)
+    }
+}
+
+struct CachedResponse {
+    inputs: ResponseInputs,
+    // `asset` URLs the layout rendered: (requested, rendered).
+    assets: Arc<[(String, String)]>,
+    // When a date or schedule next changes which pages are published.
+    valid_until: Option<DateTime<Tz>>,
+    rendered: Rendered,
+    last_access: Instant,
+}
+
+impl Responses {
+    async fn get(
+        &self,
+        app: &App,
+        key: &ResponseKey,
+        inputs: &ResponseInputs,
+        now: DateTime<Tz>,
+    ) -> Option<Rendered> {
+        let (rendered, assets) = {
+            let mut cached = self.map.get_mut(key)?;
+            if !cached.inputs.same(inputs) || cached.valid_until.is_some_and(|time| now >= time) {
+                return None;
+            }
+            cached.last_access = Instant::now();
+            (cached.rendered.clone(), cached.assets.clone())
+        };
+        // Fingerprints change with the files, without any template changing.
+        for (url, rendered) in assets.iter() {
+            if app.asset_url(url).await != *rendered {
+                return None;
+            }
+        }
+        #[cfg(test)]
+        self.hits.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        Some(rendered)
+    }
+
+    // Past the cap, the least recently used go, an eighth of the cap more
+    // than the excess, so that the scan behind it only happens once every
+    // `cap / 8` inserts.
+    fn insert(&self, key: ResponseKey, response: CachedResponse) {
+        self.map.insert(key, response);
+        if self.map.len() <= self.cap {
+            return;
+        }
+        let mut entries: Vec<_> = self
+            .map
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.last_access))
+            .collect();
+        let evict = (entries.len() + self.cap / 8)
+            .saturating_sub(self.cap)
+            .min(entries.len());
+        if evict == 0 {
+            return;
+        }
+        entries.select_nth_unstable_by_key(evict - 1, |(_, time)| *time);
+        for (key, _) in entries.into_iter().take(evict) {
+            self.map.remove(&key);
+        }
+    }
+
+    fn sweep(&self, ttl: Duration) {
+        let now = Instant::now();
+        self.map
+            .retain(|_, cached| now.saturating_duration_since(cached.last_access) < ttl);
+    }
+}
+
 #[derive(Debug, Default)]
 struct Config {
     // Path prefix -> users allowed to access it (HTTP Basic auth).
     protected: HashMap<String, Vec<String>>,
     // Plain-text credentials (user -> password).
     users: HashMap<String, String>,
+    // Group -> its users, as templates see them in `request.groups`.
+    groups: HashMap<String, Vec<String>>,
+    redirects: Redirects,
+    maintenance: Maintenance,
+    // Public URL of the site, without a trailing slash, for absolute links.
+    base_url: Option<String>,
+    // The `[site]` table, as templates see it under `site`.
+    site: Map<String, Json>,
+    // Front-matter fields whose terms get listing pages (`tags`...).
+    taxonomies: Vec<String>,
+    // For front-matter dates without offset (`publish_date`...).
+    timezone: Tz,
+    // Who may see `draft = true` pages.
+    drafts: Drafts,
+    // Missing template fields are errors rather than empty.
+    strict_templates: bool,
+}
+
+// The `[site]` keys flaty itself uses; any others are for templates.
+#[derive(Deserialize, Default)]
+struct SiteSection {
+    base_url: Option<String>,
+    // IANA name (`Europe/Paris`); UTC by default.
+    timezone: Option<String>,
+    // For the `date` and `number` helpers (`fr_FR`); `en_US` by default.
+    locale: Option<String>,
 }
 
 #[derive(Deserialize, Default)]
@@ -232,40 +494,237 @@ struct ConfigFile {
     protected: HashMap<String, Vec<String>>,
     #[serde(default)]
     users: HashMap<String, String>,
+    #[serde(default)]
+    groups: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    redirects: HashMap<String, RuleSpec>,
+    maintenance: Option<MaintenanceSpec>,
+    #[serde(default)]
+    site: toml::Table,
+    #[serde(default)]
+    taxonomies: Vec<String>,
+    #[serde(default)]
+    drafts: DraftsSection,
+    #[serde(default)]
+    templates: TemplatesSection,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct TemplatesSection {
+    #[serde(default)]
+    strict: bool,
 }
 
 impl Cacheable for Config {
     fn compute(src: &str) -> anyhow::Result<Self> {
         let cf: ConfigFile = toml::from_str(src)?;
+        taxonomy::check_names(&cf.taxonomies)?;
+        let section = SiteSection::deserialize(toml::Value::Table(cf.site.clone()))
+            .map_err(|err| anyhow::anyhow!("site: {err}"))?;
+        if let Some(locale) = &section.locale {
+            if helpers::parse_locale(locale).is_none() {
+                anyhow::bail!("site: unknown locale `{locale}`");
+            }
+        }
+        let base_url = section
+            .base_url
+            .map(|url| parse_base_url(&url))
+            .transpose()?;
+        let Json::Object(mut site) = toml_to_json(toml::Value::Table(cf.site)) else {
+            unreachable!("a table converts to an object");
+        };
+        if site.contains_key("data") {
+            anyhow::bail!("site: `data` is reserved for `_data/` files");
+        }
+        if let Some(base_url) = &base_url {
+            site.insert("base_url".into(), Json::from(base_url.as_str()));
+        }
         Ok(Config {
             protected: cf.protected,
             users: cf.users,
+            groups: cf.groups,
+            redirects: Redirects::new(cf.redirects)?,
+            maintenance: Maintenance::new(cf.maintenance)?,
+            base_url,
+            site,
+            taxonomies: cf.taxonomies,
+            timezone: match section.timezone {
+                Some(name) => name
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("site: unknown timezone `{name}`"))?,
+                None => Tz::UTC,
+            },
+            drafts: Drafts::new(cf.drafts)?,
+            strict_templates: cf.templates.strict,
         })
     }
 }
 
+impl Config {
+    // The groups `user` is in, sorted.
+    fn groups_of(&self, user: &str) -> Vec<String> {
+        let mut groups: Vec<String> = self
+            .groups
+            .iter()
+            .filter(|(_, users)| users.iter().any(|u| u == user))
+            .map(|(group, _)| group.clone())
+            .collect();
+        groups.sort();
+        groups
+    }
+}
+
+// `https://example.com/` -> `https://example.com`: an http(s) URL with a host
+// and no query or fragment, trailing slash dropped.
+fn parse_base_url(url: &str) -> anyhow::Result<String> {
+    let rest = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"));
+    let valid = rest.is_some_and(|rest| {
+        !rest.starts_with(['/', ':'])
+            && !rest.is_empty()
+            && !rest.contains(['?', '#'])
+            && !rest.chars().any(|c| c.is_whitespace() || c.is_control())
+    });
+    if !valid {
+        anyhow::bail!("site: invalid base_url `{url}`, expected an http(s) URL");
+    }
+    Ok(url.trim_end_matches('/').to_owned())
+}
+
 #[allow(clippy::upper_case_acronyms)]
 pub enum MyRequest<'a> {
     GET {
         path: &'a str,
+        query: Option<&'a str>,
         authorization: Option<&'a str>,
+        // Address of the connecting client (or proxy).
+        peer: Option<IpAddr>,
+        forwarded_for: Option<&'a str>,
+        host: Option<&'a str>,
+        accept_language: Option<&'a str>,
     },
 }
 
+// How long clients may reuse a response without asking again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CachePolicy {
+    // Revalidate on every use (ETag / Last-Modified).
+    Revalidate,
+    // The URL carries the current content fingerprint, so it never changes.
+    Immutable,
+    // Never stored by shared or browser caches (drafts).
+    Private,
+}
+
+// A rendered page, with the status and content type its front matter asks
+// for.
+#[derive(Clone)]
+pub struct Rendered {
+    pub body: String,
+    // Of `body`, when computed ahead (see `etag`).
+    pub etag: Option<String>,
+    pub status: u16,
+    // Overrides `text/html` (a page can render to plain text, JSON...).
+    pub content_type: Option<String>,
+    // For a 200; other statuses are never cached, except `Private` ones.
+    pub policy: CachePolicy,
+}
+
+impl Rendered {
+    fn html(body: String) -> Self {
+        Rendered {
+            body,
+            etag: None,
+            status: 200,
+            content_type: None,
+            policy: CachePolicy::Revalidate,
+        }
+    }
+}
+
+// Development mode's page for a template error: where it is, why, and the
+// lines around it.
+pub fn error_overlay(diagnostic: &Diagnostic) -> Rendered {
+    let escape = handlebars::html_escape;
+    let mut location = format!("<code>{}</code>", escape(&diagnostic.template));
+    if let Some(line) = diagnostic.line {
+        location.push_str(&format!(" line {line}"));
+    }
+    if let Some(column) = diagnostic.column {
+        location.push_str(&format!(", column {column}"));
+    }
+    let mut excerpt = String::new();
+    for (number, text) in &diagnostic.excerpt {
+        let text = format!("{number:4} | {}", escape(text));
+        if Some(*number) == diagnostic.line {
+            excerpt.push_str(&format!("<mark>{text}</mark>\n"));
+        } else {
+            excerpt.push_str(&format!("{text}\n"));
+        }
+    }
+    let page = diagnostic
+        .page
+        .as_deref()
+        .map(|page| format!("<p>Rendering {}</p>", escape(page)))
+        .unwrap_or_default();
+    let body = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Template error</title>\
+         <style>body{{margin:0;background:#0008;font:16px sans-serif}}\
+         div{{margin:5vh auto;max-width:60em;padding:1em 2em;background:#fff;\
+         border-top:6px solid #c00}}h1{{color:#c00}}pre{{overflow:auto;padding:1em;\
+         background:#f4f4f4}}mark{{background:#fdd;display:block}}</style></head>\n\
+         <body><div><h1>Template error</h1><p>{location}</p><pre>{}</pre>{}{page}</div>\
+         </body></html>\n",
+        escape(&diagnostic.message),
+        if excerpt.is_empty() {
+            String::new()
+        } else {
+            format!("<pre>{excerpt}</pre>")
+        },
+    );
+    Rendered {
+        status: 500,
+        policy: CachePolicy::Private,
+        ..Rendered::html(body)
+    }
+}
+
+// A strong ETag for a generated body.
+pub fn etag(body: &str) -> String {
+    hash_etag(XxHash3_128::oneshot(body.as_bytes()))
+}
+
+// The ETag of contents already hashed (a stylesheet's, as in its `asset` URL).
+pub fn hash_etag(hash: u128) -> String {
+    format!("\"{hash:032x}\"")
+}
+
 pub enum MyResponse {
-    Html(String),
-    Css(String),
-    File(Utf8PathBuf),
-    Redirect(String),
+    Page(Rendered),
+    // The CSS and its hash, which its ETag and fingerprint share.
+    Css(String, u128, CachePolicy),
+    File(Utf8PathBuf, CachePolicy),
+    // Location and status (301, 302, 307 or 308).
+    Redirect(String, u16),
+    // Not to be indexed by search engines (`X-Robots-Tag: noindex`).
+    Noindex(Box<MyResponse>),
 }
 
 #[derive(Debug)]
 pub enum MyError {
     NotFound,
+    // Withdrawn for good (`expire_date` passed): 410.
+    Gone,
     Unauthorized,
     InvalidPage,
     InvalidScss,
     CannotRead,
+    // Maintenance mode: 503, try again after this many seconds.
+    Unavailable { retry_after: u32 },
+    // A template that does not compile or render (already logged).
+    Template(Box<Diagnostic>),
     Internal(String),
 }
 
@@ -274,10 +733,14 @@ pub type MyResult = Result<MyResponse, MyError>;
 pub async fn web(app: Arc<App>, req: MyRequest<'_>) -> MyResult {
     let MyRequest::GET {
         path,
+        query,
         authorization,
+        peer,
+        forwarded_for,
+        host,
+        accept_language,
     } = req;
     debug!("GET {path}");
-    let url = UrlPath::new(path).ok_or(MyError::NotFound)?;
 
     // A missing `_config.toml` is treated as empty (an unconfigured site).
     // An invalid one -> 404, rather than serving a misconfigured site; the
@@ -287,184 +750,1306 @@ pub async fn web(app: Arc<App>, req: MyRequest<'_>) -> MyResult {
         Err(_) => return Err(MyError::NotFound),
     };
 
-    if !authorized(&config, url.path(), authorization) {
+    // Signing in works during maintenance, so listed users can get through.
+    if path == "/_login" {
+        return login(&config, query, authorization);
+    }
+
+    if config.maintenance.enabled || app.probes.exists(app.root.join("_maintenance")).await {
+        let user = authenticated_user(&config, authorization);
+        // Stylesheets belong to the theme, so the maintenance page keeps its look.
+        let stylesheet = match UrlPath::new(path) {
+            Some(url) => matches!(compiled_stylesheet(&app, url).await, Ok(Some(_))),
+            None => false,
+        };
+        if !stylesheet
+            && !config
+                .maintenance
+                .bypass(user.as_deref(), peer, forwarded_for)
+        {
+            return Err(MyError::Unavailable {
+                retry_after: config.maintenance.retry_after,
+            });
+        }
+    }
+
+    // Configured redirects apply to any path, even one that is no longer
+    // valid or no longer exists, so they come before everything else.
+    if let Some((location, status)) = config.redirects.resolve(path, query) {
+        return Ok(MyResponse::Redirect(location, status));
+    }
+
+    let user = authenticated_user(&config, authorization);
+    let groups = user
+        .as_deref()
+        .map(|user| config.groups_of(user))
+        .unwrap_or_default();
+    let request = RequestVars::new(path, query, host, user, groups, accept_language);
+
+    // Reserved endpoints: `_` paths are never content.
+    match path {
+        "/_search" => return search(&app, &config, query, authorization, &request, false).await,
+        "/_search/" => return search(&app, &config, query, authorization, &request, true).await,
+        _ => {}
+    }
+
+    let result = match serve(&app, &config, path, query, authorization, &request).await {
+        // Only paths that match nothing else fall back to page aliases; the
+        // site snapshot is at hand, so a flood of 404s never waits for a walk.
+        Err(MyError::NotFound) => match app.site.load(&app.root, &app.pages).await.alias(path) {
+            Some(AliasTarget::Page(url)) => Ok(MyResponse::Redirect(url.clone(), 301)),
+            Some(AliasTarget::Conflict) => Err(MyError::InvalidPage),
+            None => Err(MyError::NotFound),
+        },
+        result => result,
+    };
+    // Protected content stays out of search engines, even for allowed users.
+    if allowed_users(&config, path).is_some() {
+        return result.map(|response| MyResponse::Noindex(Box::new(response)));
+    }
+    result
+}
+
+// `/_login?next=/path/`: a Basic auth challenge until the browser sends valid
+// credentials, then back to `next` (a path on this site) or the home page.
+// Browsers only send credentials once challenged, and then keep sending them
+// for the whole site.
+fn login(config: &Config, query: Option<&str>, authorization: Option<&str>) -> MyResult {
+    if config.users.is_empty() {
+        return Err(MyError::NotFound);
+    }
+    if authenticated_user(config, authorization).is_none() {
+        return Err(MyError::Unauthorized);
+    }
+    let next = form_urlencoded::parse(query.unwrap_or_default().as_bytes())
+        .find(|(key, _)| key == "next")
+        .map(|(_, next)| next.into_owned())
+        .filter(|next| {
+            next.starts_with('/')
+                && !next.starts_with("//")
+                && !next.contains('\\')
+                && !next.chars().any(char::is_control)
+        })
+        .unwrap_or_else(|| "/".into());
+    Ok(MyResponse::Redirect(next, 302))
+}
+
+async fn serve(
+    app: &Arc<App>,
+    config: &Config,
+    path: &str,
+    query: Option<&str>,
+    authorization: Option<&str>,
+    request: &RequestVars,
+) -> MyResult {
+    let url = UrlPath::new(path).ok_or(MyError::NotFound)?;
+
+    if !authorized(config, url.path(), authorization) {
         return Err(MyError::Unauthorized);
     }
 
     if url.has_final_slash() {
-        let html = render_page(&app, url).await?;
-        return Ok(MyResponse::Html(html));
-    }
-
-    if let Some(name) = url.path().strip_prefix('/').filter(|p| !p.contains('/')) {
-        if let Some(stem) = name.strip_suffix(".css") {
-            // Serve a real `.css` file as-is (falls through to raw file below);
-            // only compile `_style/{stem}.scss` when no such file exists.
-            let css_path = app.root.join(name);
-            let css_exists = tokio::fs::try_exists(&css_path).await.unwrap_or(false);
-            if !css_exists && valid_asset_name(stem) {
-                let scss_path = app.root.join(format!("_style/{stem}.scss"));
-                // Don't create cache entries for missing stylesheets.
-                if !tokio::fs::try_exists(&scss_path).await.unwrap_or(false) {
-                    return Err(MyError::NotFound);
-                }
-                let css = match app.styles.load(&scss_path).await {
-                    Ok(css) => css,
-                    Err(_) => return Err(MyError::InvalidScss),
-                };
-                return Ok(MyResponse::Css(css.css().to_owned()));
+        // Pages win over generated taxonomy pages.
+        return match render_page(app, config, url, query, authorization, request).await {
+            Err(MyError::NotFound) => {
+                render_taxonomy(app, config, url, authorization, request).await
             }
-        }
+            result => result,
+        };
+    }
+
+    if let Some(css) = compiled_stylesheet(app, url).await? {
+        let policy = cache_policy(query, css.hash());
+        return Ok(MyResponse::Css(css.css().to_owned(), css.hash(), policy));
     }
 
     // Any path with an extension is served as a raw file.
     if url.extension().is_some() {
-        return Ok(MyResponse::File(app.root.join(url.relative_path())));
+        let path = app.root.join(url.relative_path());
+        if !tokio::fs::metadata(&path)
+            .await
+            .is_ok_and(|meta| meta.is_file())
+        {
+            // A real file wins over a generated one.
+            let generated = match url.path() {
+                "/sitemap.xml" => Some(render_sitemap(app, config).await?),
+                "/robots.txt" => Some(MyResponse::Page(Rendered {
+                    content_type: Some("text/plain; charset=utf-8".into()),
+                    ..Rendered::html(sitemap::robots(config.base_url.as_deref()))
+                })),
+                _ => render_feed(app, config, url).await?,
+            };
+            return generated.ok_or(MyError::NotFound);
+        }
+        // Only fingerprinted URLs need the file's digest. A stale or unknown
+        // fingerprint still serves the current file, just not as immutable.
+        let policy = match query_param(query, "v") {
+            Some(_) => match app.assets.load(&path).await {
+                Ok(digest) => cache_policy(query, digest.hash()),
+                Err(_) => CachePolicy::Revalidate,
+            },
+            None => CachePolicy::Revalidate,
+        };
+        return Ok(MyResponse::File(path, policy));
     }
 
     if tokio::fs::try_exists(app.root.join(format!("{}/page.md", url.relative_path())))
         .await
         .unwrap_or(false)
     {
-        return Ok(MyResponse::Redirect(format!("{}/", url.path())));
+        return Ok(MyResponse::Redirect(format!("{}/", url.path()), 301));
     }
 
     Err(MyError::NotFound)
 }
 
-async fn render_page(app: &App, url: UrlPath<'_>) -> Result<String, MyError> {
-    let page_path = app.root.join(format!("{}page.md", url.relative_path()));
-    // Don't create cache entries for missing pages.
-    if !tokio::fs::try_exists(&page_path).await.unwrap_or(false) {
-        return Err(MyError::NotFound);
-    }
-    let page = match app.pages.load(&page_path).await {
-        Ok(page) => page,
-        // The file exists (checked above), so a load failure is a bad page.
-        Err(_) => return Err(MyError::InvalidPage),
-    };
-
-    let template = page.template();
-    if !valid_asset_name(template) {
+// Full-text search for `?q=`: JSON hits, or (`html`) the results rendered
+// through `_style/search.html`. Protected pages are only listed for users
+// allowed to see them.
+async fn search(
+    app: &Arc<App>,
+    config: &Arc<Config>,
+    query: Option<&str>,
+    authorization: Option<&str>,
+    request: &RequestVars,
+    html: bool,
+) -> MyResult {
+    let words = query
+        .and_then(|query| {
+            form_urlencoded::parse(query.as_bytes())
+                .find(|(key, _)| key == "q")
+                .map(|(_, value)| value.into_owned())
+        })
+        .unwrap_or_default();
+    let tpl_path = app.root.join("_style/search.html");
+    if html && !app.probes.exists(&tpl_path).await {
         return Err(MyError::NotFound);
     }
-    let tpl_path = app.root.join(format!("_style/{template}.html"));
-    let tpl = match app.templates.load(&tpl_path).await {
-        Ok(tpl) => tpl,
-        Err(_) => return Err(MyError::CannotRead),
-    };
-
-    let mut snippets = Vec::new();
-    load_snippet_templates(app, page.body(), &mut snippets).await?;
-    let contents = app
-        .rendered
-        .load(&page_path, &app.root, page.clone(), snippets)
-        .await?;
-    let mut fields = page.fields().clone();
-    fields.insert("contents".into(), Json::String(contents));
 
-    let mut hbs = handlebars::Handlebars::new();
-    hbs.register_helper("is_empty", Box::new(is_empty));
-    let html = hbs
-        .render_template(&tpl.0, &fields)
-        .map_err(|_| MyError::Internal("invalid template".into()))?;
+    let site = app.site.load(&app.root, &app.pages).await;
+    let user = authenticated_user(config, authorization);
+    let visible_config = config.clone();
+    let now = app.now(config);
+    let search_words = words.clone();
+    // Off the runtime: the index is built on first use.
+    let hits = tokio::task::spawn_blocking(move || {
+        site.search().search(&search_words, |url| {
+            site.find(url)
+                .is_some_and(|entry| published(&entry.page, now))
+                && user_allowed(&visible_config, url, user.as_deref())
+        })
+    })
+    .await
+    .map_err(|err| {
+        error!("search task failed: {err}");
+        MyError::Internal("cannot search".into())
+    })?;
 
-    Ok(html)
+    let mut vars = Map::new();
+    vars.insert("query".into(), Json::String(words));
+    vars.insert(
+        "hits".into(),
+        serde_json::to_value(hits).map_err(|err| MyError::Internal(err.to_string()))?,
+    );
+    if !html {
+        return Ok(MyResponse::Page(Rendered {
+            content_type: Some("application/json".into()),
+            ..Rendered::html(Json::Object(vars).to_string())
+        }));
+    }
+    // For the template only: who asks is none of the JSON's business.
+    vars.insert(
+        "request".into(),
+        Json::Object(request.to_json(RequestFields::ALL)),
+    );
+    let tpl = load_template(app, &tpl_path, MyError::CannotRead).await?;
+    let body = render_layout(app, &tpl_path, tpl, vars, request.path()).await?;
+    Ok(MyResponse::Page(Rendered::html(body)))
 }
 
-fn load_snippet_templates<'a>(
-    app: &'a App,
-    document: &'a Document,
-    templates: &'a mut Vec<Arc<Template>>,
-) -> Pin<Box<dyn Future<Output = Result<(), MyError>> + Send + 'a>> {
-    Box::pin(async move {
-        for block in document.blocks() {
-            let Block::Snippet {
-                name, body, line, ..
-            } = block
-            else {
-                continue;
-            };
-            let path = app.root.join(format!("_style/snippets/{name}.html"));
-            if !tokio::fs::try_exists(&path).await.unwrap_or(false) {
-                error!("missing snippet `{path}` used at line {line}");
-                return Err(MyError::InvalidPage);
-            }
-            let template = match app.templates.load(&path).await {
-                Ok(template) => template,
-                Err(_) => return Err(MyError::InvalidPage),
-            };
-            templates.push(template);
-            load_snippet_templates(app, body, templates).await?;
+// `/{taxonomy}/` and `/{taxonomy}/{term}/` for the configured taxonomies,
+// rendered through `_style/taxonomy.html`. Only lists pages the user may see.
+async fn render_taxonomy(
+    app: &Arc<App>,
+    config: &Config,
+    url: UrlPath<'_>,
+    authorization: Option<&str>,
+    request: &RequestVars,
+) -> MyResult {
+    let components: Vec<&str> = url.path().split_terminator('/').skip(1).collect();
+    let (name, term_slug) = match components.as_slice() {
+        [name] => (*name, None),
+        [name, term] => {
+            let term = percent_encoding::percent_decode_str(term).decode_utf8_lossy();
+            (*name, Some(term))
         }
-        Ok(())
-    })
+        _ => return Err(MyError::NotFound),
+    };
+    if !config.taxonomies.iter().any(|taxonomy| taxonomy == name) {
+        return Err(MyError::NotFound);
+    }
+    let tpl_path = app.root.join("_style/taxonomy.html");
+    if !app.probes.exists(&tpl_path).await {
+        return Err(MyError::NotFound);
+    }
+
+    let site = app.site.load(&app.root, &app.pages).await;
+    let now = app.now(config);
+    let user = authenticated_user(config, authorization);
+    let terms = taxonomy::terms(&site, name, |entry| {
+        published(&entry.page, now) && user_allowed(config, &entry.url, user.as_deref())
+    });
+    let term_json = |term: &taxonomy::Term| {
+        serde_json::json!({
+            "name": term.name,
+            "slug": term.slug,
+            "url": taxonomy::term_url(name, &term.slug),
+            "count": term.pages.len(),
+        })
+    };
+    let mut vars = Map::new();
+    vars.insert("taxonomy".into(), Json::from(name));
+    vars.insert("url".into(), Json::from(url.path()));
+    vars.insert(
+        "request".into(),
+        Json::Object(request.to_json(RequestFields::ALL)),
+    );
+    vars.insert(
+        "terms".into(),
+        Json::Array(terms.iter().map(term_json).collect()),
+    );
+    match term_slug {
+        None => {
+            vars.insert("term".into(), Json::Null);
+            vars.insert("pages".into(), Json::Array(Vec::new()));
+        }
+        Some(term_slug) => {
+            let term = terms
+                .iter()
+                .find(|term| term.slug == *term_slug)
+                .ok_or(MyError::NotFound)?;
+            vars.insert("term".into(), term_json(term));
+            vars.insert(
+                "pages".into(),
+                Json::Array(term.pages.iter().copied().map(entry_fields).collect()),
+            );
+        }
+    }
+
+    let tpl = load_template(app, &tpl_path, MyError::CannotRead).await?;
+    let body = render_layout(app, &tpl_path, tpl, vars, url.path()).await?;
+    Ok(MyResponse::Page(Rendered::html(body)))
 }
 
-fn render_document<'a>(
-    root: &Utf8Path,
-    document: &Document,
-    page: &serde_json::Map<String, Json>,
-    templates: &mut impl Iterator<Item = &'a Arc<Template>>,
-) -> Result<String, MyError> {
-    let mut hbs = handlebars::Handlebars::new();
-    hbs.register_helper("is_empty", Box::new(is_empty));
-    render_document_with(&hbs, root, document, page, templates)
+// Every page a search engine can reach: not protected, published, not
+// opted out with `sitemap = false`, and neither a redirect nor an error page.
+async fn render_sitemap(app: &Arc<App>, config: &Config) -> Result<MyResponse, MyError> {
+    let Some(base_url) = &config.base_url else {
+        error!("`/sitemap.xml` needs `base_url` in `[site]`");
+        return Err(MyError::Internal("no base_url for sitemap".into()));
+    };
+    let site = app.site.load(&app.root, &app.pages).await;
+    let now = app.now(config);
+    let pages: Vec<_> = site
+        .pages()
+        .iter()
+        .filter(|entry| {
+            let page = &entry.page;
+            allowed_users(config, &entry.url).is_none()
+                && published(page, now)
+                && page.fields().get("sitemap") != Some(&Json::Bool(false))
+                && page.redirect().is_none()
+                && matches!(page.status(), Ok(None | Some(..300)))
+        })
+        .map(|entry| {
+            let lastmod = entry
+                .page
+                .updated()
+                .or(entry.modified.map(DateTime::<Utc>::from));
+            (entry.url.clone(), lastmod)
+        })
+        .collect();
+    Ok(MyResponse::Page(Rendered {
+        content_type: Some("application/xml; charset=utf-8".into()),
+        ..Rendered::html(sitemap::sitemap(base_url, &pages))
+    }))
 }
 
-fn render_document_with<'a>(
-    hbs: &handlebars::Handlebars<'_>,
-    root: &Utf8Path,
-    document: &Document,
-    page: &serde_json::Map<String, Json>,
-    templates: &mut impl Iterator<Item = &'a Arc<Template>>,
-) -> Result<String, MyError> {
-    let mut expanded = String::new();
-    for block in document.blocks() {
-        match block {
-            Block::Markdown(src) => expanded.push_str(src),
-            Block::Snippet {
-                name,
-                params,
-                body,
-                line,
-            } => {
-                let template = templates.next().ok_or_else(|| {
-                    MyError::Internal("missing snippet rendering dependency".into())
-                })?;
-                let body = render_document_with(hbs, root, body, page, templates)?;
-                let mut context = params.clone();
-                context.insert("contents".into(), Json::String(body));
-                context.insert("page".into(), Json::Object(page.clone()));
+// The feed at `url` (`/blog/feed.xml`, `/blog/rss.xml`), when the page of
+// its directory publishes one; None otherwise.
+async fn render_feed(
+    app: &Arc<App>,
+    config: &Config,
+    url: UrlPath<'_>,
+) -> Result<Option<MyResponse>, MyError> {
+    let Some((dir, name)) = url.path().rsplit_once('/') else {
+        return Ok(None);
+    };
+    let Some(format) = feed::Format::from_file_name(name) else {
+        return Ok(None);
+    };
+    let dir = format!("{dir}/");
+    let page_path = app.root.join(dir.trim_start_matches('/')).join("page.md");
+    if !app.probes.exists(&page_path).await {
+        return Ok(None);
+    }
+    let page = app
+        .pages
+        .load(&app.root, &page_path)
+        .await
+        .map_err(|_| MyError::InvalidPage)?;
+    let formats = feed::formats(&page).map_err(|err| {
+        error!("`{page_path}`: {err}");
+        MyError::InvalidPage
+    })?;
+    if !formats.contains(&format) {
+        return Ok(None);
+    }
+    let Some(base_url) = &config.base_url else {
+        error!("feed `{}` needs `base_url` in `[site]`", url.path());
+        return Err(MyError::Internal("no base_url for feed".into()));
+    };
 
-                let path = root.join(format!("_style/snippets/{name}.html"));
-                let html = hbs.render_template(&template.0, &context).map_err(|err| {
-                    error!("invalid snippet `{path}` used at line {line}: {err}");
-                    MyError::InvalidPage
-                })?;
-                expanded.push_str("\n\n");
-                expanded.push_str(&strip_html_comments(&html));
-                expanded.push_str("\n\n");
-            }
+    let collection = match Collection::new(&dir, &page) {
+        None => Collection::children(&dir),
+        Some(Ok(collection)) => collection,
+        Some(Err(err)) => {
+            error!("invalid collection in `{page_path}`: {err}");
+            return Err(MyError::InvalidPage);
         }
+    };
+    let now = app.now(config);
+    let site = app.site.load(&app.root, &app.pages).await;
+    let site_vars = SiteVars::load(app).await;
+    let mut entries = Vec::new();
+    // Feeds are public: protected pages, redirects and error pages stay out.
+    let public = |entry: &SitePage| {
+        published(&entry.page, now)
+            && allowed_users(config, &entry.url).is_none()
+            && entry.page.redirect().is_none()
+            && matches!(entry.page.status(), Ok(None | Some(200)))
+    };
+    for member in collection
+        .members(&site, public)
+        .into_iter()
+        .take(feed::MAX_ENTRIES)
+    {
+        let member_path = app
+            .root
+            .join(member.url.trim_start_matches('/'))
+            .join("page.md");
+        let mut snippets = Vec::new();
+        load_snippet_templates(app, member.page.body(), &mut snippets).await?;
+        let partials = load_partials(app, &snippets).await?;
+        let contents = app
+            .rendered
+            .load(
+                app,
+                &member_path,
+                member.page.clone(),
+                snippets,
+                partials,
+                site_vars.clone(),
+            )
+            .await?;
+        let fields = member.page.fields();
+        let published = member.page.date();
+        let modified = member.modified.map(DateTime::<Utc>::from);
+        entries.push(feed::Entry {
+            url: member.url.clone(),
+            title: fields
+                .get("title")
+                .and_then(Json::as_str)
+                .unwrap_or_default()
+                .to_owned(),
+            author: fields
+                .get("author")
+                .and_then(Json::as_str)
+                .map(str::to_owned),
+            published,
+            updated: member
+                .page
+                .updated()
+                .or(published)
+                .or(modified)
+                .unwrap_or(now.to_utc()),
+            summary: member.page.summary(),
+            contents,
+        });
     }
-    Ok(strip_html_comments(&render_markdown(&expanded)))
+    let fields = page.fields();
+    let feed = feed::Feed {
+        title: fields
+            .get("title")
+            .and_then(Json::as_str)
+            .unwrap_or(base_url)
+            .to_owned(),
+        description: page.summary(),
+        author: fields
+            .get("author")
+            .and_then(Json::as_str)
+            .map(str::to_owned),
+        url: dir,
+        entries,
+    };
+    Ok(Some(MyResponse::Page(Rendered {
+        content_type: Some(format.content_type().into()),
+        ..Rendered::html(feed.render(format, base_url, now.to_utc()))
+    })))
 }
 
-// True for null, empty string, empty array, or empty object.
-handlebars::handlebars_helper!(is_empty: |v: Json| {
-    use handlebars::JsonValue;
-    match v {
-        JsonValue::Null => true,
-        JsonValue::String(s) => s.is_empty(),
-        JsonValue::Array(a) => a.is_empty(),
-        JsonValue::Object(o) => o.is_empty(),
-        _ => false,
+// The stylesheet compiled from `_style/{stem}.scss` for a top-level
+// `/{stem}.css`, or None when the URL names no such stylesheet. A real `.css`
+// file wins and is served as-is, so SCSS is only compiled when none exists.
+async fn compiled_stylesheet(
+    app: &App,
+    url: UrlPath<'_>,
+) -> Result<Option<Arc<Stylesheet>>, MyError> {
+    let Some(name) = url.path().strip_prefix('/').filter(|p| !p.contains('/')) else {
+        return Ok(None);
+    };
+    let Some(stem) = name.strip_suffix(".css") else {
+        return Ok(None);
+    };
+    let css_exists = tokio::fs::try_exists(app.root.join(name))
+        .await
+        .unwrap_or(false);
+    if css_exists || !valid_asset_name(stem) {
+        return Ok(None);
     }
-});
-
-// Frontmatter/URL supplied names must be bare identifiers, no path traversal.
-fn valid_asset_name(name: &str) -> bool {
+    let scss_path = app.root.join(format!("_style/{stem}.scss"));
+    // Don't create cache entries for missing stylesheets.
+    if !tokio::fs::try_exists(&scss_path).await.unwrap_or(false) {
+        return Err(MyError::NotFound);
+    }
+    match app.styles.load(&scss_path).await {
+        Ok(css) => Ok(Some(css)),
+        Err(_) => Err(MyError::InvalidScss),
+    }
+}
+
+// Short hex form of a content hash, as used in `?v=` asset URLs.
+fn fingerprint(hash: u128) -> String {
+    format!("{:016x}", (hash >> 64) as u64)
+}
+
+// Immutable only when the request's `?v=` matches the current contents.
+fn cache_policy(query: Option<&str>, hash: u128) -> CachePolicy {
+    if query_param(query, "v") == Some(fingerprint(hash).as_str()) {
+        CachePolicy::Immutable
+    } else {
+        CachePolicy::Revalidate
+    }
+}
+
+// Value of the first `name=value` pair of a raw query string (not decoded).
+fn query_param<'a>(query: Option<&'a str>, name: &str) -> Option<&'a str> {
+    query?.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        (key == name).then_some(value)
+    })
+}
+
+async fn render_page(
+    app: &Arc<App>,
+    config: &Config,
+    url: UrlPath<'_>,
+    query: Option<&str>,
+    authorization: Option<&str>,
+    request: &RequestVars,
+) -> MyResult {
+    let page_path = app.root.join(format!("{}page.md", url.relative_path()));
+    // Don't create cache entries for missing pages.
+    if !tokio::fs::try_exists(&page_path).await.unwrap_or(false) {
+        return Err(MyError::NotFound);
+    }
+    let page = match app.pages.load(&app.root, &page_path).await {
+        Ok(page) => page,
+        // The file exists (checked above), so a load failure is a bad page.
+        Err(_) => return Err(MyError::InvalidPage),
+    };
+
+    // Drafts and pages outside their schedule don't exist (expired ones are
+    // gone), except for editors and holders of a preview link.
+    let now = app.now(config);
+    let schedule = Schedule::of(&page, now).map_err(|err| {
+        error!("invalid schedule in `{page_path}`: {err}");
+        MyError::InvalidPage
+    })?;
+    let hidden = page.draft() || schedule != Schedule::Live;
+    let mut preview_url = None;
+    if hidden {
+        let unix_now = now.timestamp().max(0) as u64;
+        let user = authenticated_user(config, authorization);
+        if config.drafts.is_editor(user.as_deref()) {
+            preview_url = config.drafts.token(url.path(), unix_now).map(|token| {
+                let base_url = config.base_url.as_deref().unwrap_or_default();
+                format!("{base_url}{}?preview={token}", url.path())
+            });
+        } else if !query_param(query, "preview")
+            .is_some_and(|token| config.drafts.verify(url.path(), token, unix_now))
+        {
+            return Err(match schedule {
+                Schedule::Expired if !page.draft() => MyError::Gone,
+                _ => MyError::NotFound,
+            });
+        }
+    }
+
+    // A pure redirect page has no contents of its own.
+    if let Some(location) = page.redirect() {
+        let valid = (location.starts_with('/')
+            || location.starts_with("https://")
+            || location.starts_with("http://"))
+            // It becomes a `Location` header.
+            && HeaderValue::from_str(location).is_ok();
+        if !valid {
+            error!(
+                "invalid redirect `{}` in `{page_path}`",
+                location.escape_debug()
+            );
+            return Err(MyError::InvalidPage);
+        }
+        return Ok(MyResponse::Redirect(location.to_owned(), 301));
+    }
+
+    let status = match page.status() {
+        Ok(None) => 200,
+        // Statuses that carry no body (204, 304...) or mean something else
+        // (1xx, 3xx) make no sense for a rendered page.
+        Ok(Some(status @ (200..=203 | 206..=299 | 400..=599))) => status,
+        Ok(Some(status)) => {
+            error!("invalid status {status} in `{page_path}`");
+            return Err(MyError::InvalidPage);
+        }
+        Err(err) => {
+            error!("{err} in `{page_path}`");
+            return Err(MyError::InvalidPage);
+        }
+    };
+    let content_type = page.content_type().map(str::to_owned);
+    if content_type
+        .as_deref()
+        .is_some_and(|mime| mime.is_empty() || !mime.bytes().all(|b| (0x20..0x7f).contains(&b)))
+    {
+        error!("invalid content_type in `{page_path}`");
+        return Err(MyError::InvalidPage);
+    }
+
+    let collection = match Collection::new(url.path(), &page) {
+        None => None,
+        Some(Err(err)) => {
+            error!("invalid collection in `{page_path}`: {err}");
+            return Err(MyError::InvalidPage);
+        }
+        Some(Ok(collection)) => {
+            let number = match query_param(query, "page") {
+                Some(number) => number.parse().map_err(|_| MyError::NotFound)?,
+                None => 1,
+            };
+            Some((collection, number))
+        }
+    };
+    let user = authenticated_user(config, authorization);
+    let site = app.site.load(&app.root, &app.pages).await;
+    let inputs = ResponseInputs {
+        page: page.clone(),
+        site: site.clone(),
+        layout: LayoutInputs::load(app, &page).await?,
+    };
+    // Only what the layout reads of the request varies its response; what
+    // clients vary at will (the whole query, languages) is not cached.
+    let fields = inputs.layout.request_fields();
+    let request_vars = request.to_json(fields);
+    let key = (!hidden && fields.cacheable()).then(|| ResponseKey {
+        url: url.path().to_owned(),
+        number: collection.as_ref().map(|(_, number)| *number),
+        user: user.clone().filter(|_| !config.protected.is_empty()),
+        request: request.cache_key(fields, &inputs.layout.query_params()),
+    });
+    if let Some(key) = &key {
+        if let Some(rendered) = app.responses.get(app, key, &inputs, now).await {
+            return Ok(MyResponse::Page(rendered));
+        }
+    }
+
+    // Menus and listings only name pages the user may see.
+    let visible = |entry: &SitePage| {
+        published(&entry.page, now) && user_allowed(config, &entry.url, user.as_deref())
+    };
+    let nav = site.nav(url.path(), visible);
+    let mut extra = Map::new();
+    extra.insert("url".into(), Json::from(url.path()));
+    extra.insert("request".into(), Json::Object(request_vars));
+    if let Some(preview_url) = preview_url {
+        extra.insert("preview_url".into(), Json::String(preview_url));
+    }
+    let links = [
+        ("breadcrumbs", nav.breadcrumbs),
+        ("children", nav.children),
+        ("siblings", nav.siblings),
+    ];
+    for (name, links) in links {
+        let links =
+            serde_json::to_value(links).map_err(|err| MyError::Internal(err.to_string()))?;
+        extra.insert(name.into(), links);
+    }
+    // Only for pages listed in a collection; null at either end.
+    if let Some((prev, next)) = neighbours(&site, url.path(), visible) {
+        for (name, link) in [("prev", prev), ("next", next)] {
+            let link =
+                serde_json::to_value(link).map_err(|err| MyError::Internal(err.to_string()))?;
+            extra.insert(name.into(), link);
+        }
+    }
+
+    if !config.taxonomies.is_empty() {
+        let related = taxonomy::related(&site, &config.taxonomies, url.path(), &page, visible);
+        extra.insert(
+            "related".into(),
+            Json::Array(related.into_iter().map(entry_fields).collect()),
+        );
+    }
+
+    if let Some((collection, number)) = collection {
+        let listing = collection
+            .listing(&site, url.path(), number, visible)
+            .ok_or(MyError::NotFound)?;
+        extra.insert("collection".into(), listing);
+    }
+
+    let (body, assets) = render_inputs(app, &page_path, &page, &inputs.layout, extra).await?;
+    if hidden {
+        // Kept out of caches and search engines, whoever may see it.
+        let rendered = MyResponse::Page(Rendered {
+            status,
+            content_type,
+            policy: CachePolicy::Private,
+            ..Rendered::html(body)
+        });
+        return Ok(MyResponse::Noindex(Box::new(rendered)));
+    }
+    let rendered = Rendered {
+        status,
+        content_type,
+        etag: Some(etag(&body)),
+        ..Rendered::html(body)
+    };
+    if let Some(key) = key {
+        // Pages appear in (or leave) navigation and listings with time.
+        let pages = site.pages().iter().map(|entry| &*entry.page);
+        let valid_until = next_change(pages.chain([&*page]), now);
+        let response = CachedResponse {
+            inputs,
+            assets: assets.into(),
+            valid_until,
+            rendered: rendered.clone(),
+            last_access: Instant::now(),
+        };
+        app.responses.insert(key, response);
+    }
+    Ok(MyResponse::Page(rendered))
+}
+
+// What rendering a page through its layout depends on, besides the page and
+// the variables its request adds.
+#[derive(Clone)]
+struct LayoutInputs {
+    tpl_path: Utf8PathBuf,
+    tpl: Arc<Template>,
+    // Partials the layout includes.
+    partials: Vec<Partial>,
+    snippets: Vec<Arc<Template>>,
+    // Partials the snippets include.
+    snippet_partials: Vec<Partial>,
+    site: SiteVars,
+}
+
+impl LayoutInputs {
+    async fn load(app: &App, page: &Page) -> Result<Self, MyError> {
+        let template = page.template();
+        if !valid_asset_name(template) {
+            return Err(MyError::NotFound);
+        }
+        let tpl_path = app.root.join(format!("_style/{template}.html"));
+        let tpl = load_template(app, &tpl_path, MyError::CannotRead).await?;
+        let partials = load_partials(app, std::slice::from_ref(&tpl)).await?;
+        let mut snippets = Vec::new();
+        load_snippet_templates(app, page.body(), &mut snippets).await?;
+        let snippet_partials = load_partials(app, &snippets).await?;
+        Ok(LayoutInputs {
+            tpl_path,
+            tpl,
+            partials,
+            snippets,
+            snippet_partials,
+            site: SiteVars::load(app).await,
+        })
+    }
+
+    // What the layout and its partials read of `request`. Snippets do not
+    // see it: their output is cached per page.
+    fn request_fields(&self) -> RequestFields {
+        self.partials
+            .iter()
+            .map(|(_, partial)| partial.request_fields())
+            .fold(self.tpl.request_fields(), RequestFields::union)
+    }
+
+    // The query parameters they read by name.
+    fn query_params(&self) -> Vec<String> {
+        let mut params = self.tpl.query_params().to_vec();
+        for (_, partial) in &self.partials {
+            params.extend(partial.query_params().iter().cloned());
+        }
+        params
+    }
+
+    fn same(&self, other: &LayoutInputs) -> bool {
+        Arc::ptr_eq(&self.tpl, &other.tpl)
+            && same_partials(&self.partials, &other.partials)
+            && same_templates(&self.snippets, &other.snippets)
+            && same_partials(&self.snippet_partials, &other.snippet_partials)
+            && self.site.identical(&other.site)
+    }
+}
+
+// Render a page's body and snippets, then its layout. `extra` variables are
+// added to (and override) the page's front matter in the layout.
+async fn render_with_layout(
+    app: &Arc<App>,
+    page_path: &Utf8Path,
+    page: &Arc<Page>,
+    extra: Map<String, Json>,
+) -> Result<String, MyError> {
+    let inputs = LayoutInputs::load(app, page).await?;
+    let (body, _) = render_inputs(app, page_path, page, &inputs, extra).await?;
+    Ok(body)
+}
+
+// Also returns the `asset` URLs the layout rendered, as (requested,
+// rendered) pairs.
+async fn render_inputs(
+    app: &Arc<App>,
+    page_path: &Utf8Path,
+    page: &Arc<Page>,
+    inputs: &LayoutInputs,
+    extra: Map<String, Json>,
+) -> Result<(String, Vec<(String, String)>), MyError> {
+    let contents = app
+        .rendered
+        .load(
+            app,
+            page_path,
+            page.clone(),
+            inputs.snippets.clone(),
+            inputs.snippet_partials.clone(),
+            inputs.site.clone(),
+        )
+        .await?;
+    let mut fields = page.fields().clone();
+    fields.extend(extra);
+    fields.insert("contents".into(), Json::String(contents));
+
+    let page_name = format!(
+        "`{}`",
+        page_path.strip_prefix(&app.root).unwrap_or(page_path)
+    );
+    render_compiled(
+        app,
+        &inputs.tpl_path,
+        inputs.tpl.clone(),
+        inputs.partials.clone(),
+        &inputs.site,
+        fields,
+        &page_name,
+    )
+    .await
+}
+
+// Render a layout-level template (page layouts, error pages) found at
+// `path`, with the helpers available to them, for the request to `url`.
+async fn render_layout(
+    app: &Arc<App>,
+    path: &Utf8Path,
+    tpl: Arc<Template>,
+    context: Map<String, Json>,
+    url: &str,
+) -> Result<String, MyError> {
+    let partials = load_partials(app, std::slice::from_ref(&tpl)).await?;
+    let site = SiteVars::load(app).await;
+    let page_name = format!("`{url}`");
+    let (body, _) = render_compiled(app, path, tpl, partials, &site, context, &page_name).await?;
+    Ok(body)
+}
+
+thread_local! {
+    // `asset` URLs rendered so far by the layout rendering on this thread.
+    static RENDERED_ASSETS: RefCell<Vec<(String, String)>> = const { RefCell::new(Vec::new()) };
+}
+
+// `render_layout` with its inputs loaded, for `page` (as logged); also
+// returns the `asset` URLs rendered.
+async fn render_compiled(
+    app: &Arc<App>,
+    path: &Utf8Path,
+    tpl: Arc<Template>,
+    partials: Vec<Partial>,
+    site: &SiteVars,
+    mut context: Map<String, Json>,
+    page: &str,
+) -> Result<(String, Vec<(String, String)>), MyError> {
+    context.insert("site".into(), site.to_json());
+    app.layout_hbs.get_or_init(|| {
+        let runtime = tokio::runtime::Handle::current();
+        Registry::new(layout_helpers(Arc::downgrade(app), runtime))
+    });
+    // Off the runtime: helpers such as `asset` block on cache lookups.
+    let app = app.clone();
+    let path = path.to_owned();
+    let scripts = site.scripts.clone();
+    let strict = site.config.strict_templates;
+    let page = page.to_owned();
+    tokio::task::spawn_blocking(move || {
+        let registry = app.layout_hbs.get().expect("set up above");
+        let rendering = registry.prepare(
+            &scripts,
+            strict,
+            partials
+                .iter()
+                .map(|(name, partial)| (name.as_str(), partial))
+                .chain([(path.as_str(), &tpl)]),
+        );
+        RENDERED_ASSETS.with_borrow_mut(Vec::clear);
+        let body = rendering.render(path.as_str(), &context).map_err(|err| {
+            let diagnostic = rendering.diagnose(&err, &app.root, &page);
+            error!("{diagnostic}");
+            MyError::Template(Box::new(diagnostic))
+        })?;
+        Ok((body, RENDERED_ASSETS.take()))
+    })
+    .await
+    .map_err(|err| {
+        error!("layout rendering task failed: {err}");
+        MyError::Internal("cannot render page".into())
+    })?
+}
+
+// The page for an error `status`: `_style/{status}.html` rendered with the
+// variables below, or `_style/{status}.md` rendered like a page through its
+// layout, else the same with `error` in place of the status. A 404 first
+// looks for the `_404.md` page nearest to `path`. Without any of these, or
+// when rendering one fails, a plain-text `message`.
+pub async fn error_page(app: &Arc<App>, status: u16, message: &str, path: &str) -> Rendered {
+    let mut vars = Map::new();
+    vars.insert("status".into(), Json::from(status));
+    vars.insert("message".into(), Json::from(message));
+    vars.insert("path".into(), Json::from(path));
+
+    let plain = || Rendered {
+        status,
+        content_type: Some("text/plain; charset=utf-8".into()),
+        ..Rendered::html(message.to_owned())
+    };
+    let rendered = |body| Rendered {
+        status,
+        ..Rendered::html(body)
+    };
+
+    let mut candidates = Vec::new();
+    if status == 404 {
+        candidates.extend(
+            section_dirs(path)
+                .into_iter()
+                .map(|dir| app.root.join(dir).join("_404.md")),
+        );
+    }
+    for name in [status.to_string(), "error".to_string()] {
+        candidates.push(app.root.join(format!("_style/{name}.html")));
+        candidates.push(app.root.join(format!("_style/{name}.md")));
+    }
+
+    for candidate in candidates {
+        // Cached, since a flood of bad requests probes the same files.
+        if !app.probes.exists(&candidate).await {
+            continue;
+        }
+        if candidate.extension() == Some("html") {
+            let Ok(tpl) = app.templates.load(&candidate).await else {
+                return plain();
+            };
+            // Failures are logged.
+            return match render_layout(app, &candidate, tpl, vars, path).await {
+                Ok(body) => rendered(body),
+                Err(_) => plain(),
+            };
+        }
+        let Ok(page) = app.pages.load(&app.root, &candidate).await else {
+            return plain();
+        };
+        return match render_with_layout(app, &candidate, &page, vars).await {
+            Ok(body) => rendered(body),
+            Err(err) => {
+                error!("cannot render error page `{candidate}`: {err:?}");
+                plain()
+            }
+        };
+    }
+    plain()
+}
+
+// Directories (relative to the site root) enclosing what `path` names,
+// nearest first and ending with the root itself. Stops short of any
+// component that could not be served, so nothing outside the site is probed.
+fn section_dirs(path: &str) -> Vec<String> {
+    let mut components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+    // The requested page or file itself is not one of its sections.
+    components.pop();
+    let valid = components
+        .iter()
+        .take_while(|c| !c.starts_with(['.', '_']))
+        .count();
+    (0..=valid)
+        .rev()
+        .map(|n| components[..n].join("/"))
+        .collect()
+}
+
+// Most partials one template may pull in, directly or not.
+const MAX_PARTIALS: usize = 64;
+
+// The partials `templates` include, and those they include in turn: `{{> name}}`
+// is `_style/partials/{name}.html`, else the layout `_style/{name}.html` (a
+// parent layout). Names matching no file are left to Handlebars, as they may
+// be inline partials (`{{#*inline "name"}}`).
+async fn load_partials(app: &App, templates: &[Arc<Template>]) -> Result<Vec<Partial>, MyError> {
+    let mut partials: Vec<Partial> = Vec::new();
+    let mut seen: Vec<String> = Vec::new();
+    let mut pending: Vec<String> = templates
+        .iter()
+        .flat_map(|template| template::partial_names(template.source()))
+        .map(str::to_owned)
+        .collect();
+    while let Some(name) = pending.pop() {
+        if seen.contains(&name) || !valid_asset_name(&name) {
+            continue;
+        }
+        if seen.len() == MAX_PARTIALS {
+            error!("more than {MAX_PARTIALS} partials, not loading `{name}`");
+            return Err(MyError::CannotRead);
+        }
+        seen.push(name.clone());
+        let mut path = None;
+        for candidate in [
+            app.root.join(format!("_style/partials/{name}.html")),
+            app.root.join(format!("_style/{name}.html")),
+        ] {
+            if app.probes.exists(&candidate).await {
+                path = Some(candidate);
+                break;
+            }
+        }
+        let Some(path) = path else {
+            continue;
+        };
+        let partial = load_template(app, &path, MyError::CannotRead).await?;
+        pending.extend(
+            template::partial_names(partial.source())
+                .into_iter()
+                .map(str::to_owned),
+        );
+        partials.push((name, partial));
+    }
+    Ok(partials)
+}
+
+// A template from its cache. One that exists but does not compile is a
+// `MyError::Template` (the cache has logged why), anything else `missing`.
+async fn load_template(
+    app: &App,
+    path: &Utf8Path,
+    missing: MyError,
+) -> Result<Arc<Template>, MyError> {
+    match app.templates.load(path).await {
+        Ok(template) => Ok(template),
+        Err((_, err)) if tokio::fs::try_exists(path).await.unwrap_or(false) => {
+            let diagnostic = Diagnostic::load(&app.root, path, format!("{err:#}"));
+            Err(MyError::Template(Box::new(diagnostic)))
+        }
+        Err(_) => Err(missing),
+    }
+}
+
+fn load_snippet_templates<'a>(
+    app: &'a App,
+    document: &'a Document,
+    templates: &'a mut Vec<Arc<Template>>,
+) -> Pin<Box<dyn Future<Output = Result<(), MyError>> + Send + 'a>> {
+    Box::pin(async move {
+        for block in document.blocks() {
+            let Block::Snippet {
+                name, body, line, ..
+            } = block
+            else {
+                continue;
+            };
+            let path = app.root.join(format!("_style/snippets/{name}.html"));
+            if !tokio::fs::try_exists(&path).await.unwrap_or(false) {
+                error!("missing snippet `{path}` used at line {line}");
+                return Err(MyError::InvalidPage);
+            }
+            let template = load_template(app, &path, MyError::InvalidPage)
+                .await
+                .map_err(|err| snippet_error(app, err))?;
+            templates.push(template);
+            load_snippet_templates(app, body, templates).await?;
+        }
+        Ok(())
+    })
+}
+
+// `page_name` is the page as logged; `templates` are its snippets, as
+// `load_snippet_templates` loads them.
+fn render_document(
+    app: &App,
+    page_name: &str,
+    document: &Document,
+    page: &serde_json::Map<String, Json>,
+    site: &SiteVars,
+    partials: &[Partial],
+    templates: &[Arc<Template>],
+) -> Result<String, MyError> {
+    let mut paths = Vec::new();
+    snippet_paths(&app.root, document, &mut paths);
+    if paths.len() != templates.len() {
+        return Err(MyError::Internal(
+            "snippet rendering dependencies do not match".into(),
+        ));
+    }
+    let rendering = app.snippet_hbs.prepare(
+        &site.scripts,
+        site.config.strict_templates,
+        partials
+            .iter()
+            .map(|(name, partial)| (name.as_str(), partial))
+            .chain(paths.iter().map(String::as_str).zip(templates)),
+    );
+    let site = site.to_json();
+    render_document_with(&rendering, &app.root, page_name, document, page, &site)
+        .map_err(|err| snippet_error(app, err))
+}
+
+// Outside development, a broken snippet is an invalid page, as it was before
+// template diagnostics: the details are in the log, not in the browser.
+fn snippet_error(app: &App, err: MyError) -> MyError {
+    match err {
+        MyError::Template(_) if !app.development() => MyError::InvalidPage,
+        err => err,
+    }
+}
+
+// The body of the page at `path` (a `page.md`), its snippets rendered afresh
+// past the rendered-page cache: what `benches/snippets.rs` times.
+#[doc(hidden)]
+pub async fn render_body(app: &App, path: &Utf8Path) -> Result<String, MyError> {
+    let page = app
+        .pages
+        .load(&app.root, path)
+        .await
+        .map_err(|_| MyError::InvalidPage)?;
+    let mut snippets = Vec::new();
+    load_snippet_templates(app, page.body(), &mut snippets).await?;
+    let partials = load_partials(app, &snippets).await?;
+    let site = SiteVars::load(app).await;
+    render_document(
+        app,
+        path.as_str(),
+        page.body(),
+        page.fields(),
+        &site,
+        &partials,
+        &snippets,
+    )
+}
+
+// The paths the snippets of `document` are registered under, in the order
+// `load_snippet_templates` loads them.
+fn snippet_paths(root: &Utf8Path, document: &Document, paths: &mut Vec<String>) {
+    for block in document.blocks() {
+        if let Block::Snippet { name, body, .. } = block {
+            paths.push(
+                root.join(format!("_style/snippets/{name}.html"))
+                    .into_string(),
+            );
+            snippet_paths(root, body, paths);
+        }
+    }
+}
+
+fn render_document_with(
+    rendering: &Rendering,
+    root: &Utf8Path,
+    page_name: &str,
+    document: &Document,
+    page: &serde_json::Map<String, Json>,
+    site: &Json,
+) -> Result<String, MyError> {
+    let mut expanded = String::new();
+    for block in document.blocks() {
+        match block {
+            Block::Markdown(src) => expanded.push_str(src),
+            Block::Snippet {
+                name,
+                params,
+                body,
+                line,
+            } => {
+                let body = render_document_with(rendering, root, page_name, body, page, site)?;
+                let mut context = params.clone();
+                context.insert("contents".into(), Json::String(body));
+                context.insert("page".into(), Json::Object(page.clone()));
+                context.insert("site".into(), site.clone());
+
+                let path = root.join(format!("_style/snippets/{name}.html"));
+                let html = rendering.render(path.as_str(), &context).map_err(|err| {
+                    let used_at = format!("{page_name} line {line}");
+                    let diagnostic = rendering.diagnose(&err, root, &used_at);
+                    error!("{diagnostic}");
+                    MyError::Template(Box::new(diagnostic))
+                })?;
+                expanded.push_str("\n\n");
+                expanded.push_str(&strip_html_comments(&html));
+                expanded.push_str("\n\n");
+            }
+        }
+    }
+    Ok(strip_html_comments(&render_markdown(&expanded)))
+}
+
+// What layouts and snippets see as `site`: the `[site]` table of the config
+// plus `data` from `_data/`. Never the rest of the config. Also the helper
+// scripts they may call.
+#[derive(Clone)]
+struct SiteVars {
+    config: Arc<Config>,
+    data: Arc<Json>,
+    scripts: Scripts,
+}
+
+impl SiteVars {
+    async fn load(app: &App) -> Self {
+        // An invalid config leaves error pages without `[site]` values.
+        let config = app.config.load_optional().await.unwrap_or_default();
+        SiteVars {
+            config,
+            data: app.data.load(&app.root).await,
+            scripts: app.scripts.load(&app.root).await,
+        }
+    }
+
+    // Whether templates would see the same values (and helpers).
+    fn same(&self, other: &SiteVars) -> bool {
+        Arc::ptr_eq(&self.data, &other.data)
+            && Arc::ptr_eq(&self.scripts, &other.scripts)
+            && self.config.site == other.config.site
+            && self.config.strict_templates == other.config.strict_templates
+    }
+
+    // Whether both come from the same config, data and script files, so no
+    // setting at all has changed since.
+    fn identical(&self, other: &SiteVars) -> bool {
+        Arc::ptr_eq(&self.data, &other.data)
+            && Arc::ptr_eq(&self.scripts, &other.scripts)
+            && Arc::ptr_eq(&self.config, &other.config)
+    }
+
+    fn to_json(&self) -> Json {
+        let mut site = self.config.site.clone();
+        site.insert("data".into(), (*self.data).clone());
+        Json::Object(site)
+    }
+}
+
+fn snippet_helpers() -> handlebars::Handlebars<'static> {
+    let mut hbs = handlebars::Handlebars::new();
+    helpers::register(&mut hbs);
+    hbs
+}
+
+// Snippet helpers, plus `asset`: its URLs change with the files, so it is
+// left out of the (cached) Markdown rendering.
+fn layout_helpers(
+    app: Weak<App>,
+    runtime: tokio::runtime::Handle,
+) -> handlebars::Handlebars<'static> {
+    let mut hbs = snippet_helpers();
+    hbs.register_helper(helpers::ASSET, Box::new(AssetHelper { app, runtime }));
+    hbs
+}
+
+// `{{asset "/default.css"}}` -> `/default.css?v=<fingerprint>`, so layouts can
+// link assets that are then served as immutable.
+struct AssetHelper {
+    // Weak: the app owns the helper, through its registry.
+    app: Weak<App>,
+    runtime: tokio::runtime::Handle,
+}
+
+impl AssetHelper {
+    fn url(&self, h: &handlebars::Helper<'_>) -> Result<String, handlebars::RenderError> {
+        let url = h.param(0).and_then(|param| param.value().as_str()).ok_or(
+            handlebars::RenderErrorReason::ParamNotFoundForIndex("asset", 0),
+        )?;
+        let rendered = match self.app.upgrade() {
+            Some(app) => self.runtime.block_on(app.asset_url(url)),
+            None => url.to_owned(),
+        };
+        RENDERED_ASSETS.with_borrow_mut(|assets| assets.push((url.to_owned(), rendered.clone())));
+        Ok(rendered)
+    }
+}
+
+impl handlebars::HelperDef for AssetHelper {
+    // As a subexpression, e.g. `(asset "/logo.png")`.
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &handlebars::Helper<'rc>,
+        _: &'reg handlebars::Handlebars<'reg>,
+        _: &'rc handlebars::Context,
+        _: &mut handlebars::RenderContext<'reg, 'rc>,
+    ) -> Result<handlebars::ScopedJson<'rc>, handlebars::RenderError> {
+        Ok(Json::String(self.url(h)?).into())
+    }
+
+    // Written directly so `?v=` is not entity-escaped like `{{...}}` output;
+    // only the characters that matter inside an attribute are.
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &handlebars::Helper<'rc>,
+        _: &'reg handlebars::Handlebars<'reg>,
+        _: &'rc handlebars::Context,
+        _: &mut handlebars::RenderContext<'reg, 'rc>,
+        out: &mut dyn handlebars::Output,
+    ) -> handlebars::HelperResult {
+        let url = self.url(h)?;
+        let mut escaped = String::with_capacity(url.len());
+        for c in url.chars() {
+            match c {
+                '&' => escaped.push_str("&amp;"),
+                '<' => escaped.push_str("&lt;"),
+                '>' => escaped.push_str("&gt;"),
+                '"' => escaped.push_str("&quot;"),
+                '\'' => escaped.push_str("&#x27;"),
+                c => escaped.push(c),
+            }
+        }
+        out.write(&escaped)?;
+        Ok(())
+    }
+}
+
+// Frontmatter/URL supplied names must be bare identifiers, no path traversal.
+pub fn valid_asset_name(name: &str) -> bool {
     !name.is_empty()
         && name
             .chars()
@@ -501,21 +2086,30 @@ fn parse_basic(header: &str) -> Option<(String, String)> {
     Some((user.to_owned(), pass.to_owned()))
 }
 
+// The user named by the credentials, when the password is correct.
+fn authenticated_user(config: &Config, authorization: Option<&str>) -> Option<String> {
+    use subtle::ConstantTimeEq;
+    let (user, pass) = authorization.and_then(parse_basic)?;
+    config
+        .users
+        .get(&user)
+        .is_some_and(|p| p.as_bytes().ct_eq(pass.as_bytes()).into())
+        .then_some(user)
+}
+
 // Access is allowed unless the path is protected and the credentials name an
 // allowed user with the correct password.
 fn authorized(config: &Config, path: &str, authorization: Option<&str>) -> bool {
-    use subtle::ConstantTimeEq;
     let Some(allowed) = allowed_users(config, path) else {
         return true;
     };
-    let Some((user, pass)) = authorization.and_then(parse_basic) else {
-        return false;
-    };
-    allowed.iter().any(|u| u == &user)
-        && config
-            .users
-            .get(&user)
-            .is_some_and(|p| p.as_bytes().ct_eq(pass.as_bytes()).into())
+    authenticated_user(config, authorization).is_some_and(|user| allowed.contains(&user))
+}
+
+// Same as `authorized`, for an already authenticated user.
+fn user_allowed(config: &Config, path: &str, user: Option<&str>) -> bool {
+    allowed_users(config, path)
+        .is_none_or(|allowed| user.is_some_and(|user| allowed.iter().any(|u| u == user)))
 }
 
 #[cfg(test)]
@@ -536,53 +2130,147 @@ mod tests {
     async fn rendered_cache_tracks_page_and_snippets() {
         use std::sync::atomic::Ordering;
 
-        let cache = RenderedPages::default();
+        let site = |data| SiteVars {
+            config: Arc::default(),
+            data: Arc::new(data),
+            scripts: Scripts::default(),
+        };
+        let data = site(Json::Null);
         let root = Utf8Path::new("/tmp/rendered-cache-test");
+        let app = Arc::new(App::new(root.to_owned()));
+        let cache = &app.rendered;
         let path = root.join("page.md");
         let page = Arc::new(Page::compute(":::card\n\n**first**\n:::\n").unwrap());
-        let template = Arc::new(Template("<aside>one {{{contents}}}</aside>".into()));
+        let template = Arc::new(Template::compute("<aside>one {{{contents}}}</aside>").unwrap());
 
         let html = cache
-            .load(&path, root, page.clone(), vec![template.clone()])
+            .load(
+                &app,
+                &path,
+                page.clone(),
+                vec![template.clone()],
+                Vec::new(),
+                data.clone(),
+            )
             .await
             .unwrap();
         assert!(html.contains("one <p><strong>first</strong>"));
         assert_eq!(cache.renders.load(Ordering::Relaxed), 1);
 
         cache
-            .load(&path, root, page.clone(), vec![template])
+            .load(
+                &app,
+                &path,
+                page.clone(),
+                vec![template],
+                Vec::new(),
+                data.clone(),
+            )
             .await
             .unwrap();
         assert_eq!(cache.renders.load(Ordering::Relaxed), 1);
 
-        let template = Arc::new(Template("<aside>two {{{contents}}}</aside>".into()));
+        let template = Arc::new(Template::compute("<aside>two {{{contents}}}</aside>").unwrap());
         let html = cache
-            .load(&path, root, page, vec![template.clone()])
+            .load(
+                &app,
+                &path,
+                page.clone(),
+                vec![template.clone()],
+                Vec::new(),
+                data.clone(),
+            )
             .await
             .unwrap();
         assert!(html.contains("two <p><strong>first</strong>"));
         assert_eq!(cache.renders.load(Ordering::Relaxed), 2);
 
         let page = Arc::new(Page::compute(":::card\n\n**second**\n:::\n").unwrap());
-        let html = cache.load(&path, root, page, vec![template]).await.unwrap();
+        let html = cache
+            .load(
+                &app,
+                &path,
+                page.clone(),
+                vec![template.clone()],
+                Vec::new(),
+                data,
+            )
+            .await
+            .unwrap();
         assert!(html.contains("two <p><strong>second</strong>"));
         assert_eq!(cache.renders.load(Ordering::Relaxed), 3);
 
-        cache.sweep(Duration::ZERO);
-        assert!(cache.map.is_empty());
-    }
-
-    // Runs against the checked-in `example_site` (cargo test CWD = crate root).
+        // New site data or `[site]` values re-render pages with snippets only.
+        let data = site(serde_json::json!({ "a": 1 }));
+        cache
+            .load(
+                &app,
+                &path,
+                page.clone(),
+                vec![template.clone()],
+                Vec::new(),
+                data.clone(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(cache.renders.load(Ordering::Relaxed), 4);
+        let titled = SiteVars {
+            config: Arc::new(Config::compute("[site]\ntitle = \"T\"").unwrap()),
+            ..data.clone()
+        };
+        cache
+            .load(&app, &path, page, vec![template], Vec::new(), titled)
+            .await
+            .unwrap();
+        assert_eq!(cache.renders.load(Ordering::Relaxed), 5);
+        let plain = root.join("plain/page.md");
+        let page = Arc::new(Page::compute("Plain.").unwrap());
+        for data in [data, site(Json::Null)] {
+            cache
+                .load(&app, &plain, page.clone(), Vec::new(), Vec::new(), data)
+                .await
+                .unwrap();
+        }
+        assert_eq!(cache.renders.load(Ordering::Relaxed), 6);
+
+        cache.sweep(Duration::ZERO);
+        assert!(cache.map.is_empty());
+    }
+
+    // An anonymous GET for `path`.
+    fn get(path: &str) -> MyRequest<'_> {
+        get_query(path, None)
+    }
+
+    fn get_query<'a>(path: &'a str, query: Option<&'a str>) -> MyRequest<'a> {
+        MyRequest::GET {
+            path,
+            query,
+            authorization: None,
+            peer: None,
+            forwarded_for: None,
+            host: None,
+            accept_language: None,
+        }
+    }
+
+    // A GET for `path` with an `Authorization` header.
+    fn get_as<'a>(path: &'a str, authorization: &'a str) -> MyRequest<'a> {
+        MyRequest::GET {
+            path,
+            query: None,
+            authorization: Some(authorization),
+            peer: None,
+            forwarded_for: None,
+            host: None,
+            accept_language: None,
+        }
+    }
+
+    // Runs against the checked-in `example_site` (cargo test CWD = crate root).
     async fn resp(path: &str) -> MyResult {
         let app = Arc::new(App::new("example_site".into()));
-        web(
-            app,
-            MyRequest::GET {
-                path,
-                authorization: None,
-            },
-        )
-        .await
+        web(app, get(path)).await
     }
 
     #[test]
@@ -599,7 +2287,11 @@ mod tests {
                 vec!["user1".to_string(), "user2".to_string()],
             ),
         ]);
-        let config = Config { protected, users };
+        let config = Config {
+            protected,
+            users,
+            ..Default::default()
+        };
         // base64 of "user1:pw1" and "user2:pw2".
         let u1 = Some("Basic dXNlcjE6cHcx");
         let u2 = Some("Basic dXNlcjI6cHcy");
@@ -630,7 +2322,7 @@ mod tests {
     #[tokio::test]
     async fn renders_home() {
         match resp("/").await.unwrap() {
-            MyResponse::Html(h) => {
+            MyResponse::Page(Rendered { body: h, .. }) => {
                 assert!(h.contains("Hello"));
                 assert!(h.contains("<strong>A snippet</strong>"));
                 assert!(h.contains("contains <strong>Markdown</strong>"));
@@ -641,160 +2333,1479 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn renders_per_page_template() {
-        match resp("/about/").await.unwrap() {
-            MyResponse::Html(h) => assert!(h.contains("wide")),
-            _ => panic!("expected html"),
-        }
+    async fn renders_per_page_template() {
+        match resp("/about/").await.unwrap() {
+            MyResponse::Page(Rendered { body: h, .. }) => assert!(h.contains("wide")),
+            _ => panic!("expected html"),
+        }
+    }
+
+    #[tokio::test]
+    async fn renders_snippet_context_and_body() {
+        let dir = Utf8PathBuf::from_path_buf(
+            std::env::temp_dir().join(format!("flaty-snippet-{}", std::process::id())),
+        )
+        .unwrap();
+        std::fs::create_dir_all(dir.join("_style/snippets")).unwrap();
+        std::fs::write(dir.join("_style/default.html"), "{{{contents}}}").unwrap();
+        std::fs::write(
+            dir.join("_style/snippets/card.html"),
+            "<aside>{{label}} {{page.title}} {{{contents}}}</aside>",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("page.md"),
+            "---\ntitle = \"<Page>\"\n---\n[Link][target]\n\n:::card\nlabel = \"<Label>\"\n\n**Body**\n:::\n\n[target]: /ok\n",
+        )
+        .unwrap();
+
+        let app = Arc::new(App::new(dir.clone()));
+        let response = web(app, get("/")).await.unwrap();
+        let MyResponse::Page(Rendered { body: html, .. }) = response else {
+            panic!("expected html");
+        };
+        assert!(html.contains("&lt;Label&gt; &lt;Page&gt;"));
+        assert!(html.contains("<strong>Body</strong>"));
+        assert!(html.contains("<a href=\"/ok\">Link</a>"));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn preserves_comments_from_layouts() {
+        let dir = Utf8PathBuf::from_path_buf(
+            std::env::temp_dir().join(format!("flaty-comments-{}", std::process::id())),
+        )
+        .unwrap();
+        std::fs::create_dir_all(dir.join("_style")).unwrap();
+        std::fs::write(
+            dir.join("_style/default.html"),
+            "<!-- layout note --><main>{{{contents}}}</main>",
+        )
+        .unwrap();
+        std::fs::write(dir.join("page.md"), "Visible").unwrap();
+
+        let app = Arc::new(App::new(dir.clone()));
+        let MyResponse::Page(Rendered { body: html, .. }) = web(app, get("/")).await.unwrap()
+        else {
+            panic!("expected html");
+        };
+        assert!(html.contains("<!-- layout note -->"));
+        assert!(html.contains("<p>Visible</p>"));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn unclosed_comments_stop_at_expansion_boundaries() {
+        let dir = Utf8PathBuf::from_path_buf(
+            std::env::temp_dir().join(format!("flaty-comment-boundaries-{}", std::process::id())),
+        )
+        .unwrap();
+        std::fs::create_dir_all(dir.join("_style/snippets")).unwrap();
+        std::fs::write(
+            dir.join("_style/default.html"),
+            "<main>{{{contents}}}</main><footer>layout after</footer>",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("_style/snippets/card.html"),
+            "<span>snippet before</span>{{{value}}}snippet after",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("page.md"),
+            ":::card\nvalue = \"<!-- unfinished\"\n:::\n\nPage after snippet\n\n<script><!--\ncontent after comment",
+        )
+        .unwrap();
+
+        let app = Arc::new(App::new(dir.clone()));
+        let MyResponse::Page(Rendered { body: html, .. }) = web(app, get("/")).await.unwrap()
+        else {
+            panic!("expected html");
+        };
+        assert!(html.contains("snippet before"));
+        assert!(!html.contains("snippet after"), "{html}");
+        assert!(html.contains("Page after snippet"));
+        assert!(!html.contains("content after comment"));
+        assert!(html.contains("layout after"));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn missing_snippet_invalidates_page() {
+        let dir = Utf8PathBuf::from_path_buf(
+            std::env::temp_dir().join(format!("flaty-missing-snippet-{}", std::process::id())),
+        )
+        .unwrap();
+        std::fs::create_dir_all(dir.join("_style")).unwrap();
+        std::fs::write(dir.join("_style/default.html"), "{{{contents}}}").unwrap();
+        std::fs::write(dir.join("page.md"), ":::missing\n:::\n").unwrap();
+
+        let app = Arc::new(App::new(dir.clone()));
+        let response = web(app, get("/")).await;
+        assert!(matches!(response, Err(MyError::InvalidPage)));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn redirects_without_slash() {
+        match resp("/page1").await.unwrap() {
+            MyResponse::Redirect(loc, status) => {
+                assert_eq!(loc, "/page1/");
+                assert_eq!(status, 301);
+            }
+            _ => panic!("expected redirect"),
+        }
+    }
+
+    #[tokio::test]
+    async fn configured_redirects() {
+        let dir = Utf8PathBuf::from_path_buf(
+            std::env::temp_dir().join(format!("flaty-redirects-{}", std::process::id())),
+        )
+        .unwrap();
+        std::fs::create_dir_all(dir.join("new")).unwrap();
+        std::fs::create_dir_all(dir.join("_style")).unwrap();
+        std::fs::write(dir.join("_style/default.html"), "{{{contents}}}").unwrap();
+        std::fs::write(dir.join("new/page.md"), "New").unwrap();
+        std::fs::write(
+            dir.join("_config.toml"),
+            "[redirects]\n\"/_old/\" = { to = \"/new/\", status = 307 }\n\"/blog/*\" = \"/posts/*\"\n\
+             \"/café/\" = \"/new/\"\n",
+        )
+        .unwrap();
+
+        let app = Arc::new(App::new(dir.clone()));
+        let get = |path: &'static str| web(app.clone(), get(path));
+        // Sources need not be valid page URLs.
+        assert!(matches!(
+            get("/_old/").await,
+            Ok(MyResponse::Redirect(loc, 307)) if loc == "/new/"
+        ));
+        assert!(matches!(
+            get("/blog/2020/post/").await,
+            Ok(MyResponse::Redirect(loc, 301)) if loc == "/posts/2020/post/"
+        ));
+        // Request paths arrive percent-encoded.
+        assert!(matches!(
+            get("/caf%C3%A9/").await,
+            Ok(MyResponse::Redirect(loc, 301)) if loc == "/new/"
+        ));
+        assert!(matches!(get("/new/").await, Ok(MyResponse::Page(_))));
+
+        // A chained rule makes the config invalid: the site serves 404.
+        std::fs::write(
+            dir.join("_config.toml"),
+            "[redirects]\n\"/a/\" = \"/b/\"\n\"/b/\" = \"/new/\"\n",
+        )
+        .unwrap();
+        let app = Arc::new(App::new(dir.clone()));
+        assert!(app.check_config().await.is_err());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn page_aliases_and_redirect_pages() {
+        let dir = Utf8PathBuf::from_path_buf(
+            std::env::temp_dir().join(format!("flaty-aliases-{}", std::process::id())),
+        )
+        .unwrap();
+        for sub in ["_style", "docs/new", "moved", "broken", "a", "b", "_hidden"] {
+            std::fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        std::fs::write(dir.join("_style/default.html"), "{{{contents}}}").unwrap();
+        std::fs::write(
+            dir.join("docs/new/page.md"),
+            "---\naliases = [\"/old/path/\", \"/older.html\"]\n---\nNew",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("moved/page.md"),
+            "---\nredirect = \"https://example.com/\"\n---\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("broken/page.md"),
+            "---\nredirect = \"/x\\u0001\"\n---\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("a/page.md"), "---\naliases = [\"/same/\"]\n---\n").unwrap();
+        std::fs::write(dir.join("b/page.md"), "---\naliases = [\"/same/\"]\n---\n").unwrap();
+        // Unreachable pages claim nothing.
+        std::fs::write(
+            dir.join("_hidden/page.md"),
+            "---\naliases = [\"/secret/\"]\n---\n",
+        )
+        .unwrap();
+
+        let app = Arc::new(App::new(dir.clone()));
+        let get = |path: &'static str| web(app.clone(), get(path));
+        for old in ["/old/path/", "/old/path", "/older.html"] {
+            assert!(matches!(
+                get(old).await,
+                Ok(MyResponse::Redirect(loc, 301)) if loc == "/docs/new/"
+            ));
+        }
+        assert!(matches!(
+            get("/moved/").await,
+            Ok(MyResponse::Redirect(loc, 301)) if loc == "https://example.com/"
+        ));
+        // A target that cannot be a `Location` header.
+        assert!(matches!(get("/broken/").await, Err(MyError::InvalidPage)));
+        assert!(matches!(get("/same/").await, Err(MyError::InvalidPage)));
+        assert!(matches!(get("/secret/").await, Err(MyError::NotFound)));
+        assert!(matches!(get("/nope/").await, Err(MyError::NotFound)));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn page_status_and_content_type() {
+        let dir = Utf8PathBuf::from_path_buf(
+            std::env::temp_dir().join(format!("flaty-status-{}", std::process::id())),
+        )
+        .unwrap();
+        for sub in ["_style", "gone", "data", "bad"] {
+            std::fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        std::fs::write(
+            dir.join("_style/default.html"),
+            "<main>{{{contents}}}</main>",
+        )
+        .unwrap();
+        std::fs::write(dir.join("_style/json.html"), "{\"title\": \"{{title}}\"}").unwrap();
+        std::fs::write(dir.join("gone/page.md"), "---\nstatus = 410\n---\nGone").unwrap();
+        std::fs::write(
+            dir.join("data/page.md"),
+            "---\ntitle = \"T\"\ntemplate = \"json\"\ncontent_type = \"application/json\"\n---\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("bad/page.md"), "---\nstatus = 302\n---\n").unwrap();
+
+        let app = Arc::new(App::new(dir.clone()));
+        let get = |path: &'static str| web(app.clone(), get(path));
+        let Ok(MyResponse::Page(gone)) = get("/gone/").await else {
+            panic!("expected page");
+        };
+        assert_eq!(gone.status, 410);
+        assert!(gone.body.contains("<main><p>Gone</p>"));
+        assert!(gone.content_type.is_none());
+
+        let Ok(MyResponse::Page(data)) = get("/data/").await else {
+            panic!("expected page");
+        };
+        assert_eq!(data.status, 200);
+        assert_eq!(data.content_type.as_deref(), Some("application/json"));
+        assert_eq!(data.body, "{\"title\": \"T\"}");
+
+        assert!(matches!(get("/bad/").await, Err(MyError::InvalidPage)));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn templated_error_pages() {
+        let dir = Utf8PathBuf::from_path_buf(
+            std::env::temp_dir().join(format!("flaty-errors-{}", std::process::id())),
+        )
+        .unwrap();
+        std::fs::create_dir_all(dir.join("_style")).unwrap();
+        std::fs::write(
+            dir.join("_style/default.html"),
+            "<main>{{title}}: {{{contents}}}</main>",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("_style/404.html"),
+            "<h1>{{status}}</h1><p>{{message}} at {{path}}</p>",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("_style/403.md"),
+            "---\ntitle = \"Forbidden\"\n---\nNo access here.",
+        )
+        .unwrap();
+        std::fs::write(dir.join("_style/error.html"), "{{status}} {{#if}}").unwrap();
+
+        let app = Arc::new(App::new(dir.clone()));
+        let page = error_page(&app, 404, "Not found", "/<missing>/").await;
+        assert_eq!(page.status, 404);
+        assert_eq!(
+            page.body,
+            "<h1>404</h1><p>Not found at /&lt;missing&gt;/</p>"
+        );
+
+        // Markdown error pages go through the layout, like any page.
+        let page = error_page(&app, 403, "Forbidden", "/x/").await;
+        assert_eq!(page.status, 403);
+        assert!(
+            page.body.starts_with("<main>Forbidden: <p>No access"),
+            "{}",
+            page.body
+        );
+
+        // A broken template falls back to plain text.
+        let page = error_page(&app, 429, "Too many requests", "/").await;
+        assert_eq!(page.status, 429);
+        assert_eq!(page.body, "Too many requests");
+        assert_eq!(
+            page.content_type.as_deref(),
+            Some("text/plain; charset=utf-8")
+        );
+
+        std::fs::write(dir.join("_style/error.html"), "Oops: {{status}}").unwrap();
+        let app = Arc::new(App::new(dir.clone()));
+        assert_eq!(error_page(&app, 405, "", "/").await.body, "Oops: 405");
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn sections_of_a_path() {
+        assert_eq!(
+            section_dirs("/docs/guide/missing/"),
+            ["docs/guide", "docs", ""]
+        );
+        assert_eq!(section_dirs("/docs/img.png"), ["docs", ""]);
+        assert_eq!(section_dirs("/"), [""]);
+        assert_eq!(section_dirs("/a/_private/b/c/"), ["a", ""]);
+        assert_eq!(section_dirs("/../../etc/passwd"), [""]);
+    }
+
+    #[tokio::test]
+    async fn section_not_found_pages() {
+        let dir = Utf8PathBuf::from_path_buf(
+            std::env::temp_dir().join(format!("flaty-section-404-{}", std::process::id())),
+        )
+        .unwrap();
+        std::fs::create_dir_all(dir.join("_style")).unwrap();
+        std::fs::create_dir_all(dir.join("docs/guide")).unwrap();
+        std::fs::write(
+            dir.join("_style/default.html"),
+            "<nav>{{section}}</nav>{{status}} {{{contents}}}",
+        )
+        .unwrap();
+        std::fs::write(dir.join("_style/404.html"), "site-wide {{path}}").unwrap();
+        std::fs::write(
+            dir.join("docs/_404.md"),
+            "---\nsection = \"Docs\"\n---\nNot in the docs.",
+        )
+        .unwrap();
+
+        let app = Arc::new(App::new(dir.clone()));
+        for path in ["/docs/missing/", "/docs/guide/missing/", "/docs/x.png"] {
+            let page = error_page(&app, 404, "Not found", path).await;
+            assert_eq!(page.status, 404);
+            assert_eq!(
+                page.body, "<nav>Docs</nav>404 <p>Not in the docs.</p>\n",
+                "{path}"
+            );
+        }
+        let page = error_page(&app, 404, "Not found", "/blog/missing/").await;
+        assert_eq!(page.body, "site-wide /blog/missing/");
+        // Only 404s use section pages.
+        let page = error_page(&app, 500, "Oops", "/docs/missing/").await;
+        assert_eq!(page.body, "Oops");
+
+        // Lookups are cached: a new file shows up once the entry is rechecked.
+        std::fs::write(dir.join("_404.md"), "Root.").unwrap();
+        let page = error_page(&app, 404, "Not found", "/blog/missing/").await;
+        assert_eq!(page.body, "site-wide /blog/missing/");
+        app.probes.sweep(Duration::ZERO);
+        let page = error_page(&app, 404, "Not found", "/blog/missing/").await;
+        assert_eq!(page.body, "<nav></nav>404 <p>Root.</p>\n");
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn maintenance_mode() {
+        let dir = Utf8PathBuf::from_path_buf(
+            std::env::temp_dir().join(format!("flaty-maintenance-{}", std::process::id())),
+        )
+        .unwrap();
+        std::fs::create_dir_all(dir.join("_style")).unwrap();
+        std::fs::write(dir.join("_style/default.html"), "{{{contents}}}").unwrap();
+        std::fs::write(dir.join("_style/default.scss"), "a { b: c }").unwrap();
+        std::fs::write(dir.join("page.md"), "Home").unwrap();
+        std::fs::write(
+            dir.join("_config.toml"),
+            "[maintenance]\nretry_after = 120\nusers = [\"admin\"]\nips = [\"10.0.0.0/8\"]\n\n[users]\nadmin = \"pw\"\n",
+        )
+        .unwrap();
+
+        let app = Arc::new(App::new(dir.clone()));
+        let request = |peer: &str, authorization: Option<&'static str>| MyRequest::GET {
+            path: "/",
+            query: None,
+            authorization,
+            peer: Some(peer.parse().unwrap()),
+            forwarded_for: None,
+            host: None,
+            accept_language: None,
+        };
+        // Not enabled yet.
+        assert!(matches!(
+            web(app.clone(), request("192.0.2.1", None)).await,
+            Ok(MyResponse::Page(_))
+        ));
+
+        // The flag file switches it on.
+        std::fs::write(dir.join("_maintenance"), "").unwrap();
+        app.probes.sweep(Duration::ZERO);
+        assert!(matches!(
+            web(app.clone(), request("192.0.2.1", None)).await,
+            Err(MyError::Unavailable { retry_after: 120 })
+        ));
+        // The theme's stylesheets stay available.
+        assert!(matches!(
+            web(app.clone(), get("/default.css")).await,
+            Ok(MyResponse::Css(..))
+        ));
+        // Listed addresses and users browse normally ("admin:pw").
+        assert!(matches!(
+            web(app.clone(), request("10.1.2.3", None)).await,
+            Ok(MyResponse::Page(_))
+        ));
+        assert!(matches!(
+            web(
+                app.clone(),
+                request("192.0.2.1", Some("Basic YWRtaW46cHc="))
+            )
+            .await,
+            Ok(MyResponse::Page(_))
+        ));
+        // A wrong password does not bypass ("admin:no").
+        assert!(matches!(
+            web(
+                app.clone(),
+                request("192.0.2.1", Some("Basic YWRtaW46bm8="))
+            )
+            .await,
+            Err(MyError::Unavailable { .. })
+        ));
+
+        // Users sign in at `/_login`, which challenges until they do.
+        let login =
+            |query: Option<&'static str>, authorization: Option<&'static str>| MyRequest::GET {
+                path: "/_login",
+                query,
+                authorization,
+                peer: Some("192.0.2.1".parse().unwrap()),
+                forwarded_for: None,
+                host: None,
+                accept_language: None,
+            };
+        assert!(matches!(
+            web(app.clone(), login(None, None)).await,
+            Err(MyError::Unauthorized)
+        ));
+        assert!(matches!(
+            web(app.clone(), login(None, Some("Basic YWRtaW46bm8="))).await,
+            Err(MyError::Unauthorized)
+        ));
+        let redirect = |response: MyResult| match response {
+            Ok(MyResponse::Redirect(location, 302)) => location,
+            _ => panic!("expected a redirect"),
+        };
+        let admin = Some("Basic YWRtaW46cHc=");
+        assert_eq!(redirect(web(app.clone(), login(None, admin)).await), "/");
+        assert_eq!(
+            redirect(web(app.clone(), login(Some("next=%2Fdocs%2F"), admin)).await),
+            "/docs/"
+        );
+        for next in [
+            "next=//evil.example/",
+            "next=https://evil.example/",
+            "next=/a%0d%0ab",
+        ] {
+            assert_eq!(
+                redirect(web(app.clone(), login(Some(next), admin)).await),
+                "/"
+            );
+        }
+
+        // Or the config switch, with the default retry delay.
+        std::fs::remove_file(dir.join("_maintenance")).unwrap();
+        std::fs::write(dir.join("_config.toml"), "maintenance = true\n").unwrap();
+        let app = Arc::new(App::new(dir.clone()));
+        assert!(matches!(
+            web(app, request("10.1.2.3", None)).await,
+            Err(MyError::Unavailable { retry_after: 3600 })
+        ));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn navigation_variables() {
+        let dir = Utf8PathBuf::from_path_buf(
+            std::env::temp_dir().join(format!("flaty-nav-{}", std::process::id())),
+        )
+        .unwrap();
+        std::fs::create_dir_all(dir.join("_style")).unwrap();
+        std::fs::create_dir_all(dir.join("_drafts")).unwrap();
+        for sub in ["docs/intro", "docs/setup"] {
+            std::fs::create_dir_all(dir.join(sub)).unwrap();
+            std::fs::write(dir.join(sub).join("page.md"), "").unwrap();
+        }
+        std::fs::write(dir.join("page.md"), "").unwrap();
+        std::fs::write(dir.join("_drafts/page.md"), "").unwrap();
+        std::fs::write(dir.join("docs/page.md"), "---\ntitle = \"Docs\"\n---\n").unwrap();
+        std::fs::write(
+            dir.join("_style/default.html"),
+            "{{url}}|{{#each breadcrumbs}}{{title}} {{/each}}|{{#each children}}{{url}} {{/each}}|{{#each siblings}}{{url}} {{/each}}",
+        )
+        .unwrap();
+        let app = Arc::new(App::new(dir.clone()));
+        let body = |response: MyResult| match response {
+            Ok(MyResponse::Page(Rendered { body, .. })) => body,
+            _ => panic!("expected page"),
+        };
+        assert_eq!(body(web(app.clone(), get("/")).await), "/||/docs/ |");
+        assert_eq!(
+            body(web(app.clone(), get("/docs/")).await),
+            "/docs/|Home |/docs/intro/ /docs/setup/ |"
+        );
+        assert_eq!(
+            body(web(app.clone(), get("/docs/setup/")).await),
+            "/docs/setup/|Home Docs ||/docs/intro/ "
+        );
+
+        // Protected pages only appear for users allowed to see them, and
+        // cached responses are kept per user ("admin:pw").
+        std::fs::write(
+            dir.join("_config.toml"),
+            "[users]\nadmin = \"pw\"\n[protected]\n\"/docs/setup/\" = [\"admin\"]\n",
+        )
+        .unwrap();
+        let app = Arc::new(App::new(dir.clone()));
+        let docs = "/docs/|Home |/docs/intro/ |";
+        assert_eq!(body(web(app.clone(), get("/docs/")).await), docs);
+        assert_eq!(
+            body(web(app.clone(), get_as("/docs/", "Basic YWRtaW46cHc=")).await),
+            "/docs/|Home |/docs/intro/ /docs/setup/ |"
+        );
+        assert_eq!(body(web(app.clone(), get("/docs/")).await), docs);
+        assert_eq!(
+            body(web(app.clone(), get("/docs/intro/")).await),
+            "/docs/intro/|Home Docs ||"
+        );
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn collections() {
+        let dir = Utf8PathBuf::from_path_buf(
+            std::env::temp_dir().join(format!("flaty-collection-{}", std::process::id())),
+        )
+        .unwrap();
+        std::fs::create_dir_all(dir.join("_style")).unwrap();
+        let posts = [
+            ("a", "date = 2024-01-01"),
+            ("b", "date = 2024-03-01"),
+            ("c", "date = 2024-02-01"),
+            ("d", "date = 2024-04-01\ndraft = true"),
+            ("e", "date = 2999-01-01"),
+            ("f", "date = 2024-05-01"),
+        ];
+        for (name, fields) in posts {
+            let post = dir.join("blog/posts").join(name);
+            std::fs::create_dir_all(&post).unwrap();
+            std::fs::write(
+                post.join("page.md"),
+                format!("---\ntitle = \"{name}\"\n{fields}\n---\nAbout {name}.\n\nMore."),
+            )
+            .unwrap();
+        }
+        std::fs::write(
+            dir.join("blog/page.md"),
+            "---\ntemplate = \"list\"\ncollection = { source = \"posts/\", per_page = 2 }\n---\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("_style/list.html"),
+            "{{#each collection.pages}}{{title}}:{{summary}} {{/each}}|{{collection.page}}/{{collection.total_pages}}|{{{collection.prev}}}|{{{collection.next}}}",
+        )
+        .unwrap();
+        std::fs::write(dir.join("_style/default.html"), "{{prev.url}}|{{next.url}}").unwrap();
+        std::fs::write(
+            dir.join("_config.toml"),
+            "[users]\nadmin = \"pw\"\n[protected]\n\"/blog/posts/f/\" = [\"admin\"]\n",
+        )
+        .unwrap();
+        let app = Arc::new(App::new(dir.clone()));
+        let body = |response: MyResult| match response {
+            Ok(MyResponse::Page(Rendered { body, .. })) => body,
+            _ => panic!("expected page"),
+        };
+
+        // Newest first; the draft, the future post and (but for allowed
+        // users) the protected one are left out.
+        assert_eq!(
+            body(web(app.clone(), get("/blog/")).await),
+            "b:About b. c:About c. |1/2||/blog/?page=2"
+        );
+        assert_eq!(
+            body(web(app.clone(), get_as("/blog/", "Basic YWRtaW46cHc=")).await),
+            "f:About f. b:About b. |1/2||/blog/?page=2"
+        );
+        assert_eq!(
+            body(web(app.clone(), get("/blog/")).await),
+            "b:About b. c:About c. |1/2||/blog/?page=2"
+        );
+        assert_eq!(
+            body(web(app.clone(), get_query("/blog/", Some("page=2"))).await),
+            "a:About a. |2/2|/blog/|"
+        );
+        for page in ["page=3", "page=0", "page=x"] {
+            assert!(matches!(
+                web(app.clone(), get_query("/blog/", Some(page))).await,
+                Err(MyError::NotFound)
+            ));
+        }
+
+        // Members link to their neighbours in the listing.
+        assert_eq!(
+            body(web(app.clone(), get("/blog/posts/c/")).await),
+            "/blog/posts/b/|/blog/posts/a/"
+        );
+        assert_eq!(
+            body(web(app.clone(), get("/blog/posts/b/")).await),
+            "|/blog/posts/c/"
+        );
+        assert_eq!(
+            body(web(app.clone(), get_as("/blog/posts/b/", "Basic YWRtaW46cHc=")).await),
+            "/blog/posts/f/|/blog/posts/c/"
+        );
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn base_urls() {
+        assert_eq!(
+            parse_base_url("https://example.com/").unwrap(),
+            "https://example.com"
+        );
+        assert_eq!(
+            parse_base_url("http://example.com/site").unwrap(),
+            "http://example.com/site"
+        );
+        for url in [
+            "example.com",
+            "https://",
+            "https:///x",
+            "https://a.b/?x",
+            "ftp://a.b",
+        ] {
+            assert!(parse_base_url(url).is_err(), "{url}");
+        }
+    }
+
+    #[tokio::test]
+    async fn feeds() {
+        let dir = Utf8PathBuf::from_path_buf(
+            std::env::temp_dir().join(format!("flaty-feed-{}", std::process::id())),
+        )
+        .unwrap();
+        std::fs::create_dir_all(dir.join("blog/one")).unwrap();
+        std::fs::create_dir_all(dir.join("blog/two")).unwrap();
+        std::fs::create_dir_all(dir.join("news")).unwrap();
+        for (name, fields) in [
+            ("team", ""),
+            ("moved", "redirect = \"/blog/one/\"\n"),
+            ("gone", "status = 410\n"),
+        ] {
+            std::fs::create_dir_all(dir.join("blog").join(name)).unwrap();
+            std::fs::write(
+                dir.join("blog").join(name).join("page.md"),
+                format!("---\ntitle = \"{name}\"\ndate = 2024-03-01\n{fields}---\nHidden {name}."),
+            )
+            .unwrap();
+        }
+        std::fs::write(
+            dir.join("blog/page.md"),
+            "---\ntitle = \"Blog\"\nfeed = true\n---\nAll posts.",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("blog/one/page.md"),
+            "---\ntitle = \"One\"\ndate = 2024-01-01\nauthor = \"Ann\"\n---\nFirst *post*.",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("blog/two/page.md"),
+            "---\ntitle = \"Two\"\ndate = 2024-02-01\n---\nSecond.",
+        )
+        .unwrap();
+        std::fs::write(dir.join("news/page.md"), "---\nfeed = \"rss\"\n---\n").unwrap();
+        let app = Arc::new(App::new(dir.clone()));
+
+        // Without a base URL, links cannot be made absolute.
+        assert!(matches!(
+            web(app.clone(), get("/blog/feed.xml")).await,
+            Err(MyError::Internal(_))
+        ));
+
+        std::fs::write(
+            dir.join("_config.toml"),
+            "[site]\nbase_url = \"https://example.com/\"\n[users]\nadmin = \"pw\"\n\
+             [protected]\n\"/blog/team/\" = [\"admin\"]\n",
+        )
+        .unwrap();
+        let app = Arc::new(App::new(dir.clone()));
+        let Ok(MyResponse::Page(atom)) = web(app.clone(), get("/blog/feed.xml")).await else {
+            panic!("expected atom feed");
+        };
+        assert_eq!(
+            atom.content_type.as_deref(),
+            Some("application/atom+xml; charset=utf-8")
+        );
+        assert!(atom.body.contains("<title>Blog</title>"));
+        assert!(atom.body.contains("<subtitle>All posts.</subtitle>"));
+        // Newest first, with rendered contents.
+        let two = atom.body.find("https://example.com/blog/two/").unwrap();
+        let one = atom.body.find("https://example.com/blog/one/").unwrap();
+        assert!(two < one);
+        assert!(atom.body.contains("<author><name>Ann</name></author>"));
+        assert!(atom
+            .body
+            .contains("&lt;p&gt;First &lt;em&gt;post&lt;/em&gt;.&lt;/p&gt;"));
+        // Protected pages, redirects and error pages are left out.
+        assert!(!atom.body.contains("Hidden"), "{}", atom.body);
+
+        let Ok(MyResponse::Page(rss)) = web(app.clone(), get("/blog/rss.xml")).await else {
+            panic!("expected rss feed");
+        };
+        assert!(rss
+            .body
+            .contains("<link>https://example.com/blog/one/</link>"));
+
+        // Only the formats a page asks for.
+        assert!(matches!(
+            web(app.clone(), get("/news/rss.xml")).await,
+            Ok(MyResponse::Page(_))
+        ));
+        for path in ["/news/feed.xml", "/feed.xml", "/blog/one/feed.xml"] {
+            assert!(matches!(
+                web(app.clone(), get(path)).await,
+                Err(MyError::NotFound)
+            ));
+        }
+        // A real file wins.
+        std::fs::write(dir.join("blog/feed.xml"), "static").unwrap();
+        assert!(matches!(
+            web(app.clone(), get("/blog/feed.xml")).await,
+            Ok(MyResponse::File(..))
+        ));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn sitemap_and_robots() {
+        let dir = Utf8PathBuf::from_path_buf(
+            std::env::temp_dir().join(format!("flaty-sitemap-{}", std::process::id())),
+        )
+        .unwrap();
+        std::fs::create_dir_all(dir.join("_style")).unwrap();
+        std::fs::write(dir.join("_style/default.html"), "{{{contents}}}").unwrap();
+        let pages = [
+            ("", "---\nupdated = 2024-03-01\n---\n"),
+            ("draft", "---\ndraft = true\n---\n"),
+            ("hidden", "---\nsitemap = false\n---\n"),
+            ("gone", "---\nstatus = 410\n---\n"),
+            ("moved", "---\nredirect = \"/\"\n---\n"),
+            ("team", ""),
+            ("team/notes", ""),
+            ("blog", ""),
+        ];
+        for (dir_name, src) in pages {
+            std::fs::create_dir_all(dir.join(dir_name)).unwrap();
+            std::fs::write(dir.join(dir_name).join("page.md"), src).unwrap();
+        }
+        std::fs::write(dir.join("team/logo.svg"), "<svg/>").unwrap();
+        std::fs::write(
+            dir.join("_config.toml"),
+            "[site]\nbase_url = \"https://example.com\"\n\n[protected]\n\"/team\" = [\"admin\"]\n\n[users]\nadmin = \"pw\"\n",
+        )
+        .unwrap();
+        let app = Arc::new(App::new(dir.clone()));
+
+        let Ok(MyResponse::Page(sitemap)) = web(app.clone(), get("/sitemap.xml")).await else {
+            panic!("expected sitemap");
+        };
+        assert_eq!(
+            sitemap.content_type.as_deref(),
+            Some("application/xml; charset=utf-8")
+        );
+        let locs: Vec<&str> = sitemap
+            .body
+            .split("<loc>")
+            .skip(1)
+            .filter_map(|rest| rest.split_once("</loc>").map(|(loc, _)| loc))
+            .collect();
+        assert_eq!(locs, ["https://example.com/", "https://example.com/blog/"]);
+        assert!(sitemap
+            .body
+            .contains("<lastmod>2024-03-01T00:00:00Z</lastmod>"));
+
+        let Ok(MyResponse::Page(robots)) = web(app.clone(), get("/robots.txt")).await else {
+            panic!("expected robots.txt");
+        };
+        assert!(robots
+            .body
+            .contains("Sitemap: https://example.com/sitemap.xml"));
+        std::fs::write(dir.join("robots.txt"), "User-agent: *\n").unwrap();
+        assert!(matches!(
+            web(app.clone(), get("/robots.txt")).await,
+            Ok(MyResponse::File(..))
+        ));
+
+        // Everything under a protected prefix is marked noindex.
+        let request = |path| MyRequest::GET {
+            path,
+            query: None,
+            authorization: Some("Basic YWRtaW46cHc="),
+            peer: None,
+            forwarded_for: None,
+            host: None,
+            accept_language: None,
+        };
+        for path in ["/team/", "/team/notes/", "/team/logo.svg"] {
+            assert!(
+                matches!(
+                    web(app.clone(), request(path)).await,
+                    Ok(MyResponse::Noindex(_))
+                ),
+                "{path}"
+            );
+        }
+        assert!(matches!(
+            web(app.clone(), request("/blog/")).await,
+            Ok(MyResponse::Page(_))
+        ));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn taxonomies() {
+        let dir = Utf8PathBuf::from_path_buf(
+            std::env::temp_dir().join(format!("flaty-taxonomy-{}", std::process::id())),
+        )
+        .unwrap();
+        std::fs::create_dir_all(dir.join("_style")).unwrap();
+        let pages = [
+            ("a", "title = \"A\"\ntags = [\"Rust\", \"Web Dev\"]"),
+            ("b", "title = \"B\"\ntags = [\"rust\"]\ndate = 2024-01-01"),
+            ("c", "title = \"C\"\ntags = [\"rust\"]\ndraft = true"),
+            ("team", "title = \"T\"\ntags = [\"rust\", \"secret\"]"),
+        ];
+        for (name, fields) in pages {
+            std::fs::create_dir_all(dir.join(name)).unwrap();
+            std::fs::write(
+                dir.join(name).join("page.md"),
+                format!("---\n{fields}\n---\n"),
+            )
+            .unwrap();
+        }
+        std::fs::write(
+            dir.join("_config.toml"),
+            "taxonomies = [\"tags\"]\n\n[protected]\n\"/team\" = [\"admin\"]\n\n[users]\nadmin = \"pw\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("_style/default.html"),
+            "{{#each related}}{{title}} {{/each}}",
+        )
+        .unwrap();
+        let app = Arc::new(App::new(dir.clone()));
+        let body = |response: MyResult| match response {
+            Ok(MyResponse::Page(Rendered { body, .. })) => body,
+            _ => panic!("expected page"),
+        };
+        // Drafts and protected pages are left out of `related`.
+        assert_eq!(body(web(app.clone(), get("/a/")).await), "B ");
+
+        // Generated pages need their template.
+        assert!(matches!(
+            web(app.clone(), get("/tags/")).await,
+            Err(MyError::NotFound)
+        ));
+        std::fs::write(
+            dir.join("_style/taxonomy.html"),
+            "{{taxonomy}}:{{term.name}}|{{#each terms}}{{name}}={{url}}({{count}}) {{/each}}|{{#each pages}}{{title}} {{/each}}",
+        )
+        .unwrap();
+        app.probes.sweep(Duration::ZERO);
+        assert_eq!(
+            body(web(app.clone(), get("/tags/")).await),
+            "tags:|Rust=/tags/rust/(2) Web Dev=/tags/web-dev/(1) |"
+        );
+        assert_eq!(
+            body(web(app.clone(), get("/tags/rust/")).await),
+            "tags:Rust|Rust=/tags/rust/(2) Web Dev=/tags/web-dev/(1) |B A "
+        );
+        // Allowed users see protected pages too ("admin:pw").
+        let request = MyRequest::GET {
+            path: "/tags/secret/",
+            query: None,
+            authorization: Some("Basic YWRtaW46cHc="),
+            peer: None,
+            forwarded_for: None,
+            host: None,
+            accept_language: None,
+        };
+        assert!(body(web(app.clone(), request).await).ends_with("|T "));
+        for path in ["/tags/secret/", "/tags/none/", "/other/", "/tags/rust/x/"] {
+            assert!(
+                matches!(web(app.clone(), get(path)).await, Err(MyError::NotFound)),
+                "{path}"
+            );
+        }
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn site_search() {
+        let dir = Utf8PathBuf::from_path_buf(
+            std::env::temp_dir().join(format!("flaty-search-{}", std::process::id())),
+        )
+        .unwrap();
+        std::fs::create_dir_all(dir.join("_style")).unwrap();
+        std::fs::create_dir_all(dir.join("team")).unwrap();
+        std::fs::write(
+            dir.join("page.md"),
+            "---\ntitle = \"Home\"\n---\nWelcome to the *garden*.",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("team/page.md"),
+            "---\ntitle = \"Garden plans\"\n---\nSecret.",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("_config.toml"),
+            "[protected]\n\"/team\" = [\"admin\"]\n\n[users]\nadmin = \"pw\"\n",
+        )
+        .unwrap();
+        let app = Arc::new(App::new(dir.clone()));
+        let json = |response: MyResult| match response {
+            Ok(MyResponse::Page(Rendered {
+                body, content_type, ..
+            })) => {
+                assert_eq!(content_type.as_deref(), Some("application/json"));
+                serde_json::from_str::<Json>(&body).unwrap()
+            }
+            _ => panic!("expected search results"),
+        };
+
+        // Protected pages are left out for anonymous users.
+        let results = json(web(app.clone(), get_query("/_search", Some("q=GARDEN"))).await);
+        assert_eq!(results["query"], "GARDEN");
+        assert_eq!(results["hits"].as_array().unwrap().len(), 1);
+        assert_eq!(results["hits"][0]["url"], "/");
+        assert_eq!(results["hits"][0]["title"], "Home");
+        assert_eq!(
+            results["hits"][0]["excerpt"],
+            "Welcome to the <mark>garden</mark>."
+        );
+        // ... and listed first, by title, for allowed ones ("admin:pw").
+        let request = MyRequest::GET {
+            path: "/_search",
+            query: Some("q=garden"),
+            authorization: Some("Basic YWRtaW46cHc="),
+            peer: None,
+            forwarded_for: None,
+            host: None,
+            accept_language: None,
+        };
+        let results = json(web(app.clone(), request).await);
+        assert_eq!(results["hits"][0]["url"], "/team/");
+        assert_eq!(results["hits"][1]["url"], "/");
+        // Only the query and its hits, nothing of who asked.
+        assert_eq!(results.as_object().unwrap().len(), 2);
+
+        // The HTML page needs its template.
+        assert!(matches!(
+            web(app.clone(), get_query("/_search/", Some("q=garden"))).await,
+            Err(MyError::NotFound)
+        ));
+        std::fs::write(
+            dir.join("_style/search.html"),
+            "{{query}}:{{#each hits}}<a href=\"{{url}}\">{{title}}</a>{{{excerpt}}}{{/each}}",
+        )
+        .unwrap();
+        app.probes.sweep(Duration::ZERO);
+        match web(app.clone(), get_query("/_search/", Some("q=welcome+garden"))).await {
+            Ok(MyResponse::Page(Rendered { body, .. })) => assert_eq!(
+                body,
+                "welcome garden:<a href=\"/\">Home</a><mark>Welcome</mark> to the <mark>garden</mark>."
+            ),
+            _ => panic!("expected search page"),
+        }
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn drafts() {
+        let dir = Utf8PathBuf::from_path_buf(
+            std::env::temp_dir().join(format!("flaty-drafts-{}", std::process::id())),
+        )
+        .unwrap();
+        std::fs::create_dir_all(dir.join("_style")).unwrap();
+        std::fs::create_dir_all(dir.join("post")).unwrap();
+        std::fs::write(
+            dir.join("_style/default.html"),
+            "{{{contents}}}|{{{preview_url}}}",
+        )
+        .unwrap();
+        std::fs::write(dir.join("page.md"), "").unwrap();
+        std::fs::write(
+            dir.join("post/page.md"),
+            "---\ndraft = true\naliases = [\"/old/\"]\n---\nSoon.",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("_config.toml"),
+            concat!(
+                "[site]\nbase_url = \"https://example.com\"\n\n",
+                "[users]\nann = \"pw\"\nbob = \"pw\"\n\n",
+                "[drafts]\neditors = [\"ann\"]\npreview_secret = \"0123456789abcdef\"\n",
+            ),
+        )
+        .unwrap();
+        let app = Arc::new(App::new(dir.clone()));
+        let as_user = |authorization| MyRequest::GET {
+            path: "/post/",
+            query: None,
+            authorization: Some(authorization),
+            peer: None,
+            forwarded_for: None,
+            host: None,
+            accept_language: None,
+        };
+        let draft = |response: MyResult| match response {
+            Ok(MyResponse::Noindex(inner)) => match *inner {
+                MyResponse::Page(page) => {
+                    assert_eq!(page.policy, CachePolicy::Private);
+                    page.body
+                }
+                _ => panic!("expected a page"),
+            },
+            _ => panic!("expected a draft"),
+        };
+
+        // Hidden from everyone else, aliases included, even while editors
+        // are configured: a draft's URL is not given away.
+        assert!(matches!(
+            web(app.clone(), get("/post/")).await,
+            Err(MyError::NotFound)
+        ));
+        assert!(matches!(
+            web(app.clone(), as_user("Basic Ym9iOnB3")).await,
+            Err(MyError::NotFound)
+        ));
+        assert!(matches!(
+            web(app.clone(), get("/old/")).await,
+            Err(MyError::NotFound)
+        ));
+
+        // Editors sign in at `/_login`, which takes them back to the draft.
+        let login = |authorization| MyRequest::GET {
+            path: "/_login",
+            query: Some("next=%2Fpost%2F"),
+            authorization,
+            peer: None,
+            forwarded_for: None,
+            host: None,
+            accept_language: None,
+        };
+        assert!(matches!(
+            web(app.clone(), login(None)).await,
+            Err(MyError::Unauthorized)
+        ));
+        assert!(matches!(
+            web(app.clone(), login(Some("Basic YW5uOnB3"))).await,
+            Ok(MyResponse::Redirect(loc, 302)) if loc == "/post/"
+        ));
+
+        // Editors ("ann:pw") get a preview link to share.
+        let body = draft(web(app.clone(), as_user("Basic YW5uOnB3")).await);
+        let (contents, link) = body.split_once('|').unwrap();
+        assert_eq!(contents, "<p>Soon.</p>\n");
+        let query = link
+            .strip_prefix("https://example.com/post/?")
+            .unwrap()
+            .to_owned();
+        assert!(query.starts_with("preview="));
+
+        // The link works for anyone, but only for that page.
+        let body = draft(web(app.clone(), get_query("/post/", Some(&query))).await);
+        assert_eq!(body, "<p>Soon.</p>\n|");
+        assert!(matches!(
+            web(app.clone(), get_query("/post/", Some("preview=1.abc"))).await,
+            Err(MyError::NotFound)
+        ));
+        std::fs::create_dir_all(dir.join("other")).unwrap();
+        std::fs::write(dir.join("other/page.md"), "---\ndraft = true\n---\n").unwrap();
+        assert!(matches!(
+            web(app.clone(), get_query("/other/", Some(&query))).await,
+            Err(MyError::NotFound)
+        ));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn scheduled_pages() {
+        use std::sync::atomic::{AtomicI64, Ordering};
+
+        let dir = Utf8PathBuf::from_path_buf(
+            std::env::temp_dir().join(format!("flaty-schedule-{}", std::process::id())),
+        )
+        .unwrap();
+        std::fs::create_dir_all(dir.join("_style")).unwrap();
+        std::fs::write(
+            dir.join("_style/default.html"),
+            "{{{contents}}}{{#each children}}{{url}} {{/each}}",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("_config.toml"),
+            concat!(
+                "[site]\ntimezone = \"Asia/Tokyo\"\n\n",
+                // Anonymous visitors still get the 404s and 410s.
+                "[users]\nann = \"pw\"\n\n[drafts]\neditors = [\"ann\"]\n",
+            ),
+        )
+        .unwrap();
+        // One second after the pinned clock, as a local time in Tokyo (no
+        // offset).
+        static NOW: AtomicI64 = AtomicI64::new(0);
+        let now = "2030-01-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        NOW.store(now.timestamp(), Ordering::Relaxed);
+        let clock = || {
+            let now = NOW.load(Ordering::Relaxed);
+            DateTime::from_timestamp(now, 0).unwrap()
+        };
+        let soon = "2030-01-01T09:00:01";
+        let pages = [
+            ("", String::new()),
+            ("news", format!("---\npublish_date = {soon}\n---\nNews.")),
+            ("sale", format!("---\nexpire_date = {soon}\n---\nSale.")),
+            ("old", "---\nexpire_date = 2000-01-01\n---\n".into()),
+            ("bad", "---\npublish_date = \"tomorrow\"\n---\n".into()),
+        ];
+        for (dir_name, src) in pages {
+            std::fs::create_dir_all(dir.join(dir_name)).unwrap();
+            std::fs::write(dir.join(dir_name).join("page.md"), src).unwrap();
+        }
+        let app = Arc::new(App::new(dir.clone()).with_clock(clock));
+        let body = |response: MyResult| match response {
+            Ok(MyResponse::Page(page)) => page.body,
+            _ => panic!("expected a page"),
+        };
+
+        assert!(matches!(
+            web(app.clone(), get("/news/")).await,
+            Err(MyError::NotFound)
+        ));
+        assert_eq!(
+            body(web(app.clone(), get("/sale/")).await),
+            "<p>Sale.</p>\n"
+        );
+        assert!(matches!(
+            web(app.clone(), get("/old/")).await,
+            Err(MyError::Gone)
+        ));
+        assert!(matches!(
+            web(app.clone(), get("/bad/")).await,
+            Err(MyError::InvalidPage)
+        ));
+        assert_eq!(body(web(app.clone(), get("/")).await), "/sale/ ");
+
+        // Neither the rendered-page cache nor the site snapshot outlive the
+        // schedule.
+        NOW.store(now.timestamp() + 2, Ordering::Relaxed);
+        assert_eq!(
+            body(web(app.clone(), get("/news/")).await),
+            "<p>News.</p>\n"
+        );
+        assert!(matches!(
+            web(app.clone(), get("/sale/")).await,
+            Err(MyError::Gone)
+        ));
+        assert_eq!(body(web(app.clone(), get("/")).await), "/news/ ");
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn site_data() {
+        let dir = Utf8PathBuf::from_path_buf(
+            std::env::temp_dir().join(format!("flaty-site-data-{}", std::process::id())),
+        )
+        .unwrap();
+        std::fs::create_dir_all(dir.join("_style/snippets")).unwrap();
+        std::fs::create_dir_all(dir.join("_data/footer")).unwrap();
+        std::fs::write(
+            dir.join("_style/default.html"),
+            "{{{contents}}}{{#each site.data.footer.links}}<a href=\"{{url}}\">{{title}}</a>{{/each}}",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("_style/snippets/contact.html"),
+            "<p>{{site.data.contact.email}}</p>",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("_data/footer/links.json"),
+            "[{\"url\": \"/about/\", \"title\": \"About\"}]",
+        )
+        .unwrap();
+        std::fs::write(dir.join("_data/contact.toml"), "email = \"a@example.com\"").unwrap();
+        std::fs::write(dir.join("page.md"), ":::contact\n:::\n").unwrap();
+        let app = Arc::new(App::new(dir.clone()));
+        let body = |response: MyResult| match response {
+            Ok(MyResponse::Page(page)) => page.body,
+            _ => panic!("expected a page"),
+        };
+
+        assert_eq!(
+            body(web(app.clone(), get("/")).await),
+            "<p>a@example.com</p>\n<a href=\"/about/\">About</a>"
+        );
+
+        // Edits reach snippets through the rendered-page cache.
+        std::fs::write(dir.join("_data/contact.toml"), "email = \"b@example.com\"").unwrap();
+        app.data.sweep(Duration::ZERO);
+        assert!(body(web(app.clone(), get("/")).await).starts_with("<p>b@example.com</p>"));
+
+        // An invalid file is left out (and logged).
+        std::fs::write(dir.join("_data/contact.toml"), "email = ").unwrap();
+        app.data.sweep(Duration::ZERO);
+        assert!(body(web(app.clone(), get("/")).await).starts_with("<p></p>"));
+        std::fs::remove_dir_all(&dir).ok();
     }
 
     #[tokio::test]
-    async fn renders_snippet_context_and_body() {
+    async fn helper_scripts() {
         let dir = Utf8PathBuf::from_path_buf(
-            std::env::temp_dir().join(format!("flaty-snippet-{}", std::process::id())),
+            std::env::temp_dir().join(format!("flaty-helper-scripts-{}", std::process::id())),
         )
         .unwrap();
         std::fs::create_dir_all(dir.join("_style/snippets")).unwrap();
-        std::fs::write(dir.join("_style/default.html"), "{{{contents}}}").unwrap();
+        std::fs::create_dir_all(dir.join("_style/helpers")).unwrap();
         std::fs::write(
-            dir.join("_style/snippets/card.html"),
-            "<aside>{{label}} {{page.title}} {{{contents}}}</aside>",
+            dir.join("_style/default.html"),
+            "{{shout title}}|{{{contents}}}",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("_style/snippets/price.html"),
+            "<p>{{cents amount}}</p>",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("_style/helpers/shout.rhai"),
+            "params[0].to_upper()",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("_style/helpers/cents.rhai"),
+            "let c = params[0];\n`${c / 100}.${c % 100}`",
         )
         .unwrap();
         std::fs::write(
             dir.join("page.md"),
-            "---\ntitle = \"<Page>\"\n---\n[Link][target]\n\n:::card\nlabel = \"<Label>\"\n\n**Body**\n:::\n\n[target]: /ok\n",
+            "---\ntitle = \"Hi\"\n---\n:::price\namount = 1250\n:::\n",
         )
         .unwrap();
-
         let app = Arc::new(App::new(dir.clone()));
-        let response = web(
-            app,
-            MyRequest::GET {
-                path: "/",
-                authorization: None,
-            },
+        let body = |response: MyResult| match response {
+            Ok(MyResponse::Page(page)) => page.body,
+            _ => panic!("expected a page"),
+        };
+        assert_eq!(body(web(app.clone(), get("/")).await), "HI|<p>12.50</p>\n");
+
+        // Edits reach layouts and (cached) snippets.
+        std::fs::write(
+            dir.join("_style/helpers/cents.rhai"),
+            "let c = params[0];\n`$${c / 100}`",
         )
-        .await
         .unwrap();
-        let MyResponse::Html(html) = response else {
-            panic!("expected html");
-        };
-        assert!(html.contains("&lt;Label&gt; &lt;Page&gt;"));
-        assert!(html.contains("<strong>Body</strong>"));
-        assert!(html.contains("<a href=\"/ok\">Link</a>"));
+        app.scripts.sweep(Duration::ZERO);
+        assert_eq!(body(web(app.clone(), get("/")).await), "HI|<p>$12</p>\n");
+
+        // A failing script fails the render.
+        std::fs::write(dir.join("_style/helpers/shout.rhai"), "loop {}").unwrap();
+        app.scripts.sweep(Duration::ZERO);
+        assert!(web(app.clone(), get("/")).await.is_err());
         std::fs::remove_dir_all(&dir).ok();
     }
 
     #[tokio::test]
-    async fn preserves_comments_from_layouts() {
+    async fn directory_defaults() {
         let dir = Utf8PathBuf::from_path_buf(
-            std::env::temp_dir().join(format!("flaty-comments-{}", std::process::id())),
+            std::env::temp_dir().join(format!("flaty-defaults-web-{}", std::process::id())),
         )
         .unwrap();
         std::fs::create_dir_all(dir.join("_style")).unwrap();
+        std::fs::create_dir_all(dir.join("blog/first")).unwrap();
+        std::fs::write(dir.join("_style/default.html"), "{{title}}").unwrap();
         std::fs::write(
-            dir.join("_style/default.html"),
-            "<!-- layout note --><main>{{{contents}}}</main>",
+            dir.join("_style/post.html"),
+            "{{title}} by {{author}}|{{{contents}}}",
         )
         .unwrap();
-        std::fs::write(dir.join("page.md"), "Visible").unwrap();
-
-        let app = Arc::new(App::new(dir.clone()));
-        let MyResponse::Html(html) = web(
-            app,
-            MyRequest::GET {
-                path: "/",
-                authorization: None,
-            },
+        std::fs::write(
+            dir.join("blog/_defaults.toml"),
+            "template = \"post\"\nauthor = \"Ann\"\n",
         )
-        .await
-        .unwrap() else {
-            panic!("expected html");
+        .unwrap();
+        std::fs::write(
+            dir.join("blog/page.md"),
+            "---\ntitle = \"Blog\"\ntemplate = \"default\"\n---\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("blog/first/page.md"),
+            "---\ntitle = \"First\"\n---\nHello\n",
+        )
+        .unwrap();
+        let app = Arc::new(App::new(dir.clone()));
+        let body = |response: MyResult| match response {
+            Ok(MyResponse::Page(page)) => page.body,
+            _ => panic!("expected a page"),
         };
-        assert!(html.contains("<!-- layout note -->"));
-        assert!(html.contains("<p>Visible</p>"));
+        assert_eq!(body(web(app.clone(), get("/blog/")).await), "Blog");
+        assert_eq!(
+            body(web(app.clone(), get("/blog/first/")).await),
+            "First by Ann|<p>Hello</p>\n"
+        );
+
+        // Editing the defaults re-renders the pages below them.
+        std::fs::write(
+            dir.join("blog/_defaults.toml"),
+            "template = \"post\"\nauthor = \"Bob\"\n",
+        )
+        .unwrap();
+        app.pages.sweep(Duration::ZERO);
+        assert_eq!(
+            body(web(app.clone(), get("/blog/first/")).await),
+            "First by Bob|<p>Hello</p>\n"
+        );
         std::fs::remove_dir_all(&dir).ok();
     }
 
     #[tokio::test]
-    async fn unclosed_comments_stop_at_expansion_boundaries() {
+    async fn site_variables() {
         let dir = Utf8PathBuf::from_path_buf(
-            std::env::temp_dir().join(format!("flaty-comment-boundaries-{}", std::process::id())),
+            std::env::temp_dir().join(format!("flaty-site-vars-{}", std::process::id())),
         )
         .unwrap();
         std::fs::create_dir_all(dir.join("_style/snippets")).unwrap();
         std::fs::write(
             dir.join("_style/default.html"),
-            "<main>{{{contents}}}</main><footer>layout after</footer>",
+            "{{site.title}}|{{title}}|{{site.base_url}}|{{site.social.mastodon}}|{{site.users}}|{{{contents}}}",
         )
         .unwrap();
         std::fs::write(
-            dir.join("_style/snippets/card.html"),
-            "<span>snippet before</span>{{{value}}}snippet after",
+            dir.join("_style/snippets/lang.html"),
+            "<p>{{site.language}}</p>",
         )
         .unwrap();
+        std::fs::write(dir.join("_style/404.html"), "{{site.title}}: {{message}}").unwrap();
         std::fs::write(
             dir.join("page.md"),
-            ":::card\nvalue = \"<!-- unfinished\"\n:::\n\nPage after snippet\n\n<script><!--\ncontent after comment",
+            "---\ntitle = \"Home\"\n---\n:::lang\n:::\n",
         )
         .unwrap();
-
-        let app = Arc::new(App::new(dir.clone()));
-        let MyResponse::Html(html) = web(
-            app,
-            MyRequest::GET {
-                path: "/",
-                authorization: None,
-            },
+        std::fs::write(
+            dir.join("_config.toml"),
+            concat!(
+                "[site]\ntitle = \"Garden\"\nbase_url = \"https://example.com/\"\n",
+                "language = \"en\"\nsocial = { mastodon = \"@g\" }\n\n",
+                "[users]\nadmin = \"secret\"\n",
+            ),
         )
-        .await
-        .unwrap() else {
-            panic!("expected html");
+        .unwrap();
+        let app = Arc::new(App::new(dir.clone()));
+        let Ok(MyResponse::Page(page)) = web(app.clone(), get("/")).await else {
+            panic!("expected a page");
         };
-        assert!(html.contains("snippet before"));
-        assert!(!html.contains("snippet after"), "{html}");
-        assert!(html.contains("Page after snippet"));
-        assert!(!html.contains("content after comment"));
-        assert!(html.contains("layout after"));
+        // Front matter stays top-level; `[users]` is not reachable.
+        assert_eq!(page.body, "Garden|Home|https://example.com|@g||<p>en</p>\n");
+        let missing = error_page(&app, 404, "Not found", "/missing/").await;
+        assert_eq!(missing.body, "Garden: Not found");
+
+        assert!(Config::compute("[site]\ndata = 1\n").is_err());
+        assert!(Config::compute("[site]\nbase_url = 1\n").is_err());
+        assert!(Config::compute("[site]\nlocale = \"xx\"\n").is_err());
         std::fs::remove_dir_all(&dir).ok();
     }
 
     #[tokio::test]
-    async fn missing_snippet_invalidates_page() {
+    async fn partials_and_parent_layouts() {
         let dir = Utf8PathBuf::from_path_buf(
-            std::env::temp_dir().join(format!("flaty-missing-snippet-{}", std::process::id())),
+            std::env::temp_dir().join(format!("flaty-partials-{}", std::process::id())),
         )
         .unwrap();
-        std::fs::create_dir_all(dir.join("_style")).unwrap();
-        std::fs::write(dir.join("_style/default.html"), "{{{contents}}}").unwrap();
-        std::fs::write(dir.join("page.md"), ":::missing\n:::\n").unwrap();
-
+        std::fs::create_dir_all(dir.join("_style/partials")).unwrap();
+        std::fs::create_dir_all(dir.join("_style/snippets")).unwrap();
+        std::fs::create_dir_all(dir.join("wide")).unwrap();
+        let files = [
+            ("_style/partials/header.html", "<h1>{{title}}</h1>"),
+            ("_style/partials/badge.html", "B1"),
+            (
+                "_style/base.html",
+                "<html>{{> header}}{{#> main}}default{{/main}}</html>",
+            ),
+            (
+                "_style/post.html",
+                "---\nlayout = \"base\"\n---\n{{#*inline \"main\"}}<main>{{{contents}}}</main>{{/inline}}",
+            ),
+            (
+                "_style/wide.html",
+                "{{#> base}}{{#*inline \"main\"}}wide{{/inline}}{{/base}}",
+            ),
+            ("_style/snippets/card.html", "<b>{{> badge}}</b>"),
+            (
+                "page.md",
+                "---\ntitle = \"Home\"\ntemplate = \"post\"\n---\n:::card\n:::\n",
+            ),
+            ("wide/page.md", "---\ntitle = \"Wide\"\ntemplate = \"wide\"\n---\n"),
+        ];
+        for (path, src) in files {
+            std::fs::write(dir.join(path), src).unwrap();
+        }
         let app = Arc::new(App::new(dir.clone()));
-        let response = web(
-            app,
-            MyRequest::GET {
-                path: "/",
-                authorization: None,
-            },
-        )
-        .await;
-        assert!(matches!(response, Err(MyError::InvalidPage)));
-        std::fs::remove_dir_all(&dir).ok();
-    }
+        let body = |response: MyResult| match response {
+            Ok(MyResponse::Page(page)) => page.body,
+            _ => panic!("expected a page"),
+        };
 
-    #[tokio::test]
-    async fn redirects_without_slash() {
-        match resp("/page1").await.unwrap() {
-            MyResponse::Redirect(loc) => assert_eq!(loc, "/page1/"),
-            _ => panic!("expected redirect"),
-        }
+        assert_eq!(
+            body(web(app.clone(), get("/")).await),
+            "<html><h1>Home</h1><main><p><b>B1</b></p>\n</main></html>"
+        );
+        assert_eq!(
+            body(web(app.clone(), get("/wide/")).await),
+            "<html><h1>Wide</h1>wide</html>"
+        );
+
+        // A partial used by a snippet invalidates the rendered page.
+        std::fs::write(dir.join("_style/partials/badge.html"), "B2").unwrap();
+        app.templates.sweep(Duration::ZERO);
+        assert!(body(web(app.clone(), get("/")).await).contains("<b>B2</b>"));
+
+        // A deleted partial is no longer rendered.
+        std::fs::remove_file(dir.join("_style/partials/badge.html")).unwrap();
+        app.templates.sweep(Duration::ZERO);
+        app.probes.sweep(Duration::ZERO);
+        assert!(matches!(
+            web(app.clone(), get("/")).await,
+            Err(MyError::InvalidPage)
+        ));
+        std::fs::remove_dir_all(&dir).ok();
     }
 
     #[tokio::test]
     async fn compiles_css() {
         match resp("/default.css").await.unwrap() {
-            MyResponse::Css(c) => assert!(c.contains("color")),
+            MyResponse::Css(c, ..) => assert!(c.contains("color")),
             _ => panic!("expected css"),
         }
     }
@@ -810,22 +3821,91 @@ mod tests {
         std::fs::write(dir.join("theme.css"), "body{color:red}").unwrap();
         std::fs::write(dir.join("_style/theme.scss"), "body{color:blue}").unwrap();
         let app = Arc::new(App::new(dir.clone()));
-        let r = web(
-            app,
-            MyRequest::GET {
-                path: "/theme.css",
-                authorization: None,
-            },
+        let r = web(app, get("/theme.css")).await;
+        assert!(matches!(r, Ok(MyResponse::File(..))));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn fingerprints_assets() {
+        let app = Arc::new(App::new("example_site".into()));
+        let css = app.asset_url("/default.css").await;
+        let svg = app.asset_url("/heart.svg").await;
+        assert!(css.starts_with("/default.css?v="), "{css}");
+        assert!(svg.starts_with("/heart.svg?v="), "{svg}");
+        assert_eq!(app.asset_url("/missing.png").await, "/missing.png");
+        assert_eq!(
+            app.asset_url("/_style/default.scss").await,
+            "/_style/default.scss"
+        );
+
+        let get = |url: String| {
+            let app = app.clone();
+            async move {
+                let (path, query) = url
+                    .split_once('?')
+                    .map_or((url.as_str(), None), |(p, q)| (p, Some(q)));
+                web(app, get_query(path, query)).await
+            }
+        };
+        let (css_path, _) = css.split_once('?').unwrap();
+        let (svg_path, _) = svg.split_once('?').unwrap();
+        match get(css.clone()).await {
+            // The ETag comes from the hash the fingerprint was made of.
+            Ok(MyResponse::Css(_, hash, CachePolicy::Immutable)) => {
+                assert!(css.ends_with(&fingerprint(hash)), "{css}");
+            }
+            _ => panic!("expected an immutable stylesheet"),
+        }
+        assert!(matches!(
+            get(svg.clone()).await,
+            Ok(MyResponse::File(_, CachePolicy::Immutable))
+        ));
+        // Stale or missing fingerprints still serve the current contents.
+        assert!(matches!(
+            get(format!("{css_path}?v=0123")).await,
+            Ok(MyResponse::Css(_, _, CachePolicy::Revalidate))
+        ));
+        assert!(matches!(
+            get(format!("{svg_path}?v=0123")).await,
+            Ok(MyResponse::File(_, CachePolicy::Revalidate))
+        ));
+        assert!(matches!(
+            get(svg_path.to_owned()).await,
+            Ok(MyResponse::File(_, CachePolicy::Revalidate))
+        ));
+    }
+
+    #[tokio::test]
+    async fn asset_helper_in_layouts() {
+        let dir = Utf8PathBuf::from_path_buf(
+            std::env::temp_dir().join(format!("flaty-asset-{}", std::process::id())),
+        )
+        .unwrap();
+        std::fs::create_dir_all(dir.join("_style")).unwrap();
+        std::fs::write(
+            dir.join("_style/default.html"),
+            "<link href=\"{{asset \"/default.css\"}}\"><img src=\"{{asset \"/logo.png\"}}\">",
         )
-        .await;
-        assert!(matches!(r, Ok(MyResponse::File(_))));
+        .unwrap();
+        std::fs::write(dir.join("_style/default.scss"), "a { b: c }").unwrap();
+        std::fs::write(dir.join("logo.png"), [0x89, 0x50, 0xff, 0x00]).unwrap();
+        std::fs::write(dir.join("page.md"), "Hi").unwrap();
+
+        let app = Arc::new(App::new(dir.clone()));
+        let MyResponse::Page(Rendered { body: html, .. }) = web(app, get("/")).await.unwrap()
+        else {
+            panic!("expected html");
+        };
+        assert!(html.contains("href=\"/default.css?v="), "{html}");
+        assert!(html.contains("src=\"/logo.png?v="), "{html}");
         std::fs::remove_dir_all(&dir).ok();
     }
 
     #[tokio::test]
     async fn serves_static_file() {
         match resp("/heart.svg").await.unwrap() {
-            MyResponse::File(f) => assert!(f.ends_with("heart.svg")),
+            MyResponse::File(f, _) => assert!(f.ends_with("heart.svg")),
             _ => panic!("expected file"),
         }
     }
@@ -840,15 +3920,8 @@ mod tests {
         std::fs::create_dir_all(&dir).unwrap();
         std::fs::write(dir.join("x.svg"), "<svg/>").unwrap();
         let app = Arc::new(App::new(dir.clone()));
-        let r = web(
-            app,
-            MyRequest::GET {
-                path: "/x.svg",
-                authorization: None,
-            },
-        )
-        .await;
-        assert!(matches!(r, Ok(MyResponse::File(_))));
+        let r = web(app, get("/x.svg")).await;
+        assert!(matches!(r, Ok(MyResponse::File(..))));
         std::fs::remove_dir_all(&dir).ok();
     }
 
@@ -856,4 +3929,295 @@ mod tests {
     async fn missing_is_not_found() {
         assert!(matches!(resp("/nope/").await, Err(MyError::NotFound)));
     }
+
+    #[tokio::test]
+    async fn response_cache() {
+        use std::sync::atomic::Ordering;
+
+        let dir = Utf8PathBuf::from_path_buf(
+            std::env::temp_dir().join(format!("flaty-responses-{}", std::process::id())),
+        )
+        .unwrap();
+        std::fs::create_dir_all(dir.join("_style")).unwrap();
+        std::fs::create_dir_all(dir.join("blog/a")).unwrap();
+        std::fs::create_dir_all(dir.join("blog/b")).unwrap();
+        let layout =
+            "{{title}}|{{asset \"/style.css\"}}|{{#each collection.pages}}{{title}}{{/each}}";
+        let files = [
+            ("_style/default.html", layout),
+            ("style.css", "a {}"),
+            (
+                "blog/page.md",
+                "---\ntitle = \"Blog\"\ncollection = { per_page = 1 }\n---\n",
+            ),
+            ("blog/a/page.md", "---\ntitle = \"A\"\n---\n"),
+            ("blog/b/page.md", "---\ntitle = \"B\"\n---\n"),
+        ];
+        for (name, contents) in files {
+            std::fs::write(dir.join(name), contents).unwrap();
+        }
+        let app = Arc::new(App::new(dir.clone()));
+        let hits = || app.responses.hits.load(Ordering::Relaxed);
+        let page = |query| {
+            let app = app.clone();
+            async move {
+                match web(app, get_query("/blog/", query)).await {
+                    Ok(MyResponse::Page(page)) => page,
+                    _ => panic!("expected a page"),
+                }
+            }
+        };
+
+        let first = page(None).await;
+        assert!(first.body.starts_with("Blog|/style.css?v=") && first.body.ends_with("|A"));
+        assert_eq!(first.etag, Some(etag(&first.body)));
+        assert_eq!(page(None).await.body, first.body);
+        assert_eq!(hits(), 1);
+        // Collection pages are kept apart.
+        assert!(page(Some("page=2")).await.body.ends_with("|B"));
+        assert_eq!(hits(), 1);
+
+        // A new asset fingerprint, then a new layout, are rendered afresh.
+        std::fs::write(dir.join("style.css"), "b {}").unwrap();
+        app.assets.sweep(Duration::ZERO);
+        let restyled = page(None).await;
+        assert_ne!(restyled.body, first.body);
+        assert_eq!(page(None).await.body, restyled.body);
+        assert_eq!(hits(), 2);
+        std::fs::write(dir.join("_style/default.html"), format!("New {layout}")).unwrap();
+        app.templates.sweep(Duration::ZERO);
+        assert!(page(None).await.body.starts_with("New Blog|"));
+        assert_eq!(hits(), 2);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn response_cap() {
+        use std::sync::atomic::Ordering;
+
+        let dir = Utf8PathBuf::from_path_buf(
+            std::env::temp_dir().join(format!("flaty-response-cap-{}", std::process::id())),
+        )
+        .unwrap();
+        std::fs::create_dir_all(dir.join("_style")).unwrap();
+        std::fs::write(dir.join("_style/default.html"), "{{request.query.n}}").unwrap();
+        std::fs::write(dir.join("page.md"), "").unwrap();
+        let mut app = App::new(dir.clone());
+        app.responses.cap = 8;
+        let app = Arc::new(app);
+        let hits = || app.responses.hits.load(Ordering::Relaxed);
+        let page = |n: usize| {
+            let app = app.clone();
+            async move {
+                let query = format!("n={n}");
+                match web(app, get_query("/", Some(&query))).await {
+                    Ok(MyResponse::Page(page)) => page.body,
+                    _ => panic!("expected a page"),
+                }
+            }
+        };
+
+        // One past the cap evicts the least recently used, and an eighth of
+        // the cap more.
+        for n in 0..8 {
+            assert_eq!(page(n).await, n.to_string());
+        }
+        assert_eq!(app.responses.map.len(), 8);
+        assert_eq!(page(8).await, "8");
+        assert_eq!(app.responses.map.len(), 7);
+        page(8).await;
+        page(2).await;
+        assert_eq!(hits(), 2);
+        page(0).await;
+        page(1).await;
+        assert_eq!(hits(), 2);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn template_diagnostics() {
+        let dir = Utf8PathBuf::from_path_buf(
+            std::env::temp_dir().join(format!("flaty-diagnostics-{}", std::process::id())),
+        )
+        .unwrap();
+        std::fs::create_dir_all(dir.join("_style/snippets")).unwrap();
+        let files = [
+            ("_config.toml", "[templates]\nstrict = true\n"),
+            (
+                "_style/default.html",
+                "{{#if preview_url}}Draft{{/if}}\n<h1>{{titel}}</h1>{{{contents}}}",
+            ),
+            ("_style/snippets/note.html", "<p>{{text}}</p>"),
+            ("page.md", "---\ntitle = \"Home\"\n---\nHello.\n"),
+            ("other/page.md", "Intro.\n\n:::note\ntxt = \"typo\"\n:::\n"),
+        ];
+        for (name, contents) in files {
+            std::fs::create_dir_all(dir.join(name).parent().unwrap()).unwrap();
+            std::fs::write(dir.join(name), contents).unwrap();
+        }
+        let app = Arc::new(App::new(dir.clone()));
+        let diagnostic = |result: MyResult| match result {
+            Err(MyError::Template(diagnostic)) => diagnostic,
+            _ => panic!("expected a template error"),
+        };
+
+        // Strict mode: a missing field is an error, located in its layout.
+        let layout = diagnostic(web(app.clone(), get("/")).await);
+        assert_eq!(layout.template, "_style/default.html");
+        assert_eq!((layout.line, layout.column), (Some(2), Some(5)));
+        assert!(layout.message.contains("titel"), "{}", layout.message);
+        assert_eq!(layout.page.as_deref(), Some("`page.md`"));
+        assert_eq!(
+            layout.excerpt[1],
+            (2, "<h1>{{titel}}</h1>{{{contents}}}".into())
+        );
+        assert_eq!(
+            layout.to_string(),
+            format!(
+                "template `_style/default.html` line 2, column 5: {} (rendering `page.md`)",
+                layout.message
+            )
+        );
+        let overlay = error_overlay(&layout);
+        assert_eq!(overlay.status, 500);
+        assert!(overlay
+            .body
+            .contains("<mark>   2 | &lt;h1&gt;{{titel}}&lt;/h1&gt;{{{contents}}}</mark>"));
+
+        // ... or in its snippet, with the line using it. Outside development,
+        // a broken snippet makes an invalid page.
+        assert!(matches!(
+            web(app.clone(), get("/other/")).await,
+            Err(MyError::InvalidPage)
+        ));
+        let dev = Arc::new(App::new(dir.clone()).with_development(true));
+        let snippet = diagnostic(web(dev, get("/other/")).await);
+        assert_eq!(snippet.template, "_style/snippets/note.html");
+        assert_eq!(snippet.page.as_deref(), Some("`other/page.md` line 3"));
+
+        // Without strict mode, missing fields are empty.
+        std::fs::write(dir.join("_config.toml"), "").unwrap();
+        let app = Arc::new(App::new(dir.clone()));
+        match web(app.clone(), get("/")).await {
+            Ok(MyResponse::Page(page)) => assert_eq!(page.body, "\n<h1></h1><p>Hello.</p>\n"),
+            _ => panic!("expected a page"),
+        }
+
+        // A template that does not compile.
+        std::fs::write(dir.join("_style/default.html"), "\n{{#if title}}").unwrap();
+        app.templates.sweep(Duration::ZERO);
+        let syntax = diagnostic(web(app.clone(), get("/")).await);
+        assert_eq!(syntax.template, "_style/default.html");
+        assert!(
+            syntax.message.contains("default.html"),
+            "{}",
+            syntax.message
+        );
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn request_variables() {
+        use std::sync::atomic::Ordering;
+
+        let dir = Utf8PathBuf::from_path_buf(
+            std::env::temp_dir().join(format!("flaty-request-{}", std::process::id())),
+        )
+        .unwrap();
+        std::fs::create_dir_all(dir.join("_style/partials")).unwrap();
+        let files = [
+            (
+                "_config.toml",
+                "[users]\nann = \"pw\"\nbob = \"pw\"\n[groups]\nstaff = [\"ann\"]\n",
+            ),
+            ("_style/default.html", "{{> nav}}|{{title}}"),
+            (
+                "_style/partials/nav.html",
+                "{{#if (eq request.path \"/\")}}home{{/if}}",
+            ),
+            ("page.md", "---\ntitle = \"Home\"\n---\n"),
+        ];
+        for (name, contents) in files {
+            std::fs::write(dir.join(name), contents).unwrap();
+        }
+        let app = Arc::new(App::new(dir.clone()));
+        let hits = || app.responses.hits.load(Ordering::Relaxed);
+        // "ann:pw" and "bob:pw".
+        let page = |authorization, query, accept_language| {
+            let app = app.clone();
+            async move {
+                let request = MyRequest::GET {
+                    path: "/",
+                    query,
+                    authorization,
+                    peer: None,
+                    forwarded_for: None,
+                    host: Some("example.com"),
+                    accept_language,
+                };
+                match web(app, request).await {
+                    Ok(MyResponse::Page(page)) => page.body,
+                    _ => panic!("expected a page"),
+                }
+            }
+        };
+        let ann = Some("Basic YW5uOnB3");
+        let bob = Some("Basic Ym9iOnB3");
+        let rust = Some("tag=rust");
+
+        // Only the path is read, so users share one response.
+        assert_eq!(page(ann, rust, None).await, "home|Home");
+        assert_eq!(page(bob, rust, None).await, "home|Home");
+        assert_eq!(hits(), 1);
+
+        std::fs::write(
+            dir.join("_style/default.html"),
+            "{{> nav}}|{{#if request.user}}Hi {{request.user}} ({{join request.groups \",\"}}){{/if}}\
+             |{{request.query.tag}}|{{request.host}}",
+        )
+        .unwrap();
+        app.templates.sweep(Duration::ZERO);
+        assert_eq!(
+            page(ann, rust, None).await,
+            "home|Hi ann (staff)|rust|example.com"
+        );
+        assert_eq!(
+            page(bob, rust, None).await,
+            "home|Hi bob ()|rust|example.com"
+        );
+        assert_eq!(page(None, rust, None).await, "home||rust|example.com");
+        assert_eq!(
+            page(ann, rust, None).await,
+            "home|Hi ann (staff)|rust|example.com"
+        );
+        assert_eq!(hits(), 2);
+        // Parameters the layout does not name do not vary its response.
+        assert_eq!(
+            page(None, Some("tag=rust&x=1"), None).await,
+            "home||rust|example.com"
+        );
+        assert_eq!(hits(), 3);
+        assert_eq!(
+            page(None, Some("tag=web"), None).await,
+            "home||web|example.com"
+        );
+        assert_eq!(hits(), 3);
+
+        // The whole query and the languages, which clients vary at will, are
+        // rendered per request.
+        let cached = app.responses.map.len();
+        std::fs::write(
+            dir.join("_style/default.html"),
+            "{{request.languages.[0]}}|{{#each request.query}}{{@key}}{{/each}}",
+        )
+        .unwrap();
+        app.templates.sweep(Duration::ZERO);
+        assert_eq!(page(None, rust, Some("fr;q=0.5, de")).await, "de|tag");
+        assert_eq!(page(None, rust, Some("fr;q=0.5, de")).await, "de|tag");
+        assert_eq!(hits(), 3);
+        assert_eq!(app.responses.map.len(), cached);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
 }